@@ -0,0 +1,149 @@
+//! Steps a battle of 10,000 single-soldier dots, on flat ground and in the
+//! mountains, and checks it keeps up with the 60 Hz battle tick. Run with
+//! `cargo bench -p dot_wars_battle`.
+
+use dot_wars_battle::*;
+use dot_wars_core::*;
+use dot_wars_world::TerrainType;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SOLDIERS_PER_SIDE: usize = 5_000;
+const COLUMNS: usize = 125;
+const SPACING: f32 = 12.0;
+const WARMUP_TICKS: u32 = 60;
+const MEASURED_TICKS: u32 = 600;
+
+fn soldier(unit_type: UnitType, faction: FactionId, formation: Formation) -> Unit {
+    Unit {
+        id: UnitId::default(),
+        unit_type,
+        count: 1,
+        max_count: 1,
+        morale: 100.0,
+        experience: 0,
+        formation,
+        faction,
+    }
+}
+
+/// Two blocks of soldiers facing each other across a 2000x1400 field,
+/// archers behind the infantry and cavalry on the wings. Without a
+/// `terrain` the ground is flat.
+fn large_battle(terrain: Option<TerrainType>) -> BattleSimulation {
+    let attacker = FactionId::default();
+    let defender = FactionId::default();
+    let (width, height) = (2000.0, 1400.0);
+    let heightmap = match terrain {
+        Some(terrain) => Heightmap::generate(&terrain, width, height, &[], &mut BattleRng::new(7)),
+        None => Heightmap::default(),
+    };
+    let battlefield = Battlefield {
+        width,
+        height,
+        terrain_effects: Vec::new(),
+        walls: Vec::new(),
+        heightmap,
+    };
+    let mut simulation = BattleSimulation::new(Battle::new("benchmark".into(), attacker, defender, battlefield), 42);
+    let registry = UnitRegistry::built_in();
+
+    for (side, faction, front, step) in [
+        (BattleSide::Attacker, attacker, 450.0, -SPACING),
+        (BattleSide::Defender, defender, 950.0, SPACING),
+    ] {
+        for i in 0..SOLDIERS_PER_SIDE {
+            let (column, row) = (i % COLUMNS, i / COLUMNS);
+            let (unit_type, formation) = match (column, row) {
+                (c, _) if !(10..COLUMNS - 10).contains(&c) => (UnitType::Cavalry, Formation::Wedge),
+                (_, r) if r >= 30 => (UnitType::Archers, Formation::Skirmish),
+                _ => (UnitType::Infantry, Formation::Line),
+            };
+            let position = Position::new(250.0 + column as f32 * SPACING, front + row as f32 * step);
+            let soldier = soldier(unit_type, faction, formation);
+            simulation.add_unit(BattleUnit::from_registry(&registry, soldier, side, position).unwrap());
+        }
+    }
+
+    simulation.battle.phase = BattlePhase::Combat;
+    simulation
+}
+
+fn report(name: &str, total: Duration, iterations: u32) {
+    let per_iteration = total / iterations;
+    println!("{name:<40} {per_iteration:>12.2?} per iteration");
+}
+
+fn bench_queries(simulation: &mut BattleSimulation) {
+    const QUERIES: u32 = 10_000;
+
+    let start = Instant::now();
+    for _ in 0..100 {
+        simulation.rebuild_spatial_index();
+    }
+    report("spatial index rebuild (10k)", start.elapsed(), 100);
+
+    let centre = Position::new(1000.0, 700.0);
+    let start = Instant::now();
+    for i in 0..QUERIES {
+        let position = Position::new(250.0 + (i % 1500) as f32, 100.0 + (i % 1200) as f32);
+        black_box(simulation.units_within(BattleSide::Defender, &position, 100.0));
+    }
+    report("radius query (100)", start.elapsed(), QUERIES);
+
+    let start = Instant::now();
+    for i in 0..QUERIES {
+        let index = i as usize % simulation.units.len();
+        let unit = &simulation.units[index];
+        black_box(simulation.nearest_unit(unit.side.opponent(), &unit.position, |u| u.can_fight()));
+    }
+    report("nearest enemy query", start.elapsed(), QUERIES);
+
+    let start = Instant::now();
+    for i in 0..QUERIES {
+        black_box(simulation.units_in_cone(BattleSide::Attacker, &centre, i as f32 * 0.01, 0.5, 200.0));
+    }
+    report("cone query (200, 60 degrees)", start.elapsed(), QUERIES);
+}
+
+/// Times `MEASURED_TICKS` steps of the battle and returns how many ticks a
+/// second it managed.
+fn bench_steps(name: &str, mut simulation: BattleSimulation) -> f64 {
+    for _ in 0..WARMUP_TICKS {
+        simulation.step(BATTLE_TICK);
+    }
+
+    let mut slowest = Duration::ZERO;
+    let start = Instant::now();
+    for _ in 0..MEASURED_TICKS {
+        let tick_start = Instant::now();
+        simulation.step(BATTLE_TICK);
+        slowest = slowest.max(tick_start.elapsed());
+    }
+    let elapsed = start.elapsed();
+    black_box(&simulation);
+
+    let ticks_per_second = MEASURED_TICKS as f64 / elapsed.as_secs_f64();
+    report(name, elapsed, MEASURED_TICKS);
+    println!("slowest tick {slowest:.2?}, {ticks_per_second:.0} ticks/s (target {BATTLE_TICK_RATE})");
+    println!(
+        "{} soldiers left, {} projectiles in flight",
+        simulation.units.iter().filter(|u| u.is_alive()).count(),
+        simulation.projectiles.len()
+    );
+    ticks_per_second
+}
+
+fn main() {
+    let mut simulation = large_battle(None);
+    println!("{} soldiers", simulation.units.len());
+
+    bench_queries(&mut simulation);
+
+    let flat = bench_steps("battle step (10k soldiers)", simulation);
+    let mountains = bench_steps("battle step (10k soldiers, mountains)", large_battle(Some(TerrainType::Mountains)));
+    if flat.min(mountains) < BATTLE_TICK_RATE {
+        println!("too slow: the simulation can't keep up with the battle tick");
+        std::process::exit(1);
+    }
+}
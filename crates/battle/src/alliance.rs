@@ -0,0 +1,339 @@
+use crate::*;
+use std::fmt;
+
+// ============================================================================
+// ALLIED CONTINGENTS
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AllianceError {
+    AlreadyFighting(FactionId, BattleSide),
+    NotInBattle(FactionId),
+}
+
+impl fmt::Display for AllianceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllianceError::AlreadyFighting(faction, side) => {
+                write!(f, "faction {:?} already fights on the {:?} side", faction.0, side)
+            }
+            AllianceError::NotInBattle(faction) => write!(f, "faction {:?} is not part of this battle", faction.0),
+        }
+    }
+}
+
+impl std::error::Error for AllianceError {}
+
+/// How one faction's contingent fared in a battle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FactionResult {
+    pub faction: FactionId,
+    pub side: BattleSide,
+    pub men: u32, // Brought to the battle
+    pub casualties: u32,
+}
+
+impl Battle {
+    /// The faction leading `side`. It takes the captured generals, keeps
+    /// what is left of the loot once it has been shared out, and its
+    /// deployment and readiness stand for the whole side.
+    pub fn leader(&self, side: BattleSide) -> FactionId {
+        match side {
+            BattleSide::Attacker => self.attacker,
+            BattleSide::Defender => self.defender,
+        }
+    }
+
+    pub fn allies(&self, side: BattleSide) -> &[FactionId] {
+        match side {
+            BattleSide::Attacker => &self.attacker_allies,
+            BattleSide::Defender => &self.defender_allies,
+        }
+    }
+
+    /// Every faction fighting on `side`, the leader first.
+    pub fn factions(&self, side: BattleSide) -> Vec<FactionId> {
+        std::iter::once(self.leader(side)).chain(self.allies(side).iter().copied()).collect()
+    }
+
+    pub fn side_of(&self, faction: FactionId) -> Option<BattleSide> {
+        [BattleSide::Attacker, BattleSide::Defender]
+            .into_iter()
+            .find(|&side| self.leader(side) == faction || self.allies(side).contains(&faction))
+    }
+
+    /// Brings `faction` into the battle as an ally of the side's leader.
+    pub fn join(&mut self, side: BattleSide, faction: FactionId) -> Result<(), AllianceError> {
+        if let Some(current) = self.side_of(faction) {
+            return Err(AllianceError::AlreadyFighting(faction, current));
+        }
+
+        match side {
+            BattleSide::Attacker => self.attacker_allies.push(faction),
+            BattleSide::Defender => self.defender_allies.push(faction),
+        }
+        Ok(())
+    }
+}
+
+impl BattleOutcome {
+    pub fn faction(&self, faction: FactionId) -> Option<&FactionResult> {
+        self.factions.iter().find(|f| f.faction == faction)
+    }
+
+    /// Victory is shared: every faction on the winning side won.
+    pub fn victors(&self) -> impl Iterator<Item = FactionId> + '_ {
+        self.factions
+            .iter()
+            .filter(|f| Some(f.side) == self.winner)
+            .map(|f| f.faction)
+    }
+
+    /// Shares `loot` out between the victors by how many men each brought
+    /// to the battle. Whatever doesn't divide evenly goes to the first of
+    /// them, which is the side's leader when it fought. Nobody gets
+    /// anything from a battle without a winner.
+    pub fn split_loot(&self, loot: &Resources) -> Vec<(FactionId, Resources)> {
+        let victors: Vec<&FactionResult> = self.factions.iter().filter(|f| Some(f.side) == self.winner).collect();
+        let total_men: u32 = victors.iter().map(|f| f.men).sum();
+        if victors.is_empty() {
+            return Vec::new();
+        }
+
+        let share = |amount: i32, men: u32| {
+            if total_men == 0 {
+                0
+            } else {
+                (amount as i64 * men as i64 / total_men as i64) as i32
+            }
+        };
+        let mut shares: Vec<(FactionId, Resources)> = victors
+            .iter()
+            .map(|f| {
+                let resources = Resources {
+                    gold: share(loot.gold, f.men),
+                    food: share(loot.food, f.men),
+                    materials: share(loot.materials, f.men),
+                    manpower: share(loot.manpower, f.men),
+                };
+                (f.faction, resources)
+            })
+            .collect();
+
+        let left_over = |field: fn(&Resources) -> i32| field(loot) - shares.iter().map(|(_, r)| field(r)).sum::<i32>();
+        let (gold, food) = (left_over(|r| r.gold), left_over(|r| r.food));
+        let (materials, manpower) = (left_over(|r| r.materials), left_over(|r| r.manpower));
+        shares[0].1.add(&Resources {
+            gold,
+            food,
+            materials,
+            manpower,
+        });
+        shares
+    }
+}
+
+/// Adds up men and casualties per faction from `(side, unit, men at the
+/// start)` entries. Factions are listed attackers first, each side in the
+/// order its factions first turn up.
+pub(crate) fn tally_factions<'a>(entries: impl Iterator<Item = (BattleSide, &'a Unit, u32)>) -> Vec<FactionResult> {
+    let mut results: Vec<FactionResult> = Vec::new();
+    for (side, unit, men) in entries {
+        let index = match results.iter().position(|r| r.faction == unit.faction && r.side == side) {
+            Some(index) => index,
+            None => {
+                results.push(FactionResult {
+                    faction: unit.faction,
+                    side,
+                    men: 0,
+                    casualties: 0,
+                });
+                results.len() - 1
+            }
+        };
+        results[index].men += men;
+        results[index].casualties += men.saturating_sub(unit.count);
+    }
+
+    results.sort_by_key(|r| r.side == BattleSide::Defender);
+    results
+}
+
+impl BattleSimulation {
+    /// Men and casualties of every faction on the field. The side leaders
+    /// come first even if their units were added after their allies'.
+    pub fn faction_results(&self) -> Vec<FactionResult> {
+        let mut results = tally_factions(self.units.iter().map(|u| (u.side, &u.unit, u.initial_count)));
+        results.sort_by_key(|r| (r.side == BattleSide::Defender, r.faction != self.battle.leader(r.side)));
+        results
+    }
+
+    /// Hands one faction's contingent over to the AI while the rest of its
+    /// side stays under the player's control. The contingent follows the
+    /// same plan as the side as a whole would.
+    pub fn enable_contingent_ai(&mut self, faction: FactionId) -> Result<(), AllianceError> {
+        let side = self.battle.side_of(faction).ok_or(AllianceError::NotInBattle(faction))?;
+        if !self.tactical_ai.iter().any(|ai| ai.side == side && ai.faction == Some(faction)) {
+            self.tactical_ai.push(TacticalAI {
+                side,
+                tactic: GroupTactic::Advance,
+                faction: Some(faction),
+            });
+        }
+        Ok(())
+    }
+
+    /// True when the AI gives this unit its orders, so the player can't.
+    pub fn is_ai_controlled(&self, id: UnitId) -> bool {
+        let Some(unit) = self.unit(id) else {
+            return false;
+        };
+
+        self.tactical_ai
+            .iter()
+            .any(|ai| ai.side == unit.side && ai.faction.is_none_or(|f| f == unit.unit.faction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn result(faction: FactionId, side: BattleSide, men: u32) -> FactionResult {
+        FactionResult {
+            faction,
+            side,
+            men,
+            casualties: 0,
+        }
+    }
+
+    fn won_by(winner: Option<BattleSide>, factions: Vec<FactionResult>) -> BattleOutcome {
+        BattleOutcome {
+            winner,
+            attacker_casualties: 0,
+            defender_casualties: 0,
+            duration: 0.0,
+            factions,
+            decided_by: None,
+            prisoners: Vec::new(),
+            captured_generals: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn a_faction_fights_on_one_side_only() {
+        let mut simulation = open_field(1);
+        let battle = &mut simulation.battle;
+        let (attacker, defender) = (battle.attacker, battle.defender);
+        let ally = FactionId::default();
+
+        battle.join(BattleSide::Attacker, ally).unwrap();
+        assert_eq!(battle.side_of(ally), Some(BattleSide::Attacker));
+        assert_eq!(battle.factions(BattleSide::Attacker), vec![attacker, ally]);
+
+        assert_eq!(
+            battle.join(BattleSide::Defender, ally),
+            Err(AllianceError::AlreadyFighting(ally, BattleSide::Attacker))
+        );
+        assert_eq!(
+            battle.join(BattleSide::Attacker, defender),
+            Err(AllianceError::AlreadyFighting(defender, BattleSide::Defender))
+        );
+        assert_eq!(battle.side_of(FactionId::default()), None);
+        assert_eq!(battle.factions(BattleSide::Defender), vec![defender]);
+    }
+
+    #[test]
+    fn casualties_are_counted_per_faction_with_the_leaders_first() {
+        let mut simulation = open_field(2);
+        let ally = FactionId::default();
+        simulation.battle.join(BattleSide::Attacker, ally).unwrap();
+
+        let allied = BattleUnit::new(unit(UnitType::Cavalry, ally, 30), BattleSide::Attacker, Position::new(600.0, 100.0));
+        let allied_id = allied.id();
+        simulation.add_unit(allied);
+        let led = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 100, 500.0, 100.0);
+        place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 80, 500.0, 700.0);
+
+        simulation.unit_mut(allied_id).unwrap().unit.count = 10;
+        simulation.unit_mut(led).unwrap().unit.count = 95;
+
+        let results = simulation.faction_results();
+        let summary: Vec<(FactionId, BattleSide, u32, u32)> =
+            results.iter().map(|r| (r.faction, r.side, r.men, r.casualties)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (simulation.battle.attacker, BattleSide::Attacker, 100, 5),
+                (ally, BattleSide::Attacker, 30, 20),
+                (simulation.battle.defender, BattleSide::Defender, 80, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn loot_is_shared_by_the_men_each_victor_brought() {
+        let (leader, ally, enemy) = (FactionId::default(), FactionId::default(), FactionId::default());
+        let outcome = won_by(
+            Some(BattleSide::Attacker),
+            vec![
+                result(leader, BattleSide::Attacker, 300),
+                result(ally, BattleSide::Attacker, 100),
+                result(enemy, BattleSide::Defender, 500),
+            ],
+        );
+        let loot = Resources {
+            gold: 101,
+            food: 40,
+            materials: 3,
+            manpower: 0,
+        };
+
+        assert_eq!(outcome.victors().collect::<Vec<_>>(), vec![leader, ally]);
+        let shares = outcome.split_loot(&loot);
+        assert_eq!(shares.len(), 2);
+        assert_eq!(shares[0].0, leader);
+        assert_eq!((shares[0].1.gold, shares[0].1.food, shares[0].1.materials), (76, 30, 3));
+        assert_eq!(shares[1].0, ally);
+        assert_eq!((shares[1].1.gold, shares[1].1.food, shares[1].1.materials), (25, 10, 0));
+        assert_eq!(shares.iter().map(|(_, r)| r.gold).sum::<i32>(), loot.gold);
+    }
+
+    #[test]
+    fn nobody_is_paid_for_a_battle_without_a_winner() {
+        let outcome = won_by(None, vec![result(FactionId::default(), BattleSide::Attacker, 100)]);
+        let loot = Resources {
+            gold: 50,
+            food: 0,
+            materials: 0,
+            manpower: 0,
+        };
+        assert!(outcome.split_loot(&loot).is_empty());
+    }
+
+    #[test]
+    fn contingent_ai_commands_only_its_own_faction() {
+        let mut simulation = open_field(3);
+        let ally = FactionId::default();
+        simulation.battle.join(BattleSide::Attacker, ally).unwrap();
+
+        let allied = BattleUnit::new(unit(UnitType::Infantry, ally, 60), BattleSide::Attacker, Position::new(600.0, 100.0));
+        let allied_id = allied.id();
+        simulation.add_unit(allied);
+        let led = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 60, 500.0, 100.0);
+        let enemy = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 60, 500.0, 700.0);
+
+        let stranger = FactionId::default();
+        assert_eq!(simulation.enable_contingent_ai(stranger), Err(AllianceError::NotInBattle(stranger)));
+        assert!(!simulation.is_ai_controlled(allied_id));
+
+        simulation.enable_contingent_ai(ally).unwrap();
+        simulation.enable_contingent_ai(ally).unwrap();
+        assert_eq!(simulation.tactical_ai.len(), 1);
+        assert!(simulation.is_ai_controlled(allied_id));
+        assert!(!simulation.is_ai_controlled(led));
+        assert!(!simulation.is_ai_controlled(enemy));
+    }
+}
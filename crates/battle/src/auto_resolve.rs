@@ -0,0 +1,372 @@
+use crate::*;
+
+// ============================================================================
+// AUTO-RESOLVE
+// ============================================================================
+
+/// An auto-resolved battle is fought in rounds of this many seconds.
+const ROUND_DURATION: f32 = 30.0;
+const MAX_ROUNDS: u32 = 20;
+
+/// Share of a side's men that fall in a round against an equal enemy.
+const BASE_CASUALTY_RATE: f32 = 0.08;
+
+/// The side that lost the larger share of its men in a round loses this
+/// much morale on top of what the casualties cost.
+const LOSING_ROUND_MORALE_LOSS: f32 = 5.0;
+
+/// While the walls stand, attackers only land this share of their blows.
+const WALL_PROTECTION: f32 = 0.6;
+
+/// Each siege tower takes this share off the protection walls give.
+const SIEGE_TOWER_PROTECTION_LOSS: f32 = 0.15;
+
+/// Everything about a battle besides the armies themselves that decides an
+/// auto-resolved fight.
+#[derive(Debug, Clone, Default)]
+pub struct AutoResolveContext {
+    pub defender_bonus: f32, // e.g. `Province::defense_bonus`
+    pub wall_strength: f32,  // 0.0 for a battle in the open field
+    pub siege_equipment: SiegeEquipment,
+    pub conditions: BattleConditions,
+}
+
+/// One side of an auto-resolved battle. Allied contingents fight in the
+/// same unit list, the faction of the first unit leads the side.
+pub struct AutoResolveSide<'a> {
+    pub units: &'a mut [Unit],
+    pub general: Option<&'a mut General>,
+    pub arrivals: Vec<f32>, // Seconds until each unit joins in, missing ones are there from the start
+}
+
+impl<'a> AutoResolveSide<'a> {
+    pub fn new(units: &'a mut [Unit], general: Option<&'a mut General>) -> Self {
+        Self {
+            units,
+            general,
+            arrivals: Vec::new(),
+        }
+    }
+
+    /// Has units join the fight late, like reinforcements in a tactical
+    /// battle. `arrivals` holds the arrival time of each unit, in order.
+    pub fn with_arrivals(mut self, arrivals: Vec<f32>) -> Self {
+        self.arrivals = arrivals;
+        self
+    }
+
+    fn arrival(&self, index: usize) -> f32 {
+        self.arrivals.get(index).copied().unwrap_or(0.0)
+    }
+
+    /// How much of round `round` (counted from 1) each unit is there for,
+    /// from 0.0 for troops still on the road to 1.0 for the whole round.
+    fn presence(&self, round: u32) -> Vec<f32> {
+        let round_end = round as f32 * ROUND_DURATION;
+        (0..self.units.len())
+            .map(|i| ((round_end - self.arrival(i)) / ROUND_DURATION).clamp(0.0, 1.0))
+            .collect()
+    }
+
+    /// A side is beaten once every unit in it would rout. Troops still on
+    /// the road keep it going, unless they can't make it before the battle
+    /// is over anyway.
+    fn is_broken(&self) -> bool {
+        let battle_end = MAX_ROUNDS as f32 * ROUND_DURATION;
+        self.units
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| self.arrival(i) < battle_end)
+            .all(|(_, u)| u.count == 0 || CombatSystem::check_rout(u))
+    }
+
+    fn command(&self) -> Option<GeneralBonuses> {
+        self.general.as_ref().filter(|g| g.is_active()).map(|g| g.bonuses())
+    }
+
+    fn faction(&self) -> Option<FactionId> {
+        self.units.first().map(|u| u.faction)
+    }
+}
+
+pub struct AutoResolve;
+
+impl AutoResolve {
+    /// Fights a battle out without the tactical simulation. Casualties,
+    /// morale, experience and what happened to the generals are applied in
+    /// place. The attacker has to break the defender to win, otherwise the
+    /// defender holds. Units arriving late only fight for the part of each
+    /// round they are there for, and take losses in proportion. A broken
+    /// army leaves some of its men behind as prisoners. Errors, changing
+    /// nothing, if a unit of either side isn't in the registry.
+    pub fn resolve(
+        attacker: AutoResolveSide,
+        defender: AutoResolveSide,
+        context: &AutoResolveContext,
+        registry: &UnitRegistry,
+        seed: u64,
+    ) -> Result<BattleOutcome, UnitRegistryError> {
+        for unit in attacker.units.iter().chain(defender.units.iter()) {
+            Self::stats(unit, registry)?;
+        }
+
+        let mut rng = BattleRng::new(seed);
+        let (mut attacker, mut defender) = (attacker, defender);
+        let attacker_start = Self::men(attacker.units);
+        let defender_start = Self::men(defender.units);
+        let attacker_men: Vec<u32> = attacker.units.iter().map(|u| u.count).collect();
+        let defender_men: Vec<u32> = defender.units.iter().map(|u| u.count).collect();
+        let mut wall_strength = context.wall_strength;
+        let conditions = context.conditions.modifiers();
+        let mut rounds = 0;
+
+        while rounds < MAX_ROUNDS && !attacker.is_broken() && !defender.is_broken() {
+            rounds += 1;
+            let attacker_presence = attacker.presence(rounds);
+            let defender_presence = defender.presence(rounds);
+
+            // Artillery and rams work on the walls while everyone else fights
+            if wall_strength > 0.0 {
+                let rams = context.siege_equipment.battering_rams;
+                let arrived: Vec<Unit> = Self::arrived(attacker.units, &attacker_presence).cloned().collect();
+                let damage = bombardment_damage(&arrived, registry, ROUND_DURATION)? * conditions.artillery
+                    + gate_damage(&arrived, registry, rams, ROUND_DURATION)?;
+                wall_strength = (wall_strength - damage).max(0.0);
+            }
+            let wall_share = if context.wall_strength > 0.0 { wall_strength / context.wall_strength } else { 0.0 };
+            let tower_loss = SIEGE_TOWER_PROTECTION_LOSS * context.siege_equipment.siege_towers as f32;
+            let protection = (WALL_PROTECTION * wall_share * (1.0 - tower_loss)).max(0.0);
+
+            let attacker_command = attacker.command();
+            let defender_command = defender.command();
+            // The attacker has to come on through whatever mud or snow there is
+            let attack_power = Self::attack_power(
+                attacker.units,
+                &attacker_presence,
+                registry,
+                attacker_command.as_ref(),
+                &conditions,
+            )? * (1.0 - protection)
+                * conditions.movement;
+            let defense_power = Self::attack_power(
+                defender.units,
+                &defender_presence,
+                registry,
+                defender_command.as_ref(),
+                &conditions,
+            )?;
+            let attacker_toughness =
+                Self::toughness(attacker.units, &attacker_presence, registry, attacker_command.as_ref())?;
+            let defender_toughness = Self::toughness(defender.units, &defender_presence, registry, defender_command.as_ref())?
+                * (1.0 + context.defender_bonus);
+
+            let defender_men = Self::men_present(defender.units, &defender_presence);
+            let attacker_men = Self::men_present(attacker.units, &attacker_presence);
+            let defender_losses = Self::losses(attack_power, defender_toughness, defender_men, &mut rng);
+            let attacker_losses = Self::losses(defense_power, attacker_toughness, attacker_men, &mut rng);
+            let defender_share = defender_losses as f32 / defender_men.max(1.0);
+            let attacker_share = attacker_losses as f32 / attacker_men.max(1.0);
+            Self::distribute_casualties(defender.units, &defender_presence, defender_losses);
+            Self::distribute_casualties(attacker.units, &attacker_presence, attacker_losses);
+
+            if defender_share > attacker_share {
+                Self::lose_morale(defender.units, &defender_presence, LOSING_ROUND_MORALE_LOSS, defender_command.as_ref());
+            } else if attacker_share > defender_share {
+                Self::lose_morale(attacker.units, &attacker_presence, LOSING_ROUND_MORALE_LOSS, attacker_command.as_ref());
+            }
+
+            if let Some(general) = attacker.general.as_deref_mut() {
+                general.roll_casualty(attacker_share, &mut rng);
+            }
+            if let Some(general) = defender.general.as_deref_mut() {
+                general.roll_casualty(defender_share, &mut rng);
+            }
+        }
+
+        let attacker_broken = attacker.is_broken();
+        let defender_broken = defender.is_broken();
+        let winner = match (attacker_broken, defender_broken) {
+            (true, true) => None,
+            (false, true) => Some(BattleSide::Attacker),
+            _ => Some(BattleSide::Defender),
+        };
+
+        // A general whose army broke may be caught as it runs
+        let (attacker_faction, defender_faction) = (attacker.faction(), defender.faction());
+        if let (Some(general), Some(captor), true) = (attacker.general.as_deref_mut(), defender_faction, attacker_broken) {
+            general.roll_capture(captor, &mut rng);
+        }
+        if let (Some(general), Some(captor), true) = (defender.general.as_deref_mut(), attacker_faction, defender_broken) {
+            general.roll_capture(captor, &mut rng);
+        }
+        let prisoners = match (winner, attacker_faction, defender_faction) {
+            (Some(BattleSide::Attacker), Some(captor), _) if defender_broken => Self::pursue(defender.units, captor),
+            (Some(BattleSide::Defender), _, Some(captor)) if attacker_broken => Self::pursue(attacker.units, captor),
+            _ => Vec::new(),
+        };
+        let captured_generals = [attacker.general.as_deref(), defender.general.as_deref()]
+            .into_iter()
+            .flatten()
+            .filter(|g| matches!(g.status, GeneralStatus::Captured(_)))
+            .cloned()
+            .collect();
+
+        let attacker_casualties = attacker_start - Self::men(attacker.units);
+        let defender_casualties = defender_start - Self::men(defender.units);
+        for (side, battle_side) in [(&mut attacker, BattleSide::Attacker), (&mut defender, BattleSide::Defender)] {
+            // Troops that never made it learned nothing
+            let presence = side.presence(rounds.max(1));
+            for (unit, _) in side.units.iter_mut().zip(presence).filter(|(u, p)| u.count > 0 && *p > 0.0) {
+                let mut experience = EXPERIENCE_PER_BATTLE;
+                if winner == Some(battle_side) {
+                    experience += EXPERIENCE_VICTORY_BONUS;
+                }
+                unit.gain_experience(experience);
+            }
+        }
+
+        let winning_general = match winner {
+            Some(BattleSide::Attacker) => attacker.general.as_deref_mut().map(|g| (g, BattleSide::Attacker)),
+            Some(BattleSide::Defender) => defender.general.as_deref_mut().map(|g| (g, BattleSide::Defender)),
+            None => None,
+        };
+        if let Some((general, side)) = winning_general.filter(|(g, _)| g.is_active()) {
+            let (own, enemy) = match side {
+                BattleSide::Attacker => ((attacker_start, attacker_casualties), (defender_start, defender_casualties)),
+                BattleSide::Defender => ((defender_start, defender_casualties), (attacker_start, attacker_casualties)),
+            };
+            general.record_victory(&VictoryRecord {
+                side,
+                outnumbered: own.0 < enemy.0,
+                casualty_ratio: own.1 as f32 / enemy.1.max(1) as f32,
+            });
+        }
+
+        let attacker_entries = attacker.units.iter().zip(attacker_men).map(|(u, men)| (BattleSide::Attacker, u, men));
+        let defender_entries = defender.units.iter().zip(defender_men).map(|(u, men)| (BattleSide::Defender, u, men));
+        Ok(BattleOutcome {
+            winner,
+            attacker_casualties,
+            defender_casualties,
+            duration: rounds as f32 * ROUND_DURATION,
+            factions: tally_factions(attacker_entries.chain(defender_entries)),
+            decided_by: Some(if attacker_broken || defender_broken {
+                VictoryKind::Rout
+            } else {
+                VictoryKind::TimeLimit
+            }),
+            prisoners,
+            captured_generals,
+        })
+    }
+
+    fn men(units: &[Unit]) -> u32 {
+        units.iter().map(|u| u.count).sum()
+    }
+
+    /// Men of the units present in a round, counting each for the part of
+    /// the round it is there for.
+    fn men_present(units: &[Unit], presence: &[f32]) -> f32 {
+        units.iter().zip(presence).map(|(u, p)| u.count as f32 * p).sum()
+    }
+
+    fn arrived<'u>(units: &'u [Unit], presence: &'u [f32]) -> impl Iterator<Item = &'u Unit> {
+        units.iter().zip(presence).filter(|(_, &p)| p > 0.0).map(|(u, _)| u)
+    }
+
+    fn lose_morale(units: &mut [Unit], presence: &[f32], amount: f32, command: Option<&GeneralBonuses>) {
+        let command = command.map_or(1.0, |b| b.morale_multiplier);
+        for (unit, _) in units.iter_mut().zip(presence).filter(|(_, &p)| p > 0.0) {
+            let loss = amount / (unit.rank().bonuses().morale_multiplier * command);
+            unit.morale = (unit.morale - loss).max(0.0);
+        }
+    }
+
+    fn stats(unit: &Unit, registry: &UnitRegistry) -> Result<UnitStats, UnitRegistryError> {
+        registry.stats(&unit.unit_type)
+    }
+
+    fn attack_power(
+        units: &[Unit],
+        presence: &[f32],
+        registry: &UnitRegistry,
+        command: Option<&GeneralBonuses>,
+        conditions: &ConditionModifiers,
+    ) -> Result<f32, UnitRegistryError> {
+        let command = command.map_or(1.0, |b| b.attack_multiplier);
+        let power = units
+            .iter()
+            .zip(presence)
+            .filter(|(u, _)| !CombatSystem::check_rout(u))
+            .map(|(u, p)| {
+                Ok(Self::stats(u, registry)?.attack as f32
+                    * u.count as f32
+                    * u.formation.get_modifiers().attack_modifier
+                    * u.rank().bonuses().attack_multiplier
+                    * (u.morale / 100.0)
+                    * Self::weather_factor(u, registry, conditions)?
+                    * p)
+            })
+            .sum::<Result<f32, UnitRegistryError>>()?;
+        Ok(power * command)
+    }
+
+    /// Missile troops shoot worse in rain, fog and the dark, and guns suffer
+    /// from wet powder on top of that.
+    fn weather_factor(
+        unit: &Unit,
+        registry: &UnitRegistry,
+        conditions: &ConditionModifiers,
+    ) -> Result<f32, UnitRegistryError> {
+        Ok(match unit.unit_type {
+            UnitType::Artillery => conditions.ranged_accuracy * conditions.artillery,
+            _ if Self::stats(unit, registry)?.range > MELEE_RANGE => conditions.ranged_accuracy,
+            _ => 1.0,
+        })
+    }
+
+    fn toughness(
+        units: &[Unit],
+        presence: &[f32],
+        registry: &UnitRegistry,
+        command: Option<&GeneralBonuses>,
+    ) -> Result<f32, UnitRegistryError> {
+        let command = command.map_or(1.0, |b| b.defense_multiplier);
+        let toughness = units
+            .iter()
+            .zip(presence)
+            .map(|(u, p)| {
+                Ok(Self::stats(u, registry)?.defense as f32
+                    * u.count as f32
+                    * u.formation.get_modifiers().defense_modifier
+                    * u.rank().bonuses().defense_multiplier
+                    * p)
+            })
+            .sum::<Result<f32, UnitRegistryError>>()?;
+        Ok(toughness * command)
+    }
+
+    fn losses(power: f32, toughness: f32, men: f32, rng: &mut BattleRng) -> u32 {
+        if power <= 0.0 || men <= 0.0 {
+            return 0;
+        }
+
+        let ratio = power / toughness.max(1.0);
+        let rate = (BASE_CASUALTY_RATE * ratio * rng.range_f32(0.75, 1.25)).min(0.5);
+        (men * rate).round() as u32
+    }
+
+    /// Shares losses out over the units in proportion to their size and how
+    /// long they were in the fight.
+    fn distribute_casualties(units: &mut [Unit], presence: &[f32], losses: u32) {
+        let men = Self::men_present(units, presence);
+        if men <= 0.0 {
+            return;
+        }
+
+        for (unit, p) in units.iter_mut().zip(presence) {
+            let share = (losses as f32 * unit.count as f32 * p / men) as u32;
+            CombatSystem::apply_casualties(unit, share);
+        }
+    }
+}
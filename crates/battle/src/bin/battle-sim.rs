@@ -0,0 +1,514 @@
+//! Fights the same battle over and over with different seeds, without a
+//! window, and reports how it tends to go. Meant for tuning unit stats and
+//! formation modifiers against numbers instead of by feel:
+//!
+//! ```text
+//! cargo run --release -p dot_wars_battle --bin battle-sim -- \
+//!     --attacker Infantry:4,Cavalry:2:Wedge --defender Infantry:4,Archers:2 \
+//!     --terrain Forest --battles 2000 --format csv
+//! ```
+//!
+//! Each seed gets its own battlefield and dice, so a run is reproducible
+//! from its arguments alone.
+
+use dot_wars_battle::*;
+use dot_wars_core::*;
+use dot_wars_world::*;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: battle-sim --attacker <army> --defender <army> [options]
+
+An army is a comma separated list of unit[:count[:formation]], for
+example Infantry:4,Cavalry:2:Wedge,Archers:2. Unit ids come from the
+unit definitions.
+
+options:
+  --attacker-formation <formation>  formation for attacker units that don't name one (Line)
+  --defender-formation <formation>  formation for defender units that don't name one (Line)
+  --terrain <terrain>               Plains, Forest, Mountains, Desert, Swamp or Coast (Plains)
+  --weather <weather>               Clear, Rain, Fog or Snow (Clear)
+  --time <time of day>              Dawn, Day, Dusk or Night (Day)
+  --battles <n>                     battles to fight (1000)
+  --seed <n>                        seed of the first battle, the others count up from it (1)
+  --men <n>                         men in every unit (100)
+  --max-seconds <n>                 battles still going after this long count as unfinished (900)
+  --ai <sides>                      sides under AI control: both, attacker, defender or none (both)
+  --units <path>                    unit definitions (assets/units/units.ron, built-in stats if missing)
+  --format <format>                 csv or json (csv)
+  --no-header                       leave out the CSV header, for appending to an earlier run
+  --threads <n>                     battles fought at once (all cores)";
+
+const DEFAULT_UNITS_PATH: &str = "assets/units/units.ron";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+/// Some units of one type in an army.
+#[derive(Debug, Clone)]
+struct ArmyEntry {
+    unit_type: UnitType,
+    count: u32,
+    formation: Option<Formation>,
+}
+
+#[derive(Debug, Clone)]
+struct Army {
+    spec: String, // As given on the command line, for the report
+    entries: Vec<ArmyEntry>,
+    formation: Formation,
+}
+
+#[derive(Debug)]
+struct Options {
+    attacker: Army,
+    defender: Army,
+    terrain: TerrainType,
+    conditions: BattleConditions,
+    battles: u32,
+    seed: u64,
+    men: u32,
+    max_seconds: f32,
+    ai: Vec<BattleSide>,
+    units: Option<String>,
+    format: Format,
+    header: bool,
+    threads: usize,
+}
+
+/// How one battle went.
+#[derive(Debug, Clone)]
+struct BattleResult {
+    winner: Option<BattleSide>,
+    finished: bool,
+    duration: f32,
+    attacker_casualties: u32,
+    defender_casualties: u32,
+    attacker_lost_gold: f32,
+    defender_lost_gold: f32,
+}
+
+impl BattleResult {
+    fn casualties(&self, side: BattleSide) -> u32 {
+        match side {
+            BattleSide::Attacker => self.attacker_casualties,
+            BattleSide::Defender => self.defender_casualties,
+        }
+    }
+
+    fn lost_gold(&self, side: BattleSide) -> f32 {
+        match side {
+            BattleSide::Attacker => self.attacker_lost_gold,
+            BattleSide::Defender => self.defender_lost_gold,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SideReport {
+    army: String,
+    men: u32,
+    cost: i32, // Gold to raise the whole army
+    wins: u32,
+    win_rate: f32,
+    mean_casualties: f32,
+    mean_lost_gold: f32,
+    cost_efficiency: f32, // Gold worth of enemy killed per gold worth of own men lost
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    terrain: String,
+    conditions: String,
+    battles: u32,
+    seed: u64,
+    draws: u32,
+    draw_rate: f32,
+    unfinished: u32, // Still going at --max-seconds, counted as draws
+    mean_duration: f32,
+    attacker: SideReport,
+    defender: SideReport,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+
+    let result = parse_options(&args).and_then(|options| {
+        let registry = load_registry(options.units.as_deref())?;
+        for army in [&options.attacker, &options.defender] {
+            check_army(army, &registry)?;
+        }
+        let results = run_battles(&options, &registry)?;
+        let report = summarise(&options, &registry, &results);
+        match options.format {
+            Format::Csv => Ok(to_csv(&report, options.header)),
+            Format::Json => serde_json::to_string_pretty(&report).map_err(|e| e.to_string()),
+        }
+    });
+
+    match result {
+        Ok(output) => {
+            println!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(message) => {
+            eprintln!("battle-sim: {message}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut attacker = None;
+    let mut defender = None;
+    let mut attacker_formation = Formation::Line;
+    let mut defender_formation = Formation::Line;
+    let mut terrain = TerrainType::Plains;
+    let mut conditions = BattleConditions::default();
+    let mut battles = 1000;
+    let mut seed = 1;
+    let mut men = 100;
+    let mut max_seconds = 900.0;
+    let mut ai = vec![BattleSide::Attacker, BattleSide::Defender];
+    let mut units = None;
+    let mut format = Format::Csv;
+    let mut header = true;
+    let mut threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        if flag == "--no-header" {
+            header = false;
+            continue;
+        }
+
+        let value = args.next().ok_or_else(|| format!("{flag} needs a value"))?;
+        match flag.as_str() {
+            "--attacker" => attacker = Some(value.as_str()),
+            "--defender" => defender = Some(value.as_str()),
+            "--attacker-formation" => attacker_formation = parse_name(value)?,
+            "--defender-formation" => defender_formation = parse_name(value)?,
+            "--terrain" => terrain = parse_name(value)?,
+            "--weather" => conditions.weather = parse_name(value)?,
+            "--time" => conditions.time_of_day = parse_name(value)?,
+            "--battles" => battles = parse_number(flag, value)?,
+            "--seed" => seed = parse_number(flag, value)?,
+            "--men" => men = parse_number(flag, value)?,
+            "--max-seconds" => max_seconds = parse_number(flag, value)?,
+            "--threads" => threads = parse_number::<usize>(flag, value)?.max(1),
+            "--units" => units = Some(value.clone()),
+            "--ai" => {
+                ai = match value.to_lowercase().as_str() {
+                    "both" => vec![BattleSide::Attacker, BattleSide::Defender],
+                    "attacker" => vec![BattleSide::Attacker],
+                    "defender" => vec![BattleSide::Defender],
+                    "none" => Vec::new(),
+                    _ => return Err(format!("unknown --ai value '{value}'")),
+                }
+            }
+            "--format" => {
+                format = match value.to_lowercase().as_str() {
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    _ => return Err(format!("unknown format '{value}'")),
+                }
+            }
+            _ => return Err(format!("unknown option '{flag}'")),
+        }
+    }
+
+    if men == 0 {
+        return Err("--men has to be at least 1".to_string());
+    }
+    Ok(Options {
+        attacker: parse_army(attacker.ok_or("--attacker is missing")?, attacker_formation)?,
+        defender: parse_army(defender.ok_or("--defender is missing")?, defender_formation)?,
+        terrain,
+        conditions,
+        battles,
+        seed,
+        men,
+        max_seconds,
+        ai,
+        units,
+        format,
+        header,
+        threads,
+    })
+}
+
+fn parse_army(spec: &str, formation: Formation) -> Result<Army, String> {
+    let mut entries = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.split(':');
+        let id = parts.next().unwrap_or_default();
+        let count = match parts.next() {
+            Some(count) => parse_number("unit count", count)?,
+            None => 1,
+        };
+        let formation = parts.next().map(parse_name).transpose()?;
+        if parts.next().is_some() {
+            return Err(format!("'{entry}' isn't unit[:count[:formation]]"));
+        }
+
+        entries.push(ArmyEntry {
+            unit_type: unit_type(id),
+            count,
+            formation,
+        });
+    }
+
+    if entries.iter().all(|e| e.count == 0) {
+        return Err(format!("army '{spec}' has no units"));
+    }
+    Ok(Army {
+        spec: spec.to_string(),
+        entries,
+        formation,
+    })
+}
+
+/// Errors on units the registry doesn't know and on formations their
+/// definitions don't allow.
+fn check_army(army: &Army, registry: &UnitRegistry) -> Result<(), String> {
+    for entry in &army.entries {
+        let formation = entry.formation.as_ref().unwrap_or(&army.formation);
+        registry
+            .check_formation(&entry.unit_type, formation)
+            .map_err(|e| format!("army '{}': {e}", army.spec))?;
+    }
+    Ok(())
+}
+
+fn unit_type(id: &str) -> UnitType {
+    [UnitType::Infantry, UnitType::Cavalry, UnitType::Archers, UnitType::Artillery]
+        .into_iter()
+        .find(|t| t.id().eq_ignore_ascii_case(id))
+        .unwrap_or_else(|| UnitType::Special(id.to_string()))
+}
+
+/// Reads a unit-less enum variant such as `Wedge` or `forest` by its name.
+fn parse_name<T: serde::de::DeserializeOwned>(name: &str) -> Result<T, String> {
+    let mut chars = name.trim().chars();
+    let capitalised: String = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect(),
+        None => String::new(),
+    };
+    ron::de::from_str(&capitalised).map_err(|_| format!("unknown name '{name}'"))
+}
+
+fn parse_number<T: std::str::FromStr>(what: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{what}: '{value}' isn't a number"))
+}
+
+fn load_registry(path: Option<&str>) -> Result<UnitRegistry, String> {
+    let path = match path {
+        Some(path) => path,
+        None if std::path::Path::new(DEFAULT_UNITS_PATH).exists() => DEFAULT_UNITS_PATH,
+        None => return Ok(UnitRegistry::built_in()),
+    };
+
+    let data = std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    UnitRegistry::from_ron_str(&data).map_err(|e| format!("{path}: {e}"))
+}
+
+/// Fights every battle, spread over `options.threads` threads. The results
+/// come back in seed order whatever the number of threads.
+fn run_battles(options: &Options, registry: &UnitRegistry) -> Result<Vec<BattleResult>, String> {
+    let seeds: Vec<u64> = (0..options.battles as u64).map(|i| options.seed.wrapping_add(i)).collect();
+    let chunk = seeds.len().div_ceil(options.threads).max(1);
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = seeds
+            .chunks(chunk)
+            .map(|seeds| scope.spawn(move || seeds.iter().map(|&seed| fight(options, registry, seed)).collect()))
+            .collect();
+
+        let mut results = Vec::with_capacity(seeds.len());
+        for worker in workers {
+            let chunk: Result<Vec<BattleResult>, String> = worker.join().map_err(|_| "a battle panicked")?;
+            results.extend(chunk?);
+        }
+        Ok(results)
+    })
+}
+
+fn fight(options: &Options, registry: &UnitRegistry, seed: u64) -> Result<BattleResult, String> {
+    let attacker = FactionId::default();
+    let defender = FactionId::default();
+    let province = Province {
+        id: ProvinceId::default(),
+        name: "Battle Simulator".to_string(),
+        owner: Some(defender),
+        position: Position::new(0.0, 0.0),
+        population: 0,
+        resources: Resources::new(),
+        buildings: Vec::new(),
+        adjacent_provinces: Vec::new(),
+        terrain_type: options.terrain.clone(),
+    };
+    let battlefield = BattlefieldGenerator::from_province(&province, seed);
+    let mut battle = Battle::new(format!("battle-sim-{seed}"), attacker, defender, battlefield);
+    battle.conditions = options.conditions;
+    let mut simulation = BattleSimulation::new(battle, seed);
+
+    for (side, faction, army) in [
+        (BattleSide::Attacker, attacker, &options.attacker),
+        (BattleSide::Defender, defender, &options.defender),
+    ] {
+        for entry in &army.entries {
+            for _ in 0..entry.count {
+                let unit = Unit {
+                    id: UnitId::default(),
+                    unit_type: entry.unit_type.clone(),
+                    count: options.men,
+                    max_count: options.men,
+                    morale: 100.0,
+                    experience: 0,
+                    formation: entry.formation.clone().unwrap_or_else(|| army.formation.clone()),
+                    faction,
+                };
+                let unit = BattleUnit::from_registry(registry, unit, side, Position::new(0.0, 0.0))
+                    .map_err(|e| e.to_string())?;
+                simulation.add_unit(unit);
+            }
+        }
+    }
+
+    for &side in &options.ai {
+        simulation.enable_ai(side);
+    }
+    for side in [BattleSide::Attacker, BattleSide::Defender] {
+        simulation
+            .auto_deploy(side)
+            .and_then(|_| simulation.mark_ready(side))
+            .map_err(|e| format!("seed {seed}: could not deploy the {side:?}: {e}"))?;
+    }
+
+    while simulation.outcome.is_none() && simulation.elapsed < options.max_seconds {
+        simulation.step(BATTLE_TICK);
+    }
+
+    let lost_gold = |side: BattleSide| -> f32 {
+        simulation
+            .units
+            .iter()
+            .filter(|u| u.side == side)
+            .map(|u| {
+                let cost = registry.definition(&u.unit.unit_type).map_or(0, |d| d.stats.cost.gold);
+                cost as f32 * u.casualties() as f32 / u.initial_count.max(1) as f32
+            })
+            .sum()
+    };
+
+    Ok(BattleResult {
+        winner: simulation.outcome.as_ref().and_then(|o| o.winner),
+        finished: simulation.outcome.is_some(),
+        duration: simulation.elapsed,
+        attacker_casualties: simulation.side_casualties(BattleSide::Attacker),
+        defender_casualties: simulation.side_casualties(BattleSide::Defender),
+        attacker_lost_gold: lost_gold(BattleSide::Attacker),
+        defender_lost_gold: lost_gold(BattleSide::Defender),
+    })
+}
+
+fn summarise(options: &Options, registry: &UnitRegistry, results: &[BattleResult]) -> Report {
+    let battles = results.len().max(1) as f32;
+    let mean = |value: &dyn Fn(&BattleResult) -> f32| results.iter().map(value).sum::<f32>() / battles;
+
+    let side = |side: BattleSide, army: &Army| {
+        let units = army.entries.iter().map(|e| (e, e.count));
+        let men = units.clone().map(|(_, count)| count * options.men).sum();
+        let cost = units
+            .map(|(e, count)| registry.definition(&e.unit_type).map_or(0, |d| d.stats.cost.gold) * count as i32)
+            .sum();
+        let wins = results.iter().filter(|r| r.winner == Some(side)).count() as u32;
+        let inflicted: f32 = results.iter().map(|r| r.lost_gold(side.opponent())).sum();
+        let suffered: f32 = results.iter().map(|r| r.lost_gold(side)).sum();
+
+        SideReport {
+            army: army.spec.clone(),
+            men,
+            cost,
+            wins,
+            win_rate: wins as f32 / battles,
+            mean_casualties: mean(&|r| r.casualties(side) as f32),
+            mean_lost_gold: suffered / battles,
+            cost_efficiency: inflicted / suffered.max(1.0),
+        }
+    };
+
+    let draws = results.iter().filter(|r| r.winner.is_none()).count() as u32;
+    Report {
+        terrain: format!("{:?}", options.terrain),
+        conditions: options.conditions.description(),
+        battles: results.len() as u32,
+        seed: options.seed,
+        draws,
+        draw_rate: draws as f32 / battles,
+        unfinished: results.iter().filter(|r| !r.finished).count() as u32,
+        mean_duration: mean(&|r| r.duration),
+        attacker: side(BattleSide::Attacker, &options.attacker),
+        defender: side(BattleSide::Defender, &options.defender),
+    }
+}
+
+/// One header line and one row, so runs from different commits can be
+/// appended to the same file with `--no-header`.
+fn to_csv(report: &Report, header: bool) -> String {
+    const SIDE_COLUMNS: [&str; 7] = [
+        "army",
+        "men",
+        "cost",
+        "win_rate",
+        "mean_casualties",
+        "mean_lost_gold",
+        "cost_efficiency",
+    ];
+
+    let mut csv = String::new();
+    if header {
+        csv.push_str("terrain,conditions,battles,seed,draw_rate,unfinished,mean_duration");
+        for side in ["attacker", "defender"] {
+            for column in SIDE_COLUMNS {
+                let _ = write!(csv, ",{side}_{column}");
+            }
+        }
+        csv.push('\n');
+    }
+
+    let _ = write!(
+        csv,
+        "{},\"{}\",{},{},{:.4},{},{:.2}",
+        report.terrain,
+        report.conditions,
+        report.battles,
+        report.seed,
+        report.draw_rate,
+        report.unfinished,
+        report.mean_duration
+    );
+    for side in [&report.attacker, &report.defender] {
+        let _ = write!(
+            csv,
+            ",\"{}\",{},{},{:.4},{:.2},{:.2},{:.4}",
+            side.army.replace('"', "\"\""),
+            side.men,
+            side.cost,
+            side.win_rate,
+            side.mean_casualties,
+            side.mean_lost_gold,
+            side.cost_efficiency
+        );
+    }
+    csv
+}
@@ -0,0 +1,141 @@
+use crate::*;
+
+// ============================================================================
+// CAVALRY CHARGES
+// ============================================================================
+
+/// Cavalry has to cover this much ground at charging speed or faster for
+/// its first blow to land as a charge.
+pub const CHARGE_RUN_UP: f32 = 60.0;
+pub const CHARGE_MIN_SPEED: f32 = 70.0;
+
+/// A charge at exactly the minimum speed strikes this many times as hard as
+/// an ordinary blow, and harder the faster it comes in.
+const CHARGE_IMPACT_MULTIPLIER: f32 = 2.0;
+
+/// Morale the target of a charge loses on top of what its casualties cost
+/// it, before its steadiness is taken into account.
+const CHARGE_MORALE_SHOCK: f32 = 15.0;
+
+/// A charge into or out of woods loses most of its weight among the trees.
+const FOREST_CHARGE_MULTIPLIER: f32 = 0.4;
+
+/// Cavalry charging a braced square runs onto its spears and takes this
+/// share of the blow its charge would have dealt, and the riders get the
+/// morale shock instead.
+const SQUARE_REPULSE_SHARE: f32 = 0.5;
+
+/// What became of a charge that went in this tick.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChargeImpact {
+    pub cavalry: UnitId,
+    pub target: UnitId,
+    pub repulsed: bool, // Broke on a braced square
+    pub casualties: u32, // Inflicted on the target, or suffered by the cavalry when repulsed
+}
+
+impl Battlefield {
+    pub fn in_forest(&self, position: &Position) -> bool {
+        self.terrain_effects
+            .iter()
+            .any(|e| matches!(e.effect_type, TerrainEffectType::Forest { .. }) && e.contains(position))
+    }
+}
+
+impl BattleUnit {
+    pub fn is_cavalry(&self) -> bool {
+        matches!(self.unit.unit_type, UnitType::Cavalry)
+    }
+
+    /// True when the unit's next blow in melee lands as a charge.
+    pub fn charge_ready(&self) -> bool {
+        self.is_cavalry() && self.can_fight() && self.run_up >= CHARGE_RUN_UP
+    }
+
+    /// Foot in a square that hasn't broken. Squares have no flanks, so it
+    /// doesn't matter where a charge comes from.
+    pub fn is_braced(&self) -> bool {
+        self.can_fight()
+            && self.unit.formation == Formation::Square
+            && matches!(self.unit.unit_type, UnitType::Infantry | UnitType::Special(_))
+    }
+}
+
+impl BattleSimulation {
+    /// Cavalry builds up a run-up while it keeps going at charging speed
+    /// and loses it as soon as it slows down or stops. Pulling up in contact
+    /// with its target doesn't count, the first blow there is the charge.
+    pub(crate) fn build_momentum(&mut self, index: usize, covered: f32, delta_time: f32) {
+        let speed = covered / delta_time.max(f32::EPSILON);
+        let closing_in = !self.units[index].is_reforming()
+            && self.units[index]
+                .target
+                .and_then(|id| self.index_of(id))
+                .is_some_and(|target| self.can_reach_in_melee(index, target));
+        if speed < CHARGE_MIN_SPEED && closing_in {
+            return;
+        }
+
+        let unit = &mut self.units[index];
+        if !unit.is_cavalry() || speed < CHARGE_MIN_SPEED {
+            unit.run_up = 0.0;
+            unit.momentum = 0.0;
+            return;
+        }
+
+        unit.run_up += covered;
+        unit.momentum = speed;
+    }
+
+    /// Turns the first blow of cavalry with a full run-up into a charge.
+    /// Against most targets it hits far harder and shakes them, against a
+    /// braced square the riders get the worst of it. Returns the casualties
+    /// the cavalry's own blow still inflicts.
+    pub(crate) fn resolve_charge(&mut self, index: usize, target_index: usize, casualties: u32) -> u32 {
+        if !self.units[index].charge_ready() {
+            return casualties;
+        }
+
+        let momentum = self.units[index].momentum / CHARGE_MIN_SPEED;
+        self.units[index].run_up = 0.0;
+        self.units[index].momentum = 0.0;
+
+        let (cavalry, target) = (self.units[index].id(), self.units[target_index].id());
+        if self.units[target_index].is_braced() {
+            let repulse = (casualties as f32 * CHARGE_IMPACT_MULTIPLIER * momentum * SQUARE_REPULSE_SHARE) as u32;
+            self.apply_hit(Some(target_index), index, repulse, AttackDirection::Front);
+            self.shock(index, momentum);
+            self.last_charges.push(ChargeImpact {
+                cavalry,
+                target,
+                repulsed: true,
+                casualties: repulse,
+            });
+            return casualties;
+        }
+
+        let battlefield = &self.battle.battlefield;
+        let in_woods = [index, target_index]
+            .iter()
+            .any(|&i| battlefield.in_forest(&self.units[i].position));
+        let terrain = if in_woods { FOREST_CHARGE_MULTIPLIER } else { 1.0 };
+
+        let impact = 1.0 + (CHARGE_IMPACT_MULTIPLIER * momentum - 1.0) * terrain;
+        self.shock(target_index, momentum * terrain);
+
+        let casualties = (casualties as f32 * impact) as u32;
+        self.last_charges.push(ChargeImpact {
+            cavalry,
+            target,
+            repulsed: false,
+            casualties,
+        });
+        casualties
+    }
+
+    fn shock(&mut self, index: usize, weight: f32) {
+        let loss = CHARGE_MORALE_SHOCK * weight / self.steadiness(index);
+        let unit = &mut self.units[index].unit;
+        unit.morale = (unit.morale - loss).max(0.0);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::*;
+
+// ============================================================================
+// BATTLE TIME SCALE
+// ============================================================================
+
+pub const MIN_BATTLE_SPEED: f32 = 0.25;
+pub const MAX_BATTLE_SPEED: f32 = 4.0;
+
+/// The speeds `BattleClock::faster` and `BattleClock::slower` step through.
+pub const BATTLE_SPEEDS: [f32; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+
+/// How fast a live battle runs compared to real time. The simulation always
+/// advances by whole `BATTLE_TICK`s, the clock only decides how many of them
+/// fit into each fixed update: four at 4x, one every fourth update at 0.25x,
+/// none while paused. Ticks stay the same length at every speed, so a replay
+/// plays out the same whatever speed the battle was watched at. The UI runs
+/// on its own time and isn't slowed down or paused with the battle.
+///
+/// Orders can still be given while paused. They wait in
+/// `BattleSimulation::pending_commands` and are carried out on the first
+/// tick after the battle goes on, which is also the tick they are recorded
+/// under.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct BattleClock {
+    pub paused: bool,
+    pub speed: f32,
+    accumulator: f32, // Ticks owed but not stepped yet
+}
+
+impl Default for BattleClock {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            speed: 1.0,
+            accumulator: 0.0,
+        }
+    }
+}
+
+impl BattleClock {
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_BATTLE_SPEED, MAX_BATTLE_SPEED);
+    }
+
+    /// Goes up to the next speed in `BATTLE_SPEEDS`.
+    pub fn faster(&mut self) {
+        if let Some(&speed) = BATTLE_SPEEDS.iter().find(|&&s| s > self.speed) {
+            self.speed = speed;
+        }
+    }
+
+    /// Goes down to the next speed in `BATTLE_SPEEDS`.
+    pub fn slower(&mut self) {
+        if let Some(&speed) = BATTLE_SPEEDS.iter().rev().find(|&&s| s < self.speed) {
+            self.speed = speed;
+        }
+    }
+
+    /// Lets a paused battle go on by a single tick.
+    pub fn step_once(&mut self) {
+        if self.paused {
+            self.accumulator += 1.0;
+        }
+    }
+
+    /// How many ticks the battle should advance in this fixed update.
+    pub fn ticks_due(&mut self) -> u32 {
+        if !self.paused {
+            self.accumulator += self.speed;
+        }
+
+        let ticks = self.accumulator.floor();
+        self.accumulator -= ticks;
+        ticks as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    const TICKS: u64 = 240;
+
+    /// Plays the test skirmish the way `step_battle_simulation` does, one
+    /// fixed update at a time, and returns the state it ends up in.
+    fn play(mut clock: BattleClock) -> u64 {
+        let mut simulation = skirmish(21);
+        let script = command_script(&simulation);
+        let mut updates = 0;
+        while simulation.tick < TICKS && simulation.outcome.is_none() {
+            clock.step_once();
+            for _ in 0..clock.ticks_due() {
+                if simulation.tick == TICKS || simulation.outcome.is_some() {
+                    break;
+                }
+                let tick = simulation.tick;
+                simulation.queue_commands(script.iter().filter(|(t, _)| *t == tick).map(|(_, c)| c.clone()));
+                simulation.step(BATTLE_TICK);
+            }
+            updates += 1;
+            assert!(updates <= TICKS * 4, "the battle stopped advancing");
+        }
+        assert_eq!(simulation.tick, TICKS);
+        simulation.checksum()
+    }
+
+    fn at_speed(speed: f32) -> BattleClock {
+        let mut clock = BattleClock::default();
+        clock.set_speed(speed);
+        clock
+    }
+
+    #[test]
+    fn the_battle_plays_out_the_same_at_every_speed() {
+        let normal = play(BattleClock::default());
+        assert_eq!(play(at_speed(MIN_BATTLE_SPEED)), normal);
+        assert_eq!(play(at_speed(MAX_BATTLE_SPEED)), normal);
+
+        let mut paused = BattleClock::default();
+        paused.pause();
+        assert_eq!(play(paused), normal);
+    }
+
+    #[test]
+    fn ticks_come_due_by_speed_and_not_at_all_while_paused() {
+        let mut clock = at_speed(MIN_BATTLE_SPEED);
+        assert_eq!((0..8).map(|_| clock.ticks_due()).collect::<Vec<_>>(), vec![0, 0, 0, 1, 0, 0, 0, 1]);
+
+        clock.set_speed(10.0);
+        assert_eq!(clock.speed, MAX_BATTLE_SPEED);
+        assert_eq!(clock.ticks_due(), 4);
+
+        clock.pause();
+        assert_eq!(clock.ticks_due(), 0);
+        clock.step_once();
+        assert_eq!(clock.ticks_due(), 1);
+        assert_eq!(clock.ticks_due(), 0);
+
+        clock.slower();
+        clock.slower();
+        assert_eq!(clock.speed, 1.0);
+        clock.faster();
+        assert_eq!(clock.speed, 2.0);
+    }
+}
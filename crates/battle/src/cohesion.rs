@@ -0,0 +1,178 @@
+use crate::*;
+
+// ============================================================================
+// FORMATION CHANGES AND COHESION
+// ============================================================================
+
+pub const MAX_COHESION: f32 = 100.0;
+
+/// Units come onto the field in good order.
+pub(crate) fn full_cohesion() -> f32 {
+    MAX_COHESION
+}
+
+/// Cohesion lost with every blow struck or taken in melee.
+const MELEE_COHESION_LOSS: f32 = 1.5;
+
+/// Cohesion lost per second wading through a river.
+const RIVER_COHESION_LOSS: f32 = 10.0;
+
+/// Cohesion regained per second standing still out of the fight. Reforming
+/// restores all of it at once.
+const COHESION_RECOVERY: f32 = 2.0;
+
+/// Units caught halfway through a formation change take this much more
+/// damage, and can't strike back until they are done.
+pub const REFORMING_DAMAGE_MULTIPLIER: f32 = 1.5;
+
+/// A unit halfway through taking up a new formation. It keeps its old one
+/// until `remaining` runs out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormationChange {
+    pub formation: Formation,
+    pub remaining: f32, // Seconds
+}
+
+impl UnitType {
+    /// Seconds green troops of this type take to change formation.
+    pub fn reform_time(&self) -> f32 {
+        match self {
+            UnitType::Infantry => 6.0,
+            UnitType::Cavalry => 4.0,
+            UnitType::Archers => 3.0,
+            UnitType::Artillery => 10.0, // The guns have to be limbered and unlimbered
+            UnitType::Special(_) => 6.0,
+        }
+    }
+}
+
+impl FormationModifiers {
+    /// The formation as held by a unit in disorder: whatever it does better
+    /// than a plain line only counts for the share of cohesion left, its
+    /// drawbacks count in full.
+    pub fn with_cohesion(&self, cohesion: f32) -> Self {
+        let share = (cohesion / MAX_COHESION).clamp(0.0, 1.0);
+        let scale = |modifier: f32| {
+            if modifier > 1.0 {
+                1.0 + (modifier - 1.0) * share
+            } else {
+                modifier
+            }
+        };
+
+        Self {
+            attack_modifier: scale(self.attack_modifier),
+            defense_modifier: scale(self.defense_modifier),
+            speed_modifier: scale(self.speed_modifier),
+            morale_modifier: scale(self.morale_modifier),
+        }
+    }
+}
+
+impl BattleUnit {
+    pub fn is_reforming(&self) -> bool {
+        self.formation_change.is_some()
+    }
+
+    /// Modifiers of the formation the unit is in, as far as its cohesion
+    /// lets it make use of them.
+    pub fn formation_modifiers(&self) -> FormationModifiers {
+        self.unit.formation.get_modifiers().with_cohesion(self.cohesion)
+    }
+
+    /// `stats` with the formation bonuses lost to disorder taken back out.
+    /// Damage is worked out with the formation's full modifiers, so the
+    /// difference comes off the stats instead.
+    pub fn disordered_stats(&self, stats: &CombatStats) -> CombatStats {
+        let full = self.unit.formation.get_modifiers();
+        let held = self.formation_modifiers();
+        CombatStats {
+            attack: stats.attack * held.attack_modifier / full.attack_modifier,
+            defense: stats.defense * held.defense_modifier / full.defense_modifier,
+            ..stats.clone()
+        }
+    }
+
+    /// Seconds this unit takes to change formation. Veterans have done it
+    /// often enough to be quicker about it.
+    pub fn reform_time(&self) -> f32 {
+        self.unit.unit_type.reform_time() * self.unit.rank().bonuses().reform_time_multiplier
+    }
+
+    /// False for formations the unit's definition doesn't allow, see
+    /// `UnitDefinition::allowed_formations`.
+    pub fn allows_formation(&self, formation: &Formation) -> bool {
+        self.allowed_formations.is_empty() || self.allowed_formations.contains(formation)
+    }
+
+    /// Starts taking up `formation`. Ordering the formation the unit is
+    /// already in makes it reform its ranks, which restores its cohesion.
+    /// Returns false if there is nothing to do.
+    pub(crate) fn change_formation(&mut self, formation: &Formation) -> bool {
+        let already = match &self.formation_change {
+            Some(change) => change.formation == *formation,
+            None => self.unit.formation == *formation && self.cohesion >= MAX_COHESION,
+        };
+        if already {
+            return false;
+        }
+
+        self.formation_change = Some(FormationChange {
+            formation: formation.clone(),
+            remaining: self.reform_time(),
+        });
+        true
+    }
+
+    fn lose_cohesion(&mut self, amount: f32) {
+        self.cohesion = (self.cohesion - amount).max(0.0);
+    }
+}
+
+impl BattleSimulation {
+    /// Counts down formation changes. A unit that has finished stands in
+    /// its new formation in good order, one that broke gives up on it.
+    pub(crate) fn update_formations(&mut self, delta_time: f32) {
+        for unit in self.units.iter_mut().filter(|u| u.is_reforming()) {
+            if !unit.can_fight() {
+                unit.formation_change = None;
+                continue;
+            }
+
+            let Some(change) = unit.formation_change.as_mut() else {
+                continue;
+            };
+            change.remaining -= delta_time;
+            if change.remaining <= 0.0 {
+                unit.unit.formation = change.formation.clone();
+                unit.cohesion = MAX_COHESION;
+                unit.formation_change = None;
+            }
+        }
+    }
+
+    /// Ranks come apart wading through a river and slowly close up again
+    /// while the unit stands still out of the fight.
+    pub(crate) fn keep_ranks(&mut self, index: usize, moving: bool, delta_time: f32) {
+        let unit = &self.units[index];
+        let in_river = self
+            .battle
+            .battlefield
+            .terrain_effects
+            .iter()
+            .any(|e| matches!(e.effect_type, TerrainEffectType::River { .. }) && e.contains(&unit.position));
+
+        if moving && in_river {
+            self.units[index].lose_cohesion(RIVER_COHESION_LOSS * delta_time);
+        } else if !moving && !self.is_engaged_in_melee(unit.id()) {
+            let unit = &mut self.units[index];
+            unit.cohesion = (unit.cohesion + COHESION_RECOVERY * delta_time).min(MAX_COHESION);
+        }
+    }
+
+    /// Both sides of a melee lose some order with every blow.
+    pub(crate) fn melee_disorder(&mut self, attacker_index: usize, defender_index: usize) {
+        self.units[attacker_index].lose_cohesion(MELEE_COHESION_LOSS);
+        self.units[defender_index].lose_cohesion(MELEE_COHESION_LOSS);
+    }
+}
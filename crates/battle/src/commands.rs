@@ -0,0 +1,191 @@
+use crate::*;
+
+// ============================================================================
+// BATTLE COMMANDS
+// ============================================================================
+
+/// An order given to a unit, by the player, the AI or a replay. Commands
+/// are plain data so they can be recorded and fed back in later.
+/// Every order but `SetFormation` and the queued ones replaces whatever the
+/// unit was told before, including orders still waiting in its queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BattleCommand {
+    Move { unit: UnitId, destination: Position },
+    Attack { unit: UnitId, target: UnitId },
+    SetFormation { unit: UnitId, formation: Formation },
+    Halt { unit: UnitId }, // Stop: drop every order and let the unit pick its own fights again
+    HoldPosition { unit: UnitId }, // Stand fast, only fight what comes close
+    Withdraw { unit: UnitId },     // Fall back off the field without routing
+    QueueMove { unit: UnitId, destination: Position }, // Carried out once the orders before it are done
+    QueueAttack { unit: UnitId, target: UnitId },
+}
+
+/// An order waiting in a unit's queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Waypoint {
+    Move(Position),
+    Attack(UnitId),
+}
+
+impl BattleCommand {
+    pub fn unit(&self) -> UnitId {
+        match self {
+            BattleCommand::Move { unit, .. }
+            | BattleCommand::Attack { unit, .. }
+            | BattleCommand::SetFormation { unit, .. }
+            | BattleCommand::Halt { unit }
+            | BattleCommand::HoldPosition { unit }
+            | BattleCommand::Withdraw { unit }
+            | BattleCommand::QueueMove { unit, .. }
+            | BattleCommand::QueueAttack { unit, .. } => *unit,
+        }
+    }
+}
+
+impl BattleUnit {
+    /// True while the unit is carrying out an order: walking to where it
+    /// was sent, or going for the enemy it was set on.
+    pub fn has_orders(&self) -> bool {
+        self.move_target.is_some() || self.target_locked || !self.waypoints.is_empty()
+    }
+
+    /// Sets the unit off on `waypoint`, leaving the orders queued up after
+    /// it alone.
+    fn begin(&mut self, waypoint: Waypoint, battlefield: &Battlefield) {
+        match waypoint {
+            Waypoint::Move(destination) => {
+                self.move_target = Some(battlefield.clamp(&destination));
+            }
+            Waypoint::Attack(target) => {
+                self.target = Some(target);
+                self.target_locked = true;
+                self.move_target = None;
+            }
+        }
+        self.hold_position = false;
+        self.withdrawing = false;
+    }
+
+    /// Sets the unit off on `waypoint` straight away if it has nothing else
+    /// to do, otherwise lines it up behind its other orders.
+    fn enqueue(&mut self, waypoint: Waypoint, battlefield: &Battlefield) {
+        if self.has_orders() {
+            self.waypoints.push_back(waypoint);
+        } else {
+            self.begin(waypoint, battlefield);
+        }
+    }
+}
+
+/// A command together with the tick it was carried out on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedCommand {
+    pub tick: u64,
+    pub command: BattleCommand,
+}
+
+impl BattleSimulation {
+    /// Queues a command to be carried out at the start of the next tick.
+    pub fn queue_command(&mut self, command: BattleCommand) {
+        self.pending_commands.push(command);
+    }
+
+    pub fn queue_commands(&mut self, commands: impl IntoIterator<Item = BattleCommand>) {
+        self.pending_commands.extend(commands);
+    }
+
+    pub(crate) fn apply_pending_commands(&mut self) {
+        let commands = std::mem::take(&mut self.pending_commands);
+        self.last_commands.clear();
+
+        for command in commands {
+            // Units under AI control take their orders from the AI alone
+            if self.is_ai_controlled(command.unit()) {
+                continue;
+            }
+            if self.apply_command(&command) {
+                self.last_commands.push(TimedCommand {
+                    tick: self.tick,
+                    command,
+                });
+            }
+        }
+    }
+
+    /// Returns false when the order was ignored, e.g. because the unit is
+    /// routing, no longer exists or can't take up the formation it was
+    /// ordered into.
+    pub(crate) fn apply_command(&mut self, command: &BattleCommand) -> bool {
+        let Some(index) = self.index_of(command.unit()) else {
+            return false;
+        };
+        if !self.units[index].can_fight() {
+            return false;
+        }
+
+        let battlefield = &self.battle.battlefield;
+        let unit = &mut self.units[index];
+        match command {
+            BattleCommand::Move { destination, .. } => {
+                unit.waypoints.clear();
+                unit.begin(Waypoint::Move(destination.clone()), battlefield);
+            }
+            BattleCommand::Attack { target, .. } => {
+                unit.waypoints.clear();
+                unit.begin(Waypoint::Attack(*target), battlefield);
+            }
+            BattleCommand::SetFormation { formation, .. } => {
+                return unit.allows_formation(formation) && unit.change_formation(formation);
+            }
+            BattleCommand::Halt { .. } => {
+                unit.waypoints.clear();
+                unit.move_target = None;
+                unit.target = None;
+                unit.target_locked = false;
+                unit.hold_position = false;
+                unit.withdrawing = false;
+            }
+            BattleCommand::HoldPosition { .. } => {
+                unit.waypoints.clear();
+                unit.move_target = None;
+                unit.hold_position = true;
+                unit.withdrawing = false;
+            }
+            BattleCommand::Withdraw { .. } => {
+                unit.waypoints.clear();
+                unit.move_target = None;
+                unit.target_locked = false;
+                unit.hold_position = false;
+                unit.withdrawing = true;
+            }
+            BattleCommand::QueueMove { destination, .. } => {
+                unit.enqueue(Waypoint::Move(destination.clone()), battlefield);
+            }
+            BattleCommand::QueueAttack { target, .. } => {
+                unit.enqueue(Waypoint::Attack(*target), battlefield);
+            }
+        }
+        true
+    }
+
+    /// Units done with their current order go on to the next one in their
+    /// queue. An attack is done once its target is dead or off the field.
+    pub(crate) fn follow_waypoints(&mut self) {
+        for i in 0..self.units.len() {
+            let unit = &self.units[i];
+            if unit.waypoints.is_empty() || !unit.can_fight() || unit.move_target.is_some() {
+                continue;
+            }
+            let attacking = unit.target_locked && unit.target.and_then(|id| self.unit(id)).is_some_and(|t| t.is_active());
+            if attacking {
+                continue;
+            }
+
+            let unit = &mut self.units[i];
+            unit.target_locked = false;
+            if let Some(waypoint) = unit.waypoints.pop_front() {
+                unit.begin(waypoint, &self.battle.battlefield);
+            }
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use crate::*;
+use std::fmt;
+
+// ============================================================================
+// DEPLOYMENT
+// ============================================================================
+
+/// Deployment zones never reach further into the field than this share of
+/// its height, and are never shallower than the minimum.
+const MAX_DEPLOYMENT_DEPTH: f32 = 0.2;
+const MIN_DEPLOYMENT_DEPTH: f32 = 0.05;
+
+/// Gap left between units placed by `auto_deploy`.
+const AUTO_DEPLOY_SPACING: f32 = 10.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentZone {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+}
+
+impl DeploymentZone {
+    pub fn contains(&self, position: &Position) -> bool {
+        position.x >= self.min_x && position.x <= self.max_x && position.y >= self.min_y && position.y <= self.max_y
+    }
+
+    pub fn width(&self) -> f32 {
+        self.max_x - self.min_x
+    }
+
+    pub fn depth(&self) -> f32 {
+        self.max_y - self.min_y
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeploymentState {
+    pub attacker_ready: bool,
+    pub defender_ready: bool,
+}
+
+impl DeploymentState {
+    pub fn is_ready(&self, side: BattleSide) -> bool {
+        match side {
+            BattleSide::Attacker => self.attacker_ready,
+            BattleSide::Defender => self.defender_ready,
+        }
+    }
+
+    fn set_ready(&mut self, side: BattleSide, ready: bool) {
+        match side {
+            BattleSide::Attacker => self.attacker_ready = ready,
+            BattleSide::Defender => self.defender_ready = ready,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeploymentError {
+    NotDeploying,
+    UnknownUnit(UnitId),
+    OutsideZone(UnitId),
+    Overlapping(UnitId, UnitId),
+    NoRoom(UnitId),
+}
+
+impl fmt::Display for DeploymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeploymentError::NotDeploying => write!(f, "the battle is not in its deployment phase"),
+            DeploymentError::UnknownUnit(id) => write!(f, "unit {:?} is not part of this battle", id.0),
+            DeploymentError::OutsideZone(id) => write!(f, "unit {:?} is outside its deployment zone", id.0),
+            DeploymentError::Overlapping(id, other) => write!(f, "unit {:?} overlaps unit {:?}", id.0, other.0),
+            DeploymentError::NoRoom(id) => write!(f, "no room left to deploy unit {:?}", id.0),
+        }
+    }
+}
+
+impl std::error::Error for DeploymentError {}
+
+impl Battlefield {
+    /// The strip along a side's edge where it may place its units. It stops
+    /// short of any river, and a defender holding fortifications may also
+    /// deploy on them.
+    pub fn deployment_zone(&self, side: BattleSide) -> DeploymentZone {
+        let edge = self.deployment_edge(side);
+        let mut depth = self.height * MAX_DEPLOYMENT_DEPTH;
+
+        for effect in &self.terrain_effects {
+            if let TerrainEffectType::River { .. } = effect.effect_type {
+                let river_bank = (effect.position.y - edge).abs() - effect.radius;
+                depth = depth.min(river_bank);
+            }
+        }
+        depth = depth.max(self.height * MIN_DEPLOYMENT_DEPTH);
+
+        let (mut min_y, mut max_y) = match side {
+            BattleSide::Attacker => (0.0, depth),
+            BattleSide::Defender => (self.height - depth, self.height),
+        };
+
+        if side == BattleSide::Defender {
+            for effect in &self.terrain_effects {
+                if let TerrainEffectType::Fortification { .. } = effect.effect_type {
+                    min_y = min_y.min(effect.position.y - effect.radius).max(0.0);
+                    max_y = max_y.max(effect.position.y + effect.radius).min(self.height);
+                }
+            }
+        }
+
+        DeploymentZone {
+            min_x: 0.0,
+            min_y,
+            max_x: self.width,
+            max_y,
+        }
+    }
+}
+
+impl BattleUnit {
+    /// Rough radius of the ground a unit covers, grows with its head count.
+    pub fn footprint_radius(&self) -> f32 {
+        5.0 + (self.unit.count as f32).sqrt() * 1.5
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Row `auto_deploy` puts the unit in, 0 being the front. It goes by
+    /// the tags of the unit's definition, and by its range for units built
+    /// without one.
+    fn deployment_row(&self) -> u32 {
+        if self.has_tag("siege") || self.min_range > 0.0 {
+            3
+        } else if self.has_tag("cavalry") {
+            1
+        } else if self.has_tag("ranged") || self.range > MELEE_RANGE {
+            2
+        } else {
+            0
+        }
+    }
+}
+
+impl BattleSimulation {
+    /// Moves a unit to `position` during deployment. Placing a unit takes
+    /// back its side's "ready".
+    pub fn place_unit(&mut self, id: UnitId, position: Position) -> Result<(), DeploymentError> {
+        if !matches!(self.battle.phase, BattlePhase::Deployment) {
+            return Err(DeploymentError::NotDeploying);
+        }
+
+        let index = self.index_of(id).ok_or(DeploymentError::UnknownUnit(id))?;
+        self.check_placement(index, &position)?;
+
+        let unit = &mut self.units[index];
+        unit.position = position;
+        let side = unit.side;
+        self.deployment.set_ready(side, false);
+        Ok(())
+    }
+
+    fn check_placement(&self, index: usize, position: &Position) -> Result<(), DeploymentError> {
+        let unit = &self.units[index];
+        let zone = self.battle.battlefield.deployment_zone(unit.side);
+        if !zone.contains(position) {
+            return Err(DeploymentError::OutsideZone(unit.id()));
+        }
+
+        let radius = unit.footprint_radius();
+        let overlapping = self.units.iter().enumerate().find(|(i, other)| {
+            *i != index && other.position.distance_to(position) < radius + other.footprint_radius()
+        });
+        if let Some((_, other)) = overlapping {
+            return Err(DeploymentError::Overlapping(unit.id(), other.id()));
+        }
+
+        Ok(())
+    }
+
+    /// Lays out a side the way the AI likes it: melee infantry in the front
+    /// line, cavalry on the wings, missile troops behind and artillery at
+    /// the back.
+    pub fn auto_deploy(&mut self, side: BattleSide) -> Result<(), DeploymentError> {
+        if !matches!(self.battle.phase, BattlePhase::Deployment) {
+            return Err(DeploymentError::NotDeploying);
+        }
+
+        let zone = self.battle.battlefield.deployment_zone(side);
+        let mut indices: Vec<usize> = (0..self.units.len()).filter(|&i| self.units[i].side == side).collect();
+        indices.sort_by_key(|&i| self.units[i].deployment_row());
+
+        // Park everyone off the field first so old positions don't block the layout
+        for &i in &indices {
+            self.units[i].position = Position::new(-1000.0, -1000.0);
+        }
+
+        let centre = (zone.min_x + zone.max_x) / 2.0;
+        for &i in &indices {
+            let row = self.units[i].deployment_row();
+            let radius = self.units[i].footprint_radius();
+            let row_depth = zone.depth() / 4.0;
+
+            // Row 0 is the front, closest to the enemy
+            let y = match side {
+                BattleSide::Attacker => zone.max_y - radius - row_depth * row as f32,
+                BattleSide::Defender => zone.min_y + radius + row_depth * row as f32,
+            };
+            let margin = radius.min(zone.depth() / 2.0);
+            let y = y.clamp(zone.min_y + margin, zone.max_y - margin);
+
+            // Cavalry starts out on the wings, everyone else from the centre
+            let start = if row == 1 { zone.min_x + radius } else { centre };
+            let spot = self.find_free_spot(i, start, y, &zone, radius);
+            match spot {
+                Some(position) => self.units[i].position = position,
+                None => return Err(DeploymentError::NoRoom(self.units[i].id())),
+            }
+        }
+
+        self.deployment.set_ready(side, false);
+        Ok(())
+    }
+
+    /// Searches outwards from `start_x` along the row for the first spot
+    /// where the unit fits.
+    fn find_free_spot(&self, index: usize, start_x: f32, y: f32, zone: &DeploymentZone, radius: f32) -> Option<Position> {
+        let step = radius + AUTO_DEPLOY_SPACING;
+        let attempts = (zone.width() / step) as i32 + 1;
+
+        for attempt in 0..=attempts * 2 {
+            // 0, +1, -1, +2, -2 ... steps away from the start
+            let offset = if attempt % 2 == 0 { attempt / 2 } else { -(attempt / 2 + 1) };
+            let x = start_x + offset as f32 * step;
+            let position = Position::new(x.clamp(zone.min_x + radius, zone.max_x - radius), y);
+            if self.check_placement(index, &position).is_ok() {
+                return Some(position);
+            }
+        }
+
+        None
+    }
+
+    /// Declares a side done with deployment. Every unit has to stand in its
+    /// zone without overlapping, and once both sides are ready the battle
+    /// moves on to combat.
+    pub fn mark_ready(&mut self, side: BattleSide) -> Result<(), DeploymentError> {
+        if !matches!(self.battle.phase, BattlePhase::Deployment) {
+            return Err(DeploymentError::NotDeploying);
+        }
+
+        for i in (0..self.units.len()).filter(|&i| self.units[i].side == side) {
+            let position = self.units[i].position.clone();
+            self.check_placement(i, &position)?;
+        }
+
+        self.deployment.set_ready(side, true);
+        if self.deployment.attacker_ready && self.deployment.defender_ready {
+            self.battle.phase = BattlePhase::Combat;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn field(terrain_effects: Vec<TerrainEffect>) -> Battlefield {
+        Battlefield {
+            width: DEFAULT_BATTLEFIELD_WIDTH,
+            height: DEFAULT_BATTLEFIELD_HEIGHT,
+            terrain_effects,
+            walls: Vec::new(),
+            heightmap: Heightmap::default(),
+        }
+    }
+
+    fn effect(y: f32, radius: f32, effect_type: TerrainEffectType) -> TerrainEffect {
+        TerrainEffect {
+            position: Position::new(600.0, y),
+            radius,
+            effect_type,
+        }
+    }
+
+    /// A battle still in deployment, on open ground.
+    fn deploying(seed: u64) -> BattleSimulation {
+        let mut simulation = open_field(seed);
+        simulation.battle.phase = BattlePhase::Deployment;
+        simulation
+    }
+
+    #[test]
+    fn zones_stop_at_rivers_and_take_in_fortifications() {
+        let open = field(Vec::new());
+        let attacker = open.deployment_zone(BattleSide::Attacker);
+        let defender = open.deployment_zone(BattleSide::Defender);
+        assert_eq!((attacker.min_y, attacker.max_y), (0.0, 160.0));
+        assert_eq!((defender.min_y, defender.max_y), (640.0, 800.0));
+        assert_eq!((attacker.min_x, attacker.max_x), (0.0, 1200.0));
+
+        let river = TerrainEffectType::River { movement_penalty: 0.5 };
+        let behind_river = field(vec![effect(100.0, 20.0, river.clone())]);
+        assert_eq!(behind_river.deployment_zone(BattleSide::Attacker).max_y, 80.0);
+        let at_the_edge = field(vec![effect(30.0, 20.0, river)]);
+        assert_eq!(at_the_edge.deployment_zone(BattleSide::Attacker).max_y, 40.0);
+
+        let walls = TerrainEffectType::Fortification {
+            defense_bonus: 0.5,
+            attack_bonus: 0.0,
+        };
+        let fortified = field(vec![effect(500.0, 50.0, walls)]);
+        let defender = fortified.deployment_zone(BattleSide::Defender);
+        assert_eq!((defender.min_y, defender.max_y), (450.0, 800.0));
+        assert_eq!(fortified.deployment_zone(BattleSide::Attacker).max_y, 160.0);
+    }
+
+    #[test]
+    fn units_are_placed_only_inside_their_zone_and_apart() {
+        let mut simulation = deploying(1);
+        let first = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 100, 300.0, 80.0);
+        let second = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 100, 600.0, 80.0);
+
+        assert_eq!(
+            simulation.place_unit(second, Position::new(600.0, 400.0)),
+            Err(DeploymentError::OutsideZone(second))
+        );
+        assert_eq!(
+            simulation.place_unit(second, Position::new(310.0, 80.0)),
+            Err(DeploymentError::Overlapping(second, first))
+        );
+        let stranger = UnitId::default();
+        assert_eq!(
+            simulation.place_unit(stranger, Position::new(600.0, 80.0)),
+            Err(DeploymentError::UnknownUnit(stranger))
+        );
+
+        simulation.place_unit(second, Position::new(700.0, 120.0)).unwrap();
+        assert_eq!(simulation.unit(second).unwrap().position.x, 700.0);
+
+        simulation.battle.phase = BattlePhase::Combat;
+        assert_eq!(
+            simulation.place_unit(second, Position::new(800.0, 120.0)),
+            Err(DeploymentError::NotDeploying)
+        );
+    }
+
+    #[test]
+    fn both_sides_ready_starts_the_fight() {
+        let mut simulation = deploying(2);
+        for side in [BattleSide::Attacker, BattleSide::Defender] {
+            for unit_type in [UnitType::Artillery, UnitType::Archers, UnitType::Cavalry, UnitType::Infantry] {
+                place(&mut simulation, side, unit_type, 40, 0.0, 0.0);
+            }
+        }
+        assert!(simulation.mark_ready(BattleSide::Attacker).is_err());
+
+        for side in [BattleSide::Attacker, BattleSide::Defender] {
+            simulation.auto_deploy(side).unwrap();
+            let zone = simulation.battle.battlefield.deployment_zone(side);
+            assert!(simulation.units.iter().filter(|u| u.side == side).all(|u| zone.contains(&u.position)));
+        }
+
+        simulation.mark_ready(BattleSide::Attacker).unwrap();
+        assert!(matches!(simulation.battle.phase, BattlePhase::Deployment));
+        simulation.mark_ready(BattleSide::Defender).unwrap();
+        assert!(matches!(simulation.battle.phase, BattlePhase::Combat));
+        assert_eq!(simulation.mark_ready(BattleSide::Attacker), Err(DeploymentError::NotDeploying));
+    }
+
+    #[test]
+    fn moving_a_unit_takes_back_the_ready() {
+        let mut simulation = deploying(3);
+        let id = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 40, 300.0, 80.0);
+        simulation.mark_ready(BattleSide::Attacker).unwrap();
+        assert!(simulation.deployment.is_ready(BattleSide::Attacker));
+
+        simulation.place_unit(id, Position::new(400.0, 80.0)).unwrap();
+        assert!(!simulation.deployment.is_ready(BattleSide::Attacker));
+    }
+
+    #[test]
+    fn rows_come_from_the_unit_definitions() {
+        let registry = UnitRegistry::from_ron_str(include_str!("../../../assets/units/units.ron")).unwrap();
+        let mut simulation = deploying(4);
+        let faction = simulation.battle.attacker;
+        let mut add = |unit_type: UnitType| {
+            let battle_unit = BattleUnit::from_registry(
+                &registry,
+                unit(unit_type, faction, 40),
+                BattleSide::Attacker,
+                Position::new(0.0, 0.0),
+            )
+            .unwrap();
+            let id = battle_unit.id();
+            simulation.add_unit(battle_unit);
+            id
+        };
+        let infantry = add(UnitType::Infantry);
+        let cavalry = add(UnitType::Cavalry);
+        let sipahi = add(UnitType::Special("Sipahi".to_string()));
+        let janissary = add(UnitType::Special("Janissary".to_string()));
+        let archers = add(UnitType::Archers);
+        let artillery = add(UnitType::Artillery);
+
+        let rows: Vec<u32> = [infantry, cavalry, sipahi, janissary, archers, artillery]
+            .iter()
+            .map(|&id| simulation.unit(id).unwrap().deployment_row())
+            .collect();
+        assert_eq!(rows, vec![0, 1, 1, 2, 2, 3]);
+
+        simulation.auto_deploy(BattleSide::Attacker).unwrap();
+        let y = |id: UnitId| simulation.unit(id).unwrap().position.y;
+        assert_eq!(y(sipahi), y(cavalry));
+        assert!(y(sipahi) < y(infantry));
+    }
+}
@@ -0,0 +1,348 @@
+use crate::*;
+
+// ============================================================================
+// ELEVATION AND LINE OF SIGHT
+// ============================================================================
+
+/// Distance between two neighbouring points of a heightmap.
+pub const HEIGHTMAP_CELL_SIZE: f32 = 25.0;
+
+/// Sizes of the broad swells and of the smaller bumps laid over them.
+const BROAD_FEATURE_SIZE: f32 = 400.0;
+const FINE_FEATURE_SIZE: f32 = 120.0;
+
+/// A hill rises this high, plus this much per point of the defense bonus of
+/// its `TerrainEffectType::HighGround` zone, and its slopes run out to this
+/// many times the zone's radius.
+const HILL_BASE_HEIGHT: f32 = 8.0;
+const HILL_HEIGHT_PER_BONUS: f32 = 50.0;
+const HILL_SPREAD: f32 = 1.5;
+
+/// Rivers run a little below the ground around them.
+const RIVER_DEPTH: f32 = 3.0;
+
+/// How far above the ground a unit looks from, and can be seen at.
+const EYE_HEIGHT: f32 = 3.0;
+
+/// Every unit of height a shooter stands above its target adds these shares
+/// to its range and accuracy, up to the caps. Shooting uphill takes them
+/// away again, down to the floors.
+const RANGE_PER_HEIGHT: f32 = 0.01;
+const MAX_RANGE_BONUS: f32 = 0.3;
+const MAX_RANGE_PENALTY: f32 = 0.2;
+const ACCURACY_PER_HEIGHT: f32 = 0.005;
+const MAX_ACCURACY_BONUS: f32 = 0.15;
+
+/// Defense bonus per unit of height a defender stands above whoever is
+/// attacking it. Being attacked from above costs defense the same way.
+const DEFENSE_PER_HEIGHT: f32 = 0.01;
+const MAX_DEFENSE_BONUS: f32 = 0.3;
+const MAX_DEFENSE_PENALTY: f32 = 0.1;
+
+/// Share of speed lost per unit of climb per unit of ground covered, or
+/// gained going downhill. Cavalry coming down a slope builds up its charge
+/// sooner and hits harder.
+const SLOPE_SPEED: f32 = 1.5;
+const MIN_SLOPE_SPEED: f32 = 0.5;
+const MAX_SLOPE_SPEED: f32 = 1.3;
+
+/// Height of the ground over a battlefield, sampled on a regular grid and
+/// interpolated in between. An empty heightmap is flat.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Heightmap {
+    pub cell_size: f32,
+    pub columns: usize,
+    pub rows: usize,
+    pub heights: Vec<f32>, // Row by row, from the low y edge
+}
+
+impl Heightmap {
+    /// Ground for a battle in `terrain`: Mountains are rugged, Plains and
+    /// Swamps hardly rise at all. Every `HighGround` zone in `features`
+    /// becomes a hill and rivers run through shallow beds.
+    pub fn generate(terrain: &TerrainType, width: f32, height: f32, features: &[TerrainEffect], rng: &mut BattleRng) -> Self {
+        let (relief, ruggedness) = Self::relief(terrain);
+        let broad = Self::noise(width, height, BROAD_FEATURE_SIZE, rng);
+        let fine = Self::noise(width, height, FINE_FEATURE_SIZE, rng);
+
+        let mut heightmap = Self::grid(width, height, HEIGHTMAP_CELL_SIZE);
+        for row in 0..heightmap.rows {
+            for column in 0..heightmap.columns {
+                let point = Position::new(column as f32 * HEIGHTMAP_CELL_SIZE, row as f32 * HEIGHTMAP_CELL_SIZE);
+                let mut ground = broad.height_at(&point) * relief + fine.height_at(&point) * ruggedness;
+
+                let mut in_river = false;
+                for feature in features {
+                    match feature.effect_type {
+                        TerrainEffectType::HighGround { defense_bonus } => {
+                            let spread = feature.radius * HILL_SPREAD;
+                            let falloff = (1.0 - (feature.position.distance_to(&point) / spread).powi(2)).max(0.0);
+                            ground += (HILL_BASE_HEIGHT + HILL_HEIGHT_PER_BONUS * defense_bonus) * falloff * falloff;
+                        }
+                        TerrainEffectType::River { .. } => in_river |= feature.contains(&point),
+                        _ => {}
+                    }
+                }
+                if in_river {
+                    ground -= RIVER_DEPTH;
+                }
+
+                heightmap.heights[row * heightmap.columns + column] = ground;
+            }
+        }
+        heightmap
+    }
+
+    /// Heights of the broad swells and of the bumps on top of them.
+    fn relief(terrain: &TerrainType) -> (f32, f32) {
+        match terrain {
+            TerrainType::Plains => (2.0, 0.5),
+            TerrainType::Forest => (3.0, 1.0),
+            TerrainType::Mountains => (20.0, 6.0),
+            TerrainType::Desert => (5.0, 2.0), // Dunes
+            TerrainType::Swamp => (0.5, 0.25),
+            TerrainType::Coast => (2.5, 1.0),
+        }
+    }
+
+    fn grid(width: f32, height: f32, cell_size: f32) -> Self {
+        let columns = (width / cell_size).ceil() as usize + 1;
+        let rows = (height / cell_size).ceil() as usize + 1;
+        Self {
+            cell_size,
+            columns,
+            rows,
+            heights: vec![0.0; columns * rows],
+        }
+    }
+
+    /// Random heights between -1 and 1 every `spacing`, which `height_at`
+    /// turns into smooth rises and dips.
+    fn noise(width: f32, height: f32, spacing: f32, rng: &mut BattleRng) -> Self {
+        let mut noise = Self::grid(width, height, spacing);
+        for value in noise.heights.iter_mut() {
+            *value = rng.range_f32(-1.0, 1.0);
+        }
+        noise
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.heights.is_empty()
+    }
+
+    /// Height of the ground at `position`. Positions off the map take the
+    /// height of the nearest edge.
+    pub fn height_at(&self, position: &Position) -> f32 {
+        if self.is_flat() {
+            return 0.0;
+        }
+
+        let x = (position.x / self.cell_size).clamp(0.0, (self.columns - 1) as f32);
+        let y = (position.y / self.cell_size).clamp(0.0, (self.rows - 1) as f32);
+        let (column, row) = ((x as usize).min(self.columns - 1), (y as usize).min(self.rows - 1));
+        let (next_column, next_row) = ((column + 1).min(self.columns - 1), (row + 1).min(self.rows - 1));
+
+        // Smoothstep in between the points, so slopes don't kink at them
+        let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+        let (tx, ty) = (smooth(x - column as f32), smooth(y - row as f32));
+        let at = |column: usize, row: usize| self.heights[row * self.columns + column];
+        let low = at(column, row) + (at(next_column, row) - at(column, row)) * tx;
+        let high = at(column, next_row) + (at(next_column, next_row) - at(column, next_row)) * tx;
+        low + (high - low) * ty
+    }
+
+    /// True when nothing rises between the two points high enough to hide
+    /// someone standing at one from someone standing at the other.
+    pub fn line_of_sight(&self, from: &Position, to: &Position) -> bool {
+        if self.is_flat() {
+            return true;
+        }
+
+        let steps = (from.distance_to(to) / (self.cell_size * 0.5)).ceil() as usize;
+        let eye = self.height_at(from) + EYE_HEIGHT;
+        let target = self.height_at(to) + EYE_HEIGHT;
+        (1..steps).all(|step| {
+            let t = step as f32 / steps as f32;
+            let point = Position::new(from.x + (to.x - from.x) * t, from.y + (to.y - from.y) * t);
+            self.height_at(&point) <= eye + (target - eye) * t
+        })
+    }
+}
+
+impl Battlefield {
+    pub fn height_at(&self, position: &Position) -> f32 {
+        self.heightmap.height_at(position)
+    }
+
+    pub fn line_of_sight(&self, from: &Position, to: &Position) -> bool {
+        self.heightmap.line_of_sight(from, to)
+    }
+
+    /// How much higher `from` stands than `to`.
+    pub fn height_advantage(&self, from: &Position, to: &Position) -> f32 {
+        self.height_at(from) - self.height_at(to)
+    }
+
+    /// Multiplier on the range of a shooter at `from` aiming at `to`.
+    pub fn elevation_range_multiplier(&self, from: &Position, to: &Position) -> f32 {
+        1.0 + (self.height_advantage(from, to) * RANGE_PER_HEIGHT).clamp(-MAX_RANGE_PENALTY, MAX_RANGE_BONUS)
+    }
+
+    /// Multiplier on the accuracy of a shooter at `from` aiming at `to`.
+    pub fn elevation_accuracy_multiplier(&self, from: &Position, to: &Position) -> f32 {
+        1.0 + (self.height_advantage(from, to) * ACCURACY_PER_HEIGHT).clamp(-MAX_ACCURACY_BONUS, MAX_ACCURACY_BONUS)
+    }
+
+    /// Defense bonus of a unit at `defender` attacked from `attacker`.
+    pub fn elevation_defense_bonus(&self, attacker: &Position, defender: &Position) -> f32 {
+        (self.height_advantage(defender, attacker) * DEFENSE_PER_HEIGHT).clamp(-MAX_DEFENSE_PENALTY, MAX_DEFENSE_BONUS)
+    }
+
+    /// Multiplier on the speed of a unit going from `from` towards `to`,
+    /// from how steeply the ground rises or falls under its first step.
+    pub fn slope_speed_multiplier(&self, from: &Position, to: &Position) -> f32 {
+        let distance = from.distance_to(to);
+        if self.heightmap.is_flat() || distance <= 0.0 {
+            return 1.0;
+        }
+
+        let step = distance.min(self.heightmap.cell_size * 0.5);
+        let ahead = Position::new(
+            from.x + (to.x - from.x) * step / distance,
+            from.y + (to.y - from.y) * step / distance,
+        );
+        let gradient = self.height_advantage(&ahead, from) / step;
+        (1.0 - gradient * SLOPE_SPEED).clamp(MIN_SLOPE_SPEED, MAX_SLOPE_SPEED)
+    }
+
+    fn diagonal(&self) -> f32 {
+        (self.width * self.width + self.height * self.height).sqrt()
+    }
+}
+
+/// How many of the closest friendly units get a look at an enemy before it
+/// counts as unseen by their side.
+const SPOTTERS: usize = 4;
+
+impl BattleSimulation {
+    /// True when the unit can make out someone standing at `position`: it
+    /// is close enough for the weather and light, and no ridge is in the
+    /// way.
+    pub fn can_see(&self, index: usize, position: &Position) -> bool {
+        let unit = &self.units[index];
+        unit.position.distance_to(position) <= self.conditions().visibility
+            && self.battle.battlefield.line_of_sight(&unit.position, position)
+    }
+
+    /// True when any of the units of `side` nearest to `position` can see
+    /// it. What one unit sees, its side knows about.
+    pub fn spotted_by(&self, side: BattleSide, position: &Position) -> bool {
+        let battlefield = &self.battle.battlefield;
+        if battlefield.heightmap.is_flat() && self.conditions().visibility >= battlefield.diagonal() {
+            return true;
+        }
+
+        self.nearest_units(side, position, SPOTTERS, |u| u.is_active())
+            .into_iter()
+            .any(|i| self.can_see(i, position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A heightmap `columns` points wide with the given heights, row by row.
+    fn heightmap(cell_size: f32, columns: usize, heights: &[f32]) -> Heightmap {
+        Heightmap {
+            cell_size,
+            columns,
+            rows: heights.len() / columns,
+            heights: heights.to_vec(),
+        }
+    }
+
+    fn battlefield(heightmap: Heightmap) -> Battlefield {
+        Battlefield {
+            width: (heightmap.columns - 1) as f32 * heightmap.cell_size,
+            height: (heightmap.rows - 1) as f32 * heightmap.cell_size,
+            terrain_effects: Vec::new(),
+            walls: Vec::new(),
+            heightmap,
+        }
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn heights_are_exact_on_the_points_and_held_at_the_edges() {
+        let map = heightmap(10.0, 3, &[0.0, 4.0, 8.0, 2.0, 6.0, 10.0]);
+        let at = |x: f32, y: f32| map.height_at(&Position::new(x, y));
+
+        assert_near(at(0.0, 0.0), 0.0);
+        assert_near(at(10.0, 0.0), 4.0);
+        assert_near(at(20.0, 10.0), 10.0); // The far corner
+        assert_near(at(5.0, 0.0), 2.0);
+        assert_near(at(20.0, 5.0), 9.0);
+        assert_near(at(10.0, 5.0), 5.0);
+
+        // Off the map the nearest edge holds
+        assert_near(at(-50.0, -50.0), 0.0);
+        assert_near(at(500.0, -50.0), 8.0);
+        assert_near(at(500.0, 500.0), 10.0);
+        assert_near(at(10.0, 500.0), 6.0);
+        assert_near(at(-1.0, 5.0), 1.0);
+
+        assert_eq!(Heightmap::default().height_at(&Position::new(30.0, 30.0)), 0.0);
+    }
+
+    #[test]
+    fn a_ridge_hides_what_is_behind_it() {
+        let mut heights = vec![0.0; 9 * 3];
+        for row in 0..3 {
+            heights[row * 9 + 4] = 30.0;
+        }
+        let map = heightmap(25.0, 9, &heights);
+        let (near, ridge, far) = (Position::new(0.0, 25.0), Position::new(100.0, 25.0), Position::new(200.0, 25.0));
+
+        assert!(!map.line_of_sight(&near, &far));
+        assert!(!map.line_of_sight(&far, &near));
+        assert!(map.line_of_sight(&ridge, &far));
+        assert!(map.line_of_sight(&near, &ridge));
+        assert!(map.line_of_sight(&near, &Position::new(75.0, 25.0)));
+
+        // A bank lower than eye height doesn't get in the way
+        let bank = heightmap(25.0, 9, &heights.iter().map(|h| h / 15.0).collect::<Vec<_>>());
+        assert!(bank.line_of_sight(&near, &far));
+        assert!(Heightmap::default().line_of_sight(&near, &far));
+    }
+
+    #[test]
+    fn height_helps_the_shooter_and_the_defender_up_to_a_cap() {
+        // A step of 10 from the first point to the second, then of 90 more
+        let field = battlefield(heightmap(100.0, 3, &[0.0, 10.0, 100.0, 0.0, 10.0, 100.0]));
+        let (low, step, peak) = (Position::new(0.0, 0.0), Position::new(100.0, 0.0), Position::new(200.0, 0.0));
+
+        assert_near(field.height_advantage(&step, &low), 10.0);
+        assert_near(field.elevation_range_multiplier(&low, &low), 1.0);
+        assert_near(field.elevation_accuracy_multiplier(&low, &low), 1.0);
+        assert_near(field.elevation_defense_bonus(&low, &low), 0.0);
+
+        assert_near(field.elevation_range_multiplier(&step, &low), 1.1);
+        assert_near(field.elevation_range_multiplier(&low, &step), 0.9);
+        assert_near(field.elevation_range_multiplier(&peak, &low), 1.0 + MAX_RANGE_BONUS);
+        assert_near(field.elevation_range_multiplier(&low, &peak), 1.0 - MAX_RANGE_PENALTY);
+
+        assert_near(field.elevation_accuracy_multiplier(&step, &low), 1.05);
+        assert_near(field.elevation_accuracy_multiplier(&peak, &low), 1.0 + MAX_ACCURACY_BONUS);
+        assert_near(field.elevation_accuracy_multiplier(&low, &peak), 1.0 - MAX_ACCURACY_BONUS);
+
+        // The defender standing higher is the one that gains
+        assert_near(field.elevation_defense_bonus(&low, &step), 0.1);
+        assert_near(field.elevation_defense_bonus(&step, &low), -0.1);
+        assert_near(field.elevation_defense_bonus(&low, &peak), MAX_DEFENSE_BONUS);
+        assert_near(field.elevation_defense_bonus(&peak, &low), -MAX_DEFENSE_PENALTY);
+    }
+}
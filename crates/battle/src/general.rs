@@ -0,0 +1,310 @@
+use crate::*;
+
+// ============================================================================
+// GENERALS
+// ============================================================================
+
+pub const MAX_SKILL_LEVEL: u32 = 10;
+const SKILL_BONUS_PER_LEVEL: f32 = 0.03;
+
+/// Units within this distance of their general fight under their command.
+const BASE_AURA_RADIUS: f32 = 100.0;
+const AURA_RADIUS_PER_LEADERSHIP: f32 = 20.0;
+
+/// Chance per man lost in the general's own unit that the general falls with them,
+/// scaled by the share of the unit that was lost.
+const GENERAL_CASUALTY_RISK: f32 = 0.15;
+
+/// Chance that the general of a broken army is caught in the rout.
+const GENERAL_CAPTURE_CHANCE: f32 = 0.5;
+
+/// Friendly units this close see it when their general falls or is taken.
+const GENERAL_LOSS_SHOCK_RADIUS: f32 = 300.0;
+const GENERAL_KILLED_MORALE_LOSS: f32 = 20.0;
+const GENERAL_CAPTURED_MORALE_LOSS: f32 = 12.0;
+
+/// A general picks up a new trait on these victories.
+const TRAIT_MILESTONES: [u32; 4] = [1, 3, 6, 10];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GeneralTrait {
+    Aggressive, // Won attacking
+    Stalwart,   // Won defending
+    Inspiring,  // Won while outnumbered
+    Tactician,  // Won cheaply
+}
+
+impl GeneralTrait {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GeneralTrait::Aggressive => "Aggressive",
+            GeneralTrait::Stalwart => "Stalwart",
+            GeneralTrait::Inspiring => "Inspiring",
+            GeneralTrait::Tactician => "Tactician",
+        }
+    }
+
+    fn apply(&self, bonuses: &mut GeneralBonuses) {
+        match self {
+            GeneralTrait::Aggressive => {
+                bonuses.attack_multiplier += 0.1;
+                bonuses.defense_multiplier -= 0.05;
+            }
+            GeneralTrait::Stalwart => bonuses.defense_multiplier += 0.1,
+            GeneralTrait::Inspiring => {
+                bonuses.morale_multiplier += 0.1;
+                bonuses.aura_radius += 50.0;
+            }
+            GeneralTrait::Tactician => {
+                bonuses.attack_multiplier += 0.05;
+                bonuses.defense_multiplier += 0.05;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GeneralSkills {
+    pub attack: u32,
+    pub defense: u32,
+    pub leadership: u32, // Widens the morale aura and steadies the men in it
+    pub movement: u32,   // Faster army marches on the world map
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GeneralStatus {
+    Active,
+    Killed,
+    Captured(FactionId),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct General {
+    pub id: GeneralId,
+    pub name: String,
+    pub faction: FactionId,
+    pub skills: GeneralSkills,
+    pub traits: Vec<GeneralTrait>,
+    pub victories: u32,
+    pub status: GeneralStatus,
+}
+
+#[derive(Debug, Clone)]
+pub struct GeneralBonuses {
+    pub attack_multiplier: f32,
+    pub defense_multiplier: f32,
+    pub morale_multiplier: f32, // Divides morale losses of units in the aura
+    pub aura_radius: f32,
+    pub movement_multiplier: f32,
+}
+
+/// What a general did to win, used to decide what the general learns from it.
+#[derive(Debug, Clone)]
+pub struct VictoryRecord {
+    pub side: BattleSide,
+    pub outnumbered: bool,
+    pub casualty_ratio: f32, // Own losses divided by the enemy's
+}
+
+impl General {
+    pub fn new(name: String, faction: FactionId) -> Self {
+        Self {
+            id: GeneralId::default(),
+            name,
+            faction,
+            skills: GeneralSkills::default(),
+            traits: Vec::new(),
+            victories: 0,
+            status: GeneralStatus::Active,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status == GeneralStatus::Active
+    }
+
+    pub fn has_trait(&self, general_trait: GeneralTrait) -> bool {
+        self.traits.contains(&general_trait)
+    }
+
+    pub fn bonuses(&self) -> GeneralBonuses {
+        let skill = |level: u32| 1.0 + level.min(MAX_SKILL_LEVEL) as f32 * SKILL_BONUS_PER_LEVEL;
+        let mut bonuses = GeneralBonuses {
+            attack_multiplier: skill(self.skills.attack),
+            defense_multiplier: skill(self.skills.defense),
+            morale_multiplier: skill(self.skills.leadership),
+            aura_radius: BASE_AURA_RADIUS + AURA_RADIUS_PER_LEADERSHIP * self.skills.leadership as f32,
+            movement_multiplier: skill(self.skills.movement),
+        };
+
+        for general_trait in &self.traits {
+            general_trait.apply(&mut bonuses);
+        }
+        bonuses
+    }
+
+    /// Counts a won battle: the skill used improves, and some
+    /// victories bring a trait that fits how the battle was won. Returns the new
+    /// trait, if any.
+    pub fn record_victory(&mut self, record: &VictoryRecord) -> Option<GeneralTrait> {
+        self.victories += 1;
+
+        let skill = match record.side {
+            BattleSide::Attacker => &mut self.skills.attack,
+            BattleSide::Defender => &mut self.skills.defense,
+        };
+        *skill = (*skill + 1).min(MAX_SKILL_LEVEL);
+
+        if !TRAIT_MILESTONES.contains(&self.victories) {
+            return None;
+        }
+
+        let mut candidates = Vec::new();
+        if record.outnumbered {
+            candidates.push(GeneralTrait::Inspiring);
+        }
+        if record.casualty_ratio < 0.5 {
+            candidates.push(GeneralTrait::Tactician);
+        }
+        candidates.push(match record.side {
+            BattleSide::Attacker => GeneralTrait::Aggressive,
+            BattleSide::Defender => GeneralTrait::Stalwart,
+        });
+
+        let new_trait = candidates.into_iter().find(|t| !self.has_trait(*t))?;
+        self.traits.push(new_trait);
+        Some(new_trait)
+    }
+
+    /// Rolls whether the general falls when the army loses `loss_share` of
+    /// its men at once. Used by auto-resolve.
+    pub(crate) fn roll_casualty(&mut self, loss_share: f32, rng: &mut BattleRng) {
+        if self.is_active() && rng.chance(GENERAL_CASUALTY_RISK * loss_share) {
+            self.status = GeneralStatus::Killed;
+        }
+    }
+
+    /// Rolls whether the general of a broken army is taken by `captor`.
+    pub(crate) fn roll_capture(&mut self, captor: FactionId, rng: &mut BattleRng) {
+        if self.is_active() && rng.chance(GENERAL_CAPTURE_CHANCE) {
+            self.status = GeneralStatus::Captured(captor);
+        }
+    }
+}
+
+impl CombatSystem {
+    /// Combat stats of a unit fighting under a general's command.
+    pub fn commanded_stats(stats: &CombatStats, bonuses: &GeneralBonuses) -> CombatStats {
+        CombatStats {
+            attack: stats.attack * bonuses.attack_multiplier,
+            defense: stats.defense * bonuses.defense_multiplier,
+            ..stats.clone()
+        }
+    }
+}
+
+impl BattleUnit {
+    pub fn attach_general(&mut self, general: General) {
+        self.general = Some(general);
+    }
+
+    pub fn active_general(&self) -> Option<&General> {
+        self.general.as_ref().filter(|g| g.is_active())
+    }
+}
+
+impl BattleSimulation {
+    /// Bonuses of the closest active general of the unit's side whose aura
+    /// reaches it.
+    pub fn commander_bonuses(&self, index: usize) -> Option<GeneralBonuses> {
+        let unit = &self.units[index];
+
+        self.commanders()
+            .map(|i| &self.units[i])
+            .filter(|u| u.side == unit.side && u.is_active())
+            .filter_map(|u| u.active_general().map(|g| (u.position.distance_to(&unit.position), g.bonuses())))
+            .filter(|(distance, bonuses)| *distance <= bonuses.aura_radius)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, bonuses)| bonuses)
+    }
+
+    /// Stats a unit fights with right now: worn down by tiredness and
+    /// disorder and lifted by any general commanding it.
+    pub(crate) fn commanded_stats(&self, index: usize) -> CombatStats {
+        let unit = &self.units[index];
+        let stats = unit.disordered_stats(&unit.tired_stats());
+        match self.commander_bonuses(index) {
+            Some(bonuses) => CombatSystem::commanded_stats(&stats, &bonuses),
+            None => stats,
+        }
+    }
+
+    /// Multiplier steadying a unit's morale: its veterancy and any general
+    /// commanding it.
+    pub(crate) fn steadiness(&self, index: usize) -> f32 {
+        let unit = &self.units[index];
+        let command = self.commander_bonuses(index).map_or(1.0, |b| b.morale_multiplier);
+        unit.unit.rank().bonuses().morale_multiplier * command
+    }
+
+    /// Rolls whether a general went down with the men their unit just lost.
+    /// A general whose unit is wiped out while running away is taken
+    /// prisoner instead.
+    pub(crate) fn check_general_casualty(&mut self, index: usize, killed: u32, count_before: u32) {
+        let unit = &self.units[index];
+        if unit.active_general().is_none() || killed == 0 {
+            return;
+        }
+
+        let captor = self.battle.leader(unit.side.opponent());
+        let status = if !unit.is_alive() && unit.state == UnitState::Routing {
+            Some(GeneralStatus::Captured(captor))
+        } else if !unit.is_alive() {
+            Some(GeneralStatus::Killed)
+        } else {
+            let risk = GENERAL_CASUALTY_RISK * killed as f32 / count_before.max(1) as f32;
+            self.rng.chance(risk).then_some(GeneralStatus::Killed)
+        };
+        let Some(status) = status else {
+            return;
+        };
+
+        let unit = &mut self.units[index];
+        if let Some(general) = unit.general.as_mut() {
+            general.status = status;
+        }
+
+        let side = unit.side;
+        let position = unit.position.clone();
+        let morale_loss = match status {
+            GeneralStatus::Captured(_) => GENERAL_CAPTURED_MORALE_LOSS,
+            _ => GENERAL_KILLED_MORALE_LOSS,
+        };
+        self.morale_shock(side, &position, GENERAL_LOSS_SHOCK_RADIUS, morale_loss);
+    }
+
+    /// Generals on the winning side who are still in command learn from it.
+    pub(crate) fn reward_generals(&mut self, winner: Option<BattleSide>) {
+        let Some(winner) = winner else {
+            return;
+        };
+
+        let own_men = self.side_initial_count(winner);
+        let enemy_men = self.side_initial_count(winner.opponent());
+        let record = VictoryRecord {
+            side: winner,
+            outnumbered: own_men < enemy_men,
+            casualty_ratio: self.side_casualties(winner) as f32 / self.side_casualties(winner.opponent()).max(1) as f32,
+        };
+
+        for unit in self.units.iter_mut().filter(|u| u.side == winner) {
+            if let Some(general) = unit.general.as_mut().filter(|g| g.is_active()) {
+                general.record_victory(&record);
+            }
+        }
+    }
+
+    fn side_initial_count(&self, side: BattleSide) -> u32 {
+        self.units.iter().filter(|u| u.side == side).map(|u| u.initial_count).sum()
+    }
+}
@@ -1,7 +1,58 @@
 use bevy::prelude::*;
 use dot_wars_core::*;
+use dot_wars_world::{BuildingType, Province, Season, TerrainType, WorldCalendar, FORT_DEFENSE_BONUS_PER_LEVEL};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+
+mod alliance;
+mod auto_resolve;
+mod charge;
+mod clock;
+mod cohesion;
+mod commands;
+mod deployment;
+mod elevation;
+mod general;
+mod morale;
+mod prisoners;
+mod registry;
+mod reinforcement;
+mod replay;
+mod selection;
+mod siege;
+mod simulation;
+mod spatial;
+mod stamina;
+mod tactics;
+#[cfg(test)]
+mod testing;
+mod veterancy;
+mod victory;
+mod weather;
+
+pub use alliance::*;
+pub use auto_resolve::*;
+pub use charge::*;
+pub use clock::*;
+pub use cohesion::*;
+pub use commands::*;
+pub use deployment::*;
+pub use elevation::*;
+pub use general::*;
+pub use morale::*;
+pub use prisoners::*;
+pub use registry::*;
+pub use reinforcement::*;
+pub use replay::*;
+pub use selection::*;
+pub use siege::*;
+pub use simulation::*;
+pub use spatial::*;
+pub use stamina::*;
+pub use tactics::*;
+pub use veterancy::*;
+pub use victory::*;
+pub use weather::*;
 
 // ============================================================================
 // UNIT SYSTEM
@@ -19,7 +70,49 @@ pub struct Unit {
     pub faction: FactionId,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Unit {
+    pub fn rank(&self) -> VeterancyRank {
+        VeterancyRank::from_experience(self.experience)
+    }
+
+    pub fn gain_experience(&mut self, amount: u32) {
+        self.experience = self.experience.saturating_add(amount);
+    }
+
+    /// Fills the unit back up with fresh recruits, up to `max_count`. The
+    /// veterans' experience is shared out over the new, larger unit.
+    /// Returns how many men were actually added.
+    pub fn add_replacements(&mut self, replacements: u32) -> u32 {
+        let added = replacements.min(self.max_count.saturating_sub(self.count));
+        if added == 0 {
+            return 0;
+        }
+
+        let new_count = self.count + added;
+        self.experience = (self.experience as u64 * self.count as u64 / new_count as u64) as u32;
+        self.count = new_count;
+        added
+    }
+
+    pub fn tooltip_text(&self) -> String {
+        let rank = self.rank();
+        let mut text = format!(
+            "{} ({})\nMen: {}/{}\nMorale: {:.0}\nExperience: {}",
+            self.unit_type.name(),
+            rank.name(),
+            self.count,
+            self.max_count,
+            self.morale,
+            self.experience,
+        );
+        if let Some(next) = rank.next_threshold() {
+            text.push_str(&format!(" / {}", next));
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UnitType {
     Infantry,
     Cavalry,
@@ -29,6 +122,8 @@ pub enum UnitType {
 }
 
 impl UnitType {
+    /// Built-in stats. These are only the defaults, the stats used in game
+    /// come from the `UnitRegistry` loaded from `assets/units/units.ron`.
     pub fn stats(&self) -> UnitStats {
         match self {
             UnitType::Infantry => UnitStats {
@@ -37,6 +132,10 @@ impl UnitType {
                 health: 100,
                 speed: 50.0,
                 range: 1.0,
+                min_range: 0.0,
+                accuracy: 0.7,
+                evasion: 0.1,
+                ammunition: 0,
                 cost: Resources { gold: 100, manpower: 10, ..Default::default() },
             },
             UnitType::Cavalry => UnitStats {
@@ -45,6 +144,10 @@ impl UnitType {
                 health: 120,
                 speed: 100.0,
                 range: 1.0,
+                min_range: 0.0,
+                accuracy: 0.7,
+                evasion: 0.2,
+                ammunition: 0,
                 cost: Resources { gold: 200, manpower: 15, ..Default::default() },
             },
             UnitType::Archers => UnitStats {
@@ -53,6 +156,10 @@ impl UnitType {
                 health: 80,
                 speed: 40.0,
                 range: 150.0,
+                min_range: 0.0,
+                accuracy: 0.6,
+                evasion: 0.15,
+                ammunition: 30,
                 cost: Resources { gold: 120, manpower: 12, ..Default::default() },
             },
             UnitType::Artillery => UnitStats {
@@ -61,6 +168,10 @@ impl UnitType {
                 health: 60,
                 speed: 20.0,
                 range: 300.0,
+                min_range: 60.0,
+                accuracy: 0.4,
+                evasion: 0.05,
+                ammunition: 20,
                 cost: Resources { gold: 500, materials: 100, manpower: 8, ..Default::default() },
             },
             UnitType::Special(_) => UnitStats {
@@ -69,12 +180,34 @@ impl UnitType {
                 health: 150,
                 speed: 60.0,
                 range: 50.0,
+                min_range: 0.0,
+                accuracy: 0.75,
+                evasion: 0.2,
+                ammunition: 0,
                 cost: Resources { gold: 800, manpower: 25, ..Default::default() },
             },
         }
     }
+
+    /// Key of this unit in the `UnitRegistry`.
+    pub fn id(&self) -> &str {
+        self.name()
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            UnitType::Infantry => "Infantry",
+            UnitType::Cavalry => "Cavalry",
+            UnitType::Archers => "Archers",
+            UnitType::Artillery => "Artillery",
+            UnitType::Special(name) => name,
+        }
+    }
 }
 
+/// Units with a longer range than this fight with missiles instead of melee.
+pub const MELEE_RANGE: f32 = 50.0;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnitStats {
     pub attack: u32,
@@ -82,6 +215,10 @@ pub struct UnitStats {
     pub health: u32,
     pub speed: f32,
     pub range: f32,
+    pub min_range: f32,
+    pub accuracy: f32, // 0.0 to 1.0, base chance to hit with a ranged volley
+    pub evasion: f32, // 0.0 to 1.0, chance to dodge an otherwise accurate volley
+    pub ammunition: u32, // Volleys carried into battle, 0 for melee units
     pub cost: Resources,
 }
 
@@ -89,7 +226,7 @@ pub struct UnitStats {
 // FORMATION SYSTEM
 // ============================================================================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Formation {
     Line,
     Column,
@@ -143,6 +280,93 @@ pub struct FormationModifiers {
     pub morale_modifier: f32,
 }
 
+impl Formation {
+    /// How badly an attack from `direction` hurts a unit in this formation.
+    /// A square faces outwards on every side, so it has no flanks or rear.
+    pub fn flank_modifiers(&self, direction: AttackDirection) -> FlankModifiers {
+        let direction = match self {
+            Formation::Square => AttackDirection::Front,
+            _ => direction,
+        };
+
+        match (self, direction) {
+            (_, AttackDirection::Front) => FlankModifiers {
+                damage_multiplier: 1.0,
+                morale_loss: 0.0,
+            },
+            (Formation::Line, AttackDirection::Flank) => FlankModifiers {
+                damage_multiplier: 1.6,
+                morale_loss: 6.0,
+            },
+            (Formation::Skirmish, AttackDirection::Flank) => FlankModifiers {
+                damage_multiplier: 1.1,
+                morale_loss: 2.0,
+            },
+            (_, AttackDirection::Flank) => FlankModifiers {
+                damage_multiplier: 1.3,
+                morale_loss: 4.0,
+            },
+            (Formation::Skirmish, AttackDirection::Rear) => FlankModifiers {
+                damage_multiplier: 1.4,
+                morale_loss: 5.0,
+            },
+            (_, AttackDirection::Rear) => FlankModifiers {
+                damage_multiplier: 2.0,
+                morale_loss: 10.0,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlankModifiers {
+    pub damage_multiplier: f32,
+    pub morale_loss: f32, // Extra morale lost by the defender per hit
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttackDirection {
+    Front,
+    Flank,
+    Rear,
+}
+
+impl AttackDirection {
+    const FRONT_ARC: f32 = std::f32::consts::FRAC_PI_3; // 60 degrees either side
+    const REAR_ARC: f32 = std::f32::consts::PI * 0.75;
+
+    /// Works out which side of the defender an attack coming from
+    /// `attacker_position` lands on, given the way the defender is facing.
+    pub fn from_positions(defender_facing: f32, defender_position: &Position, attacker_position: &Position) -> Self {
+        let towards_attacker = heading_between(defender_position, attacker_position);
+        let offset = normalize_angle(towards_attacker - defender_facing).abs();
+
+        if offset <= Self::FRONT_ARC {
+            AttackDirection::Front
+        } else if offset >= Self::REAR_ARC {
+            AttackDirection::Rear
+        } else {
+            AttackDirection::Flank
+        }
+    }
+}
+
+/// Angle in radians of the direction from `from` to `to`.
+pub fn heading_between(from: &Position, to: &Position) -> f32 {
+    (to.y - from.y).atan2(to.x - from.x)
+}
+
+/// Wraps an angle into the `-PI..=PI` range.
+pub fn normalize_angle(angle: f32) -> f32 {
+    let mut angle = angle % std::f32::consts::TAU;
+    if angle > std::f32::consts::PI {
+        angle -= std::f32::consts::TAU;
+    } else if angle < -std::f32::consts::PI {
+        angle += std::f32::consts::TAU;
+    }
+    angle
+}
+
 // ============================================================================
 // BATTLE SYSTEM
 // ============================================================================
@@ -150,13 +374,40 @@ pub struct FormationModifiers {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Battle {
     pub id: String,
-    pub attacker: FactionId,
-    pub defender: FactionId,
+    pub attacker: FactionId, // Leads the attacking side
+    pub defender: FactionId, // Leads the defending side
+    #[serde(default)]
+    pub attacker_allies: Vec<FactionId>,
+    #[serde(default)]
+    pub defender_allies: Vec<FactionId>,
     pub attacker_units: Vec<UnitId>,
     pub defender_units: Vec<UnitId>,
     pub battlefield: Battlefield,
     pub phase: BattlePhase,
     pub turn: u32,
+    #[serde(default = "victory::default_victory_conditions")]
+    pub victory_conditions: Vec<VictoryCondition>,
+    #[serde(default)]
+    pub conditions: BattleConditions,
+}
+
+impl Battle {
+    pub fn new(id: String, attacker: FactionId, defender: FactionId, battlefield: Battlefield) -> Self {
+        Self {
+            id,
+            attacker,
+            defender,
+            attacker_allies: Vec::new(),
+            defender_allies: Vec::new(),
+            attacker_units: Vec::new(),
+            defender_units: Vec::new(),
+            battlefield,
+            phase: BattlePhase::Deployment,
+            turn: 0,
+            victory_conditions: victory::default_victory_conditions(),
+            conditions: BattleConditions::default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -166,11 +417,46 @@ pub enum BattlePhase {
     Resolved,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BattleOutcome {
+    pub winner: Option<BattleSide>, // None when both sides broke at once
+    pub attacker_casualties: u32,
+    pub defender_casualties: u32,
+    pub duration: f32, // Seconds of combat
+    #[serde(default)]
+    pub factions: Vec<FactionResult>, // Every faction that fought, attackers first
+    #[serde(default)]
+    pub decided_by: Option<VictoryKind>,
+    #[serde(default)]
+    pub prisoners: Vec<PrisonerGroup>, // Counted in the casualties too
+    #[serde(default)]
+    pub captured_generals: Vec<General>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BattleSide {
+    Attacker,
+    Defender,
+}
+
+impl BattleSide {
+    pub fn opponent(&self) -> BattleSide {
+        match self {
+            BattleSide::Attacker => BattleSide::Defender,
+            BattleSide::Defender => BattleSide::Attacker,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Battlefield {
     pub width: f32,
     pub height: f32,
     pub terrain_effects: Vec<TerrainEffect>,
+    #[serde(default)]
+    pub walls: Vec<WallSegment>, // Only built for siege assaults
+    #[serde(default)]
+    pub heightmap: Heightmap,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -188,11 +474,296 @@ pub enum TerrainEffectType {
     Fortification { defense_bonus: f32, attack_bonus: f32 },
 }
 
+impl TerrainEffect {
+    pub fn contains(&self, position: &Position) -> bool {
+        self.position.distance_to(position) <= self.radius
+    }
+}
+
+/// Combined effect of every terrain zone covering a single point.
+#[derive(Debug, Clone)]
+pub struct TerrainModifiers {
+    pub defense_bonus: f32,
+    pub attack_bonus: f32,
+    pub concealment: f32,      // 0.0 to 1.0, reduces incoming ranged accuracy
+    pub movement_penalty: f32, // 0.0 to 1.0, fraction of speed lost
+}
+
+impl TerrainModifiers {
+    pub fn none() -> Self {
+        Self {
+            defense_bonus: 0.0,
+            attack_bonus: 0.0,
+            concealment: 0.0,
+            movement_penalty: 0.0,
+        }
+    }
+
+    pub fn defense_multiplier(&self) -> f32 {
+        1.0 + self.defense_bonus
+    }
+
+    pub fn attack_multiplier(&self) -> f32 {
+        1.0 + self.attack_bonus
+    }
+
+    pub fn ranged_accuracy_multiplier(&self) -> f32 {
+        (1.0 - self.concealment).max(0.0)
+    }
+
+    pub fn movement_multiplier(&self) -> f32 {
+        // Never fully stop a unit, otherwise it could get stuck in a river
+        (1.0 - self.movement_penalty).max(0.1)
+    }
+}
+
+impl Default for TerrainModifiers {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl Battlefield {
+    pub fn modifiers_at(&self, position: &Position) -> TerrainModifiers {
+        let mut modifiers = TerrainModifiers::none();
+
+        // Bonuses from overlapping zones stack, concealment and penalties don't
+        for effect in self.terrain_effects.iter().filter(|e| e.contains(position)) {
+            match effect.effect_type {
+                // Hills are part of the heightmap, and what they are worth
+                // depends on where the attack comes from
+                TerrainEffectType::HighGround { .. } => {}
+                TerrainEffectType::Forest { concealment } => {
+                    modifiers.concealment = modifiers.concealment.max(concealment);
+                }
+                TerrainEffectType::River { movement_penalty } => {
+                    modifiers.movement_penalty = modifiers.movement_penalty.max(movement_penalty);
+                }
+                TerrainEffectType::Fortification { defense_bonus, attack_bonus } => {
+                    modifiers.defense_bonus += defense_bonus;
+                    modifiers.attack_bonus += attack_bonus;
+                }
+            }
+        }
+
+        modifiers
+    }
+
+    /// Moves from `from` towards `to`, slowed down by the terrain the unit is
+    /// currently standing in and by climbing, sped up going downhill. Never
+    /// overshoots the destination.
+    pub fn move_towards(&self, from: &Position, to: &Position, speed: f32, delta_time: f32) -> Position {
+        let distance = from.distance_to(to);
+        let step = speed
+            * self.modifiers_at(from).movement_multiplier()
+            * self.slope_speed_multiplier(from, to)
+            * delta_time;
+
+        if distance <= step {
+            return to.clone();
+        }
+
+        let ratio = step / distance;
+        Position::new(
+            from.x + (to.x - from.x) * ratio,
+            from.y + (to.y - from.y) * ratio,
+        )
+    }
+
+    /// The y coordinate of the edge a side deploys on and retreats towards.
+    pub fn deployment_edge(&self, side: BattleSide) -> f32 {
+        match side {
+            BattleSide::Attacker => 0.0,
+            BattleSide::Defender => self.height,
+        }
+    }
+
+    pub fn clamp(&self, position: &Position) -> Position {
+        Position::new(
+            position.x.clamp(0.0, self.width),
+            position.y.clamp(0.0, self.height),
+        )
+    }
+}
+
+// ============================================================================
+// BATTLEFIELD GENERATION
+// ============================================================================
+
+pub const DEFAULT_BATTLEFIELD_WIDTH: f32 = 1200.0;
+pub const DEFAULT_BATTLEFIELD_HEIGHT: f32 = 800.0;
+
+pub struct BattlefieldGenerator;
+
+impl BattlefieldGenerator {
+    /// Builds a battlefield for a fight inside `province`. The attacker deploys
+    /// along the bottom edge (low y) and the defender along the top edge, so
+    /// fortifications are placed in the defender's half.
+    pub fn from_province(province: &Province, seed: u64) -> Battlefield {
+        let width = DEFAULT_BATTLEFIELD_WIDTH;
+        let height = DEFAULT_BATTLEFIELD_HEIGHT;
+        let mut rng = BattleRng::new(seed);
+        let mut terrain_effects = Self::natural_features(&province.terrain_type, width, height, &mut rng);
+        let heightmap = Heightmap::generate(&province.terrain_type, width, height, &terrain_effects, &mut rng);
+
+        for building in &province.buildings {
+            // Buildings under construction don't protect anyone yet
+            if !building.is_complete() {
+                continue;
+            }
+
+            let level = building.level as f32;
+            match building.building_type {
+                BuildingType::Fort => {
+                    // One strongpoint per level, spread along the defender's line
+                    for i in 0..building.level {
+                        let x = width * (i + 1) as f32 / (building.level + 1) as f32;
+                        terrain_effects.push(TerrainEffect {
+                            position: Position::new(x, height * 0.75),
+                            radius: 60.0,
+                            effect_type: TerrainEffectType::Fortification {
+                                defense_bonus: FORT_DEFENSE_BONUS_PER_LEVEL * level,
+                                attack_bonus: 0.1 * level,
+                            },
+                        });
+                    }
+                }
+                BuildingType::City => {
+                    terrain_effects.push(TerrainEffect {
+                        position: Position::new(width * 0.5, height * 0.85),
+                        radius: 80.0 + 20.0 * level,
+                        effect_type: TerrainEffectType::Fortification {
+                            defense_bonus: 0.1 * level,
+                            attack_bonus: 0.0,
+                        },
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Battlefield {
+            width,
+            height,
+            terrain_effects,
+            walls: Vec::new(),
+            heightmap,
+        }
+    }
+
+    fn natural_features(terrain: &TerrainType, width: f32, height: f32, rng: &mut BattleRng) -> Vec<TerrainEffect> {
+        let mut effects = Vec::new();
+
+        let (hills, forests, rivers) = match terrain {
+            TerrainType::Plains => (rng.range_u32(0, 2), rng.range_u32(0, 2), rng.range_u32(0, 2)),
+            TerrainType::Forest => (rng.range_u32(0, 2), rng.range_u32(3, 6), rng.range_u32(0, 2)),
+            TerrainType::Mountains => (rng.range_u32(3, 5), rng.range_u32(0, 2), 0),
+            TerrainType::Desert => (rng.range_u32(1, 3), 0, 0),
+            TerrainType::Swamp => (0, rng.range_u32(1, 3), rng.range_u32(2, 4)),
+            TerrainType::Coast => (rng.range_u32(0, 2), rng.range_u32(0, 2), 1),
+        };
+
+        for _ in 0..hills {
+            effects.push(TerrainEffect {
+                position: Self::random_position(width, height, rng),
+                radius: rng.range_f32(60.0, 140.0),
+                effect_type: TerrainEffectType::HighGround {
+                    defense_bonus: rng.range_f32(0.1, 0.3) + terrain.defense_bonus() * 0.5,
+                },
+            });
+        }
+
+        for _ in 0..forests {
+            effects.push(TerrainEffect {
+                position: Self::random_position(width, height, rng),
+                radius: rng.range_f32(80.0, 160.0),
+                effect_type: TerrainEffectType::Forest {
+                    concealment: rng.range_f32(0.3, 0.6),
+                },
+            });
+        }
+
+        // Rivers are approximated by a chain of overlapping circles crossing
+        // the field from left to right between the two deployment edges
+        for _ in 0..rivers {
+            let mut y = rng.range_f32(height * 0.35, height * 0.65);
+            let radius = rng.range_f32(30.0, 50.0);
+            let penalty = if matches!(terrain, TerrainType::Swamp) { 0.7 } else { 0.5 };
+            let mut x = 0.0;
+            while x <= width {
+                effects.push(TerrainEffect {
+                    position: Position::new(x, y),
+                    radius,
+                    effect_type: TerrainEffectType::River { movement_penalty: penalty },
+                });
+                x += radius;
+                y = (y + rng.range_f32(-radius, radius) * 0.5).clamp(height * 0.25, height * 0.75);
+            }
+        }
+
+        effects
+    }
+
+    fn random_position(width: f32, height: f32, rng: &mut BattleRng) -> Position {
+        Position::new(rng.range_f32(0.0, width), rng.range_f32(0.0, height))
+    }
+}
+
+// ============================================================================
+// RANDOMNESS
+// ============================================================================
+
+/// Small seeded random number generator (SplitMix64). Everything random in a
+/// battle goes through this so the same seed always gives the same battle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BattleRng {
+    state: u64,
+}
+
+impl BattleRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `[0.0, 1.0)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Uniform value in `[min, max)`.
+    pub fn range_u32(&mut self, min: u32, max: u32) -> u32 {
+        if max <= min {
+            return min;
+        }
+        min + (self.next_u64() % (max - min) as u64) as u32
+    }
+
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+}
+
 // ============================================================================
 // COMBAT SYSTEM
 // ============================================================================
 
-#[derive(Component)]
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub struct CombatStats {
     pub attack: f32,
     pub defense: f32,
@@ -200,6 +771,17 @@ pub struct CombatStats {
     pub evasion: f32,
 }
 
+impl CombatStats {
+    pub fn from_stats(stats: &UnitStats) -> Self {
+        Self {
+            attack: stats.attack as f32,
+            defense: stats.defense as f32,
+            accuracy: stats.accuracy,
+            evasion: stats.evasion,
+        }
+    }
+}
+
 pub struct CombatSystem;
 
 impl CombatSystem {
@@ -208,20 +790,46 @@ impl CombatSystem {
         attacker_stats: &CombatStats,
         defender: &Unit,
         defender_stats: &CombatStats,
+    ) -> u32 {
+        Self::calculate_damage_on_terrain(
+            attacker,
+            attacker_stats,
+            &TerrainModifiers::none(),
+            defender,
+            defender_stats,
+            &TerrainModifiers::none(),
+        )
+    }
+
+    /// Same as `calculate_damage`, but takes the terrain both units are
+    /// standing in into account (see `Battlefield::modifiers_at`).
+    pub fn calculate_damage_on_terrain(
+        attacker: &Unit,
+        attacker_stats: &CombatStats,
+        attacker_terrain: &TerrainModifiers,
+        defender: &Unit,
+        defender_stats: &CombatStats,
+        defender_terrain: &TerrainModifiers,
     ) -> u32 {
         let base_damage = attacker_stats.attack;
         let defense_reduction = defender_stats.defense;
-        
+
         // Apply formation modifiers
         let formation_mods = attacker.formation.get_modifiers();
-        let modified_attack = base_damage * formation_mods.attack_modifier;
-        
+        let modified_attack = base_damage
+            * formation_mods.attack_modifier
+            * attacker_terrain.attack_multiplier()
+            * attacker.rank().bonuses().attack_multiplier;
+
         let defender_formation_mods = defender.formation.get_modifiers();
-        let modified_defense = defense_reduction * defender_formation_mods.defense_modifier;
-        
+        let modified_defense = defense_reduction
+            * defender_formation_mods.defense_modifier
+            * defender_terrain.defense_multiplier()
+            * defender.rank().bonuses().defense_multiplier;
+
         // Calculate final damage
         let final_damage = (modified_attack - modified_defense).max(1.0);
-        
+
         // Apply morale effect
         let morale_factor = attacker.morale / 100.0;
         (final_damage * morale_factor) as u32
@@ -230,9 +838,9 @@ impl CombatSystem {
     pub fn apply_casualties(unit: &mut Unit, casualties: u32) {
         unit.count = unit.count.saturating_sub(casualties);
         
-        // Morale loss based on casualties
+        // Morale loss based on casualties, veterans take losses better
         let casualty_ratio = casualties as f32 / unit.max_count as f32;
-        let morale_loss = casualty_ratio * 20.0; // Lose up to 20 morale
+        let morale_loss = casualty_ratio * 20.0 / unit.rank().bonuses().morale_multiplier; // Lose up to 20 morale
         unit.morale = (unit.morale - morale_loss).max(0.0);
     }
     
@@ -240,6 +848,24 @@ impl CombatSystem {
         // Unit routs if morale is too low or too few soldiers remain
         unit.morale < 20.0 || (unit.count as f32 / unit.max_count as f32) < 0.1
     }
+
+    /// Chance for a ranged volley to land. Accuracy drops off towards the
+    /// edge of the shooter's range and against concealed targets.
+    pub fn hit_chance(
+        attacker_stats: &CombatStats,
+        defender_stats: &CombatStats,
+        defender_terrain: &TerrainModifiers,
+        distance: f32,
+        max_range: f32,
+    ) -> f32 {
+        let range_falloff = 1.0 - 0.5 * (distance / max_range.max(1.0)).min(1.0);
+        let chance = attacker_stats.accuracy
+            * (1.0 - defender_stats.evasion)
+            * defender_terrain.ranged_accuracy_multiplier()
+            * range_falloff;
+
+        chance.clamp(0.05, 0.95)
+    }
 }
 
 // ============================================================================
@@ -266,11 +892,151 @@ impl BattleAI {
         }
     }
     
-    pub fn choose_target(attacking_unit: &Unit, enemy_units: &[&Unit]) -> Option<UnitId> {
-        // Simple AI: target weakest enemy first
+    /// Picks the enemy `attacker` most wants to fight, by weighing up every
+    /// candidate with `score_target`. `attackers_on` tells how many other
+    /// friendly units already go for an enemy; those count for less, so a
+    /// side spreads out over the enemy line rather than piling onto one
+    /// unit. Returns `None` when there is nobody left to fight.
+    pub fn choose_target(
+        attacker: &BattleUnit,
+        enemy_units: &[&BattleUnit],
+        attackers_on: impl Fn(UnitId) -> usize,
+    ) -> Option<UnitId> {
+        let weights = TargetWeights::for_unit_type(&attacker.unit.unit_type);
         enemy_units
             .iter()
-            .min_by_key(|unit| unit.count)
-            .map(|unit| unit.id)
+            .map(|enemy| {
+                let attackers = attackers_on(enemy.id());
+                let score = Self::score_target(attacker, enemy, &weights) - weights.crowding * attackers as f32;
+                (enemy.id(), score)
+            })
+            .filter(|(_, score)| score.is_finite())
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, _)| id)
+    }
+
+    /// How attractive `target` is to `attacker`. Higher is better, and
+    /// targets the attacker cannot hurt at all score negative infinity.
+    pub fn score_target(attacker: &BattleUnit, target: &BattleUnit, weights: &TargetWeights) -> f32 {
+        let distance = attacker.position.distance_to(&target.position);
+
+        // Seconds of walking before the attacker can strike
+        let reach = if attacker.has_ranged_attack() { attacker.range } else { MELEE_CONTACT_DISTANCE };
+        let travel_time = (distance - reach).max(0.0) / attacker.speed.max(1.0);
+        let proximity = 1.0 / (1.0 + travel_time / 10.0);
+
+        let in_range = if !attacker.has_ranged_attack() {
+            0.0
+        } else if attacker.can_fire_at(distance) {
+            1.0
+        } else if distance < attacker.min_range {
+            -1.0
+        } else {
+            0.0
+        };
+
+        let matchup = attacker.unit.unit_type.matchup_against(&target.unit.unit_type) - 1.0;
+
+        // Strong enemies and enemies already going for us come first
+        let power = |u: &BattleUnit| u.stats.attack * u.unit.count as f32;
+        let mut threat = (power(target) / power(attacker).max(1.0)).min(2.0) / 2.0;
+        if target.target == Some(attacker.id()) {
+            threat += 0.5;
+        }
+
+        let direction = AttackDirection::from_positions(target.facing, &target.position, &attacker.position);
+        let exposure = target.unit.formation.flank_modifiers(direction).damage_multiplier - 1.0;
+
+        let mut morale = 1.0 - target.unit.morale / 100.0;
+        if target.state == UnitState::Routing {
+            morale = weights.pursuit;
+        }
+
+        let current = if attacker.target == Some(target.id()) { weights.stickiness } else { 0.0 };
+
+        weights.proximity * proximity
+            + weights.in_range * in_range
+            + weights.matchup * matchup
+            + weights.threat * threat
+            + weights.exposure * exposure
+            + weights.morale * morale
+            + current
+    }
+}
+
+/// How much each part of `BattleAI::score_target` counts for a unit.
+#[derive(Debug, Clone)]
+pub struct TargetWeights {
+    pub proximity: f32,
+    pub in_range: f32,
+    pub matchup: f32,
+    pub threat: f32,
+    pub exposure: f32, // Open flank or rear facing the attacker
+    pub morale: f32,
+    pub pursuit: f32,    // Score for chasing a routing unit, in place of its morale
+    pub stickiness: f32, // Bonus for the current target so units don't dither
+    pub crowding: f32,   // Penalty per friendly unit already going for the target
+}
+
+impl TargetWeights {
+    pub fn for_unit_type(unit_type: &UnitType) -> Self {
+        match unit_type {
+            UnitType::Infantry | UnitType::Special(_) => Self {
+                proximity: 2.0,
+                in_range: 0.0,
+                matchup: 1.0,
+                threat: 1.0,
+                exposure: 0.5,
+                morale: 0.5,
+                pursuit: -0.5,
+                stickiness: 0.5,
+                crowding: 0.5,
+            },
+            UnitType::Cavalry => Self {
+                proximity: 1.0,
+                in_range: 0.0,
+                matchup: 1.5,
+                threat: 0.5,
+                exposure: 1.5,
+                morale: 1.0,
+                pursuit: 0.5,
+                stickiness: 0.5,
+                crowding: 0.3,
+            },
+            UnitType::Archers | UnitType::Artillery => Self {
+                proximity: 1.0,
+                in_range: 2.0,
+                matchup: 1.0,
+                threat: 1.0,
+                exposure: 0.25,
+                morale: 0.5,
+                pursuit: -1.0,
+                stickiness: 0.25,
+                crowding: 0.2,
+            },
+        }
     }
 }
+
+impl UnitType {
+    /// How well this unit type does against `target`, 1.0 being even.
+    /// Cavalry runs down missile troops, spears and volleys punish
+    /// massed foot, guns are wasted on fast horsemen.
+    pub fn matchup_against(&self, target: &UnitType) -> f32 {
+        match (self, target) {
+            (UnitType::Cavalry, UnitType::Archers | UnitType::Artillery) => 1.5,
+            (UnitType::Cavalry, UnitType::Infantry) => 0.8,
+            (UnitType::Infantry, UnitType::Cavalry) => 1.2,
+            (UnitType::Infantry, UnitType::Archers) => 1.2,
+            (UnitType::Infantry, UnitType::Artillery) => 1.3,
+            (UnitType::Archers, UnitType::Infantry) => 1.2,
+            (UnitType::Archers, UnitType::Cavalry) => 0.9,
+            (UnitType::Archers, UnitType::Artillery) => 1.1,
+            (UnitType::Artillery, UnitType::Infantry) => 1.3,
+            (UnitType::Artillery, UnitType::Cavalry) => 0.6,
+            (UnitType::Artillery, UnitType::Artillery) => 1.2,
+            _ => 1.0,
+        }
+    }
+}
+
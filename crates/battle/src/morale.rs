@@ -0,0 +1,140 @@
+use crate::*;
+
+// ============================================================================
+// ROUT AND RALLY
+// ============================================================================
+
+/// Routing units run this much faster than their normal march.
+pub const ROUT_SPEED_MULTIPLIER: f32 = 1.2;
+
+/// Casualty multiplier against units that are running away.
+pub const ROUTING_DAMAGE_MULTIPLIER: f32 = 2.0;
+
+/// Friendly units within this distance are shaken when a unit breaks.
+pub const MORALE_CONTAGION_RADIUS: f32 = 150.0;
+const ROUT_CONTAGION_MORALE_LOSS: f32 = 8.0;
+
+/// A routing unit only recovers when no fighting enemy is this close.
+pub const RALLY_SAFE_DISTANCE: f32 = 200.0;
+const RALLY_MORALE: f32 = 40.0;
+const MORALE_RECOVERY_PER_SECOND: f32 = 2.0;
+
+impl BattleSimulation {
+    pub(crate) fn update_morale(&mut self, delta_time: f32) {
+        for i in 0..self.units.len() {
+            if !self.units[i].is_active() {
+                continue;
+            }
+
+            match self.units[i].state {
+                UnitState::Fighting => {
+                    if CombatSystem::check_rout(&self.units[i].unit) {
+                        self.rout(i);
+                    }
+                }
+                UnitState::Routing => self.update_routing_unit(i, delta_time),
+                UnitState::Fled => {}
+            }
+        }
+    }
+
+    fn rout(&mut self, index: usize) {
+        let unit = &mut self.units[index];
+        unit.state = UnitState::Routing;
+        unit.target = None;
+        unit.move_target = None;
+
+        let side = unit.side;
+        let position = unit.position.clone();
+        self.morale_shock(side, &position, MORALE_CONTAGION_RADIUS, ROUT_CONTAGION_MORALE_LOSS);
+    }
+
+    fn update_routing_unit(&mut self, index: usize, delta_time: f32) {
+        let edge = self.battle.battlefield.deployment_edge(self.units[index].side);
+        if (self.units[index].position.y - edge).abs() < 1.0 {
+            self.units[index].state = UnitState::Fled;
+            self.catch_stragglers(index);
+            return;
+        }
+
+        if self.nearest_enemy_distance(index) < RALLY_SAFE_DISTANCE {
+            return;
+        }
+
+        let command = self.commander_bonuses(index).map_or(1.0, |b| b.morale_multiplier);
+        let unit = &mut self.units[index].unit;
+        let recovery = MORALE_RECOVERY_PER_SECOND * unit.formation.get_modifiers().morale_modifier * command;
+        unit.morale = (unit.morale + recovery * delta_time).min(100.0);
+
+        if unit.morale >= RALLY_MORALE && !CombatSystem::check_rout(unit) {
+            self.units[index].state = UnitState::Fighting;
+        }
+    }
+
+    fn nearest_enemy_distance(&self, index: usize) -> f32 {
+        let unit = &self.units[index];
+
+        self.nearest_unit(unit.side.opponent(), &unit.position, |other| other.can_fight())
+            .map_or(f32::INFINITY, |other| self.units[other].position.distance_to(&unit.position))
+    }
+
+    /// Lowers the morale of every fighting unit of `side` within `radius` of
+    /// `position`. Steadier formations, veterans and commanded units are
+    /// shaken less.
+    pub fn morale_shock(&mut self, side: BattleSide, position: &Position, radius: f32, amount: f32) {
+        for i in self.units_within(side, position, radius) {
+            let unit = &self.units[i];
+            if !unit.can_fight() {
+                continue;
+            }
+
+            let steadiness = unit.formation_modifiers().morale_modifier * self.steadiness(i);
+            let unit = &mut self.units[i].unit;
+            unit.morale = (unit.morale - amount / steadiness).max(0.0);
+        }
+    }
+
+    pub(crate) fn rout_destination(&self, index: usize) -> Position {
+        let unit = &self.units[index];
+        Position::new(unit.position.x, self.battle.battlefield.deployment_edge(unit.side))
+    }
+
+    /// A side is beaten once none of its units is still fighting.
+    pub fn side_broken(&self, side: BattleSide) -> bool {
+        !self.units.iter().any(|u| u.side == side && u.can_fight())
+    }
+
+    pub fn side_casualties(&self, side: BattleSide) -> u32 {
+        self.units.iter().filter(|u| u.side == side).map(|u| u.casualties()).sum()
+    }
+
+    pub(crate) fn check_battle_end(&mut self) {
+        let Some((winner, decided_by)) = self.decide_battle() else {
+            return;
+        };
+
+        self.round_up_routers(winner);
+
+        // Everyone who lived through it learned something, winners a bit more
+        for unit in self.units.iter_mut().filter(|u| u.is_alive()) {
+            let mut experience = EXPERIENCE_PER_BATTLE;
+            if winner == Some(unit.side) {
+                experience += EXPERIENCE_VICTORY_BONUS;
+            }
+            unit.unit.gain_experience(experience);
+        }
+        self.reward_generals(winner);
+
+        self.outcome = Some(BattleOutcome {
+            winner,
+            attacker_casualties: self.side_casualties(BattleSide::Attacker),
+            defender_casualties: self.side_casualties(BattleSide::Defender),
+            duration: self.elapsed,
+            factions: self.faction_results(),
+            decided_by: Some(decided_by),
+            prisoners: self.prisoners.clone(),
+            captured_generals: self.captured_generals(),
+        });
+        self.battle.phase = BattlePhase::Resolved;
+    }
+}
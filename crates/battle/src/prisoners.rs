@@ -0,0 +1,240 @@
+use crate::*;
+
+// ============================================================================
+// PRISONERS
+// ============================================================================
+
+/// Share of the men a routing unit loses in melee who throw down their arms
+/// and are taken prisoner rather than cut down.
+const ROUT_SURRENDER_SHARE: f32 = 0.5;
+
+/// A unit running off the field with an enemy this close behind leaves this
+/// share of its men in the enemy's hands.
+const PURSUIT_DISTANCE: f32 = 60.0;
+const STRAGGLER_SHARE: f32 = 0.3;
+
+/// Share of the men left in the routed units of a broken army that the
+/// victors catch in the pursuit. Used by auto-resolve.
+const PURSUIT_CAPTURE_SHARE: f32 = 0.1;
+
+/// Men of one faction and unit type held by another after a battle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrisonerGroup {
+    pub faction: FactionId, // Whose men they are
+    pub captor: FactionId,
+    pub unit_type: UnitType,
+    pub men: u32,
+}
+
+impl PrisonerGroup {
+    /// Adds the group to `groups`, merged with the one holding the same men
+    /// if there is one.
+    pub fn add_to(self, groups: &mut Vec<PrisonerGroup>) {
+        if self.men == 0 {
+            return;
+        }
+
+        match groups
+            .iter_mut()
+            .find(|g| g.faction == self.faction && g.captor == self.captor && g.unit_type == self.unit_type)
+        {
+            Some(group) => group.men += self.men,
+            None => groups.push(self),
+        }
+    }
+}
+
+impl BattleOutcome {
+    /// Men of `faction` who ended the battle in enemy hands.
+    pub fn men_captured(&self, faction: FactionId) -> u32 {
+        self.prisoners.iter().filter(|p| p.faction == faction).map(|p| p.men).sum()
+    }
+}
+
+impl BattleSimulation {
+    /// Takes part of the men a routing unit just lost to the blow of the
+    /// unit at `captor_index` prisoner.
+    pub(crate) fn take_prisoners(&mut self, captor_index: usize, index: usize, lost: u32) {
+        let unit = &self.units[index];
+        if unit.state != UnitState::Routing {
+            return;
+        }
+
+        PrisonerGroup {
+            faction: unit.unit.faction,
+            captor: self.units[captor_index].unit.faction,
+            unit_type: unit.unit.unit_type.clone(),
+            men: (lost as f32 * ROUT_SURRENDER_SHARE).round() as u32,
+        }
+        .add_to(&mut self.prisoners);
+    }
+
+    /// Stragglers of a unit that just ran off the field are caught if the
+    /// enemy was close behind it.
+    pub(crate) fn catch_stragglers(&mut self, index: usize) {
+        let unit = &self.units[index];
+        if let Some(captor) = self.pursuer(index, PURSUIT_DISTANCE) {
+            let men = (unit.unit.count as f32 * STRAGGLER_SHARE).round() as u32;
+            self.capture(index, captor, men);
+        }
+    }
+
+    /// Routing units of the beaten side that still have a victor on their
+    /// heels when the battle ends are rounded up, general and all. Those
+    /// that got clear away escape.
+    pub(crate) fn round_up_routers(&mut self, winner: Option<BattleSide>) {
+        if winner.is_none() {
+            return;
+        }
+
+        for i in 0..self.units.len() {
+            let unit = &self.units[i];
+            if Some(unit.side) == winner || unit.state != UnitState::Routing || !unit.is_alive() {
+                continue;
+            }
+            if let Some(captor) = self.pursuer(i, RALLY_SAFE_DISTANCE) {
+                self.capture(i, captor, unit.unit.count);
+            }
+        }
+    }
+
+    /// Faction of the nearest enemy still fighting within `distance` of
+    /// the unit.
+    fn pursuer(&self, index: usize, distance: f32) -> Option<FactionId> {
+        let unit = &self.units[index];
+        self.nearest_unit(unit.side.opponent(), &unit.position, |u| u.can_fight())
+            .map(|i| &self.units[i])
+            .filter(|pursuer| pursuer.position.distance_to(&unit.position) < distance)
+            .map(|pursuer| pursuer.unit.faction)
+    }
+
+    /// Takes `men` of the unit prisoner, and its general too if nobody is
+    /// left with them.
+    fn capture(&mut self, index: usize, captor: FactionId, men: u32) {
+        let unit = &mut self.units[index];
+        let men = men.min(unit.unit.count);
+        PrisonerGroup {
+            faction: unit.unit.faction,
+            captor,
+            unit_type: unit.unit.unit_type.clone(),
+            men,
+        }
+        .add_to(&mut self.prisoners);
+        unit.unit.count -= men;
+
+        if unit.unit.count == 0 {
+            if let Some(general) = unit.general.as_mut().filter(|g| g.is_active()) {
+                general.status = GeneralStatus::Captured(captor);
+            }
+        }
+    }
+
+    /// Generals taken during the battle, as they were when taken.
+    pub fn captured_generals(&self) -> Vec<General> {
+        self.units
+            .iter()
+            .filter_map(|u| u.general.clone())
+            .filter(|g| matches!(g.status, GeneralStatus::Captured(_)))
+            .collect()
+    }
+}
+
+impl AutoResolve {
+    /// Rides down the routed units of a broken army, taking part of the men
+    /// left in each prisoner.
+    pub(crate) fn pursue(units: &mut [Unit], captor: FactionId) -> Vec<PrisonerGroup> {
+        let mut prisoners = Vec::new();
+        for unit in units.iter_mut().filter(|u| u.count > 0 && CombatSystem::check_rout(u)) {
+            let men = ((unit.count as f32 * PURSUIT_CAPTURE_SHARE).round() as u32).min(unit.count);
+            unit.count -= men;
+            PrisonerGroup {
+                faction: unit.faction,
+                captor,
+                unit_type: unit.unit_type.clone(),
+                men,
+            }
+            .add_to(&mut prisoners);
+        }
+        prisoners
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn group(faction: FactionId, captor: FactionId, unit_type: UnitType, men: u32) -> PrisonerGroup {
+        PrisonerGroup {
+            faction,
+            captor,
+            unit_type,
+            men,
+        }
+    }
+
+    #[test]
+    fn prisoners_of_the_same_kind_are_held_together() {
+        let (faction, captor) = (FactionId::default(), FactionId::default());
+        let mut groups = Vec::new();
+        group(faction, captor, UnitType::Infantry, 10).add_to(&mut groups);
+        group(faction, captor, UnitType::Infantry, 5).add_to(&mut groups);
+        group(faction, captor, UnitType::Archers, 3).add_to(&mut groups);
+        group(captor, faction, UnitType::Infantry, 2).add_to(&mut groups);
+        group(faction, captor, UnitType::Cavalry, 0).add_to(&mut groups);
+
+        let men: Vec<(UnitType, u32)> = groups.iter().map(|g| (g.unit_type.clone(), g.men)).collect();
+        assert_eq!(men, vec![(UnitType::Infantry, 15), (UnitType::Archers, 3), (UnitType::Infantry, 2)]);
+    }
+
+    #[test]
+    fn only_routing_men_give_themselves_up() {
+        let mut simulation = open_field(1);
+        let captor = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 60, 500.0, 390.0);
+        let victim = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 60, 500.0, 410.0);
+        let (captor_index, index) = (simulation.index_of(captor).unwrap(), simulation.index_of(victim).unwrap());
+
+        simulation.take_prisoners(captor_index, index, 10);
+        assert!(simulation.prisoners.is_empty());
+
+        simulation.units[index].state = UnitState::Routing;
+        simulation.take_prisoners(captor_index, index, 10);
+        assert_eq!(
+            simulation.prisoners,
+            vec![group(simulation.battle.defender, simulation.battle.attacker, UnitType::Infantry, 5)]
+        );
+    }
+
+    #[test]
+    fn routers_caught_at_the_end_are_taken_with_their_general() {
+        let mut simulation = open_field(2);
+        place(&mut simulation, BattleSide::Attacker, UnitType::Cavalry, 60, 500.0, 390.0);
+        let caught = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 30, 500.0, 420.0);
+        let escaped = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 30, 100.0, 790.0);
+        let general = General::new("Beaten".to_string(), simulation.battle.defender);
+        simulation.unit_mut(caught).unwrap().attach_general(general);
+        for id in [caught, escaped] {
+            simulation.unit_mut(id).unwrap().state = UnitState::Routing;
+        }
+
+        simulation.round_up_routers(Some(BattleSide::Attacker));
+        assert_eq!(simulation.unit(caught).unwrap().unit.count, 0);
+        assert_eq!(simulation.unit(escaped).unwrap().unit.count, 30);
+        let generals = simulation.captured_generals();
+        assert_eq!(generals.len(), 1);
+        assert_eq!(generals[0].status, GeneralStatus::Captured(simulation.battle.attacker));
+        assert_eq!(simulation.prisoners.iter().map(|p| p.men).sum::<u32>(), 30);
+    }
+
+    #[test]
+    fn the_pursuit_rides_down_only_routed_units() {
+        let (faction, captor) = (FactionId::default(), FactionId::default());
+        let mut routed = unit(UnitType::Infantry, faction, 100);
+        routed.morale = 5.0;
+        let mut units = vec![routed, unit(UnitType::Archers, faction, 100)];
+
+        let prisoners = AutoResolve::pursue(&mut units, captor);
+        assert_eq!(prisoners, vec![group(faction, captor, UnitType::Infantry, 10)]);
+        assert_eq!((units[0].count, units[1].count), (90, 100));
+    }
+}
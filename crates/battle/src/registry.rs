@@ -0,0 +1,348 @@
+use crate::*;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use std::fmt;
+
+// ============================================================================
+// UNIT DEFINITIONS
+// ============================================================================
+
+pub const UNIT_DEFINITIONS_PATH: &str = "units/units.ron";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnitDefinition {
+    pub id: String,
+    pub name: String,
+    pub stats: UnitStats,
+    pub upkeep: Resources, // Paid every turn while the unit exists
+    pub allowed_formations: Vec<Formation>,
+    pub tags: Vec<String>,
+}
+
+impl UnitDefinition {
+    pub fn allows_formation(&self, formation: &Formation) -> bool {
+        self.allowed_formations.contains(formation)
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    fn validate(&self) -> Result<(), UnitRegistryError> {
+        let invalid = |reason: &str| UnitRegistryError::InvalidDefinition {
+            id: self.id.clone(),
+            reason: reason.to_string(),
+        };
+
+        if self.id.is_empty() {
+            return Err(invalid("id is empty"));
+        }
+        if self.allowed_formations.is_empty() {
+            return Err(invalid("no allowed formations"));
+        }
+        if !(0.0..=1.0).contains(&self.stats.accuracy) || !(0.0..=1.0).contains(&self.stats.evasion) {
+            return Err(invalid("accuracy and evasion must be between 0.0 and 1.0"));
+        }
+        if self.stats.min_range > self.stats.range {
+            return Err(invalid("min_range is larger than range"));
+        }
+        Ok(())
+    }
+}
+
+/// Contents of a unit definitions file.
+#[derive(Asset, TypePath, Debug, Clone, Serialize, Deserialize)]
+pub struct UnitDefinitions {
+    pub units: Vec<UnitDefinition>,
+}
+
+#[derive(Debug)]
+pub enum UnitRegistryError {
+    UnknownUnit(String),
+    DuplicateUnit(String),
+    InvalidDefinition { id: String, reason: String },
+    FormationNotAllowed { id: String, formation: Formation },
+    Parse(String),
+    Io(String),
+}
+
+impl fmt::Display for UnitRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnitRegistryError::UnknownUnit(id) => write!(f, "unknown unit id '{}'", id),
+            UnitRegistryError::DuplicateUnit(id) => write!(f, "unit id '{}' is defined more than once", id),
+            UnitRegistryError::InvalidDefinition { id, reason } => write!(f, "unit '{}' is invalid: {}", id, reason),
+            UnitRegistryError::FormationNotAllowed { id, formation } => {
+                write!(f, "unit '{}' cannot take up the {:?} formation", id, formation)
+            }
+            UnitRegistryError::Parse(message) => write!(f, "could not parse unit definitions: {}", message),
+            UnitRegistryError::Io(message) => write!(f, "could not read unit definitions: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for UnitRegistryError {}
+
+impl From<std::io::Error> for UnitRegistryError {
+    fn from(error: std::io::Error) -> Self {
+        UnitRegistryError::Io(error.to_string())
+    }
+}
+
+// ============================================================================
+// UNIT REGISTRY
+// ============================================================================
+
+/// Every unit the game knows about, keyed by `UnitType::id`. Starts out with
+/// the built-in stats and is replaced once the definitions file has loaded.
+#[derive(Resource, Debug, Clone)]
+pub struct UnitRegistry {
+    definitions: HashMap<String, UnitDefinition>,
+}
+
+impl UnitRegistry {
+    pub fn from_definitions(definitions: Vec<UnitDefinition>) -> Result<Self, UnitRegistryError> {
+        let mut registry = HashMap::new();
+
+        for definition in definitions {
+            definition.validate()?;
+            if registry.contains_key(&definition.id) {
+                return Err(UnitRegistryError::DuplicateUnit(definition.id));
+            }
+            registry.insert(definition.id.clone(), definition);
+        }
+
+        Ok(Self { definitions: registry })
+    }
+
+    pub fn from_ron_str(data: &str) -> Result<Self, UnitRegistryError> {
+        let file: UnitDefinitions = ron::de::from_str(data).map_err(|e| UnitRegistryError::Parse(e.to_string()))?;
+        Self::from_definitions(file.units)
+    }
+
+    /// The hard-coded stats from `UnitType::stats`, used until the
+    /// definitions file is available.
+    pub fn built_in() -> Self {
+        let base_types = [
+            (UnitType::Infantry, vec![Formation::Line, Formation::Column, Formation::Square], ["melee", "infantry"]),
+            (UnitType::Cavalry, vec![Formation::Line, Formation::Column, Formation::Wedge], ["melee", "cavalry"]),
+            (UnitType::Archers, vec![Formation::Line, Formation::Skirmish], ["ranged", "infantry"]),
+            (UnitType::Artillery, vec![Formation::Line], ["ranged", "siege"]),
+        ];
+
+        let definitions = base_types
+            .into_iter()
+            .map(|(unit_type, allowed_formations, tags)| {
+                let stats = unit_type.stats();
+                UnitDefinition {
+                    id: unit_type.id().to_string(),
+                    name: unit_type.name().to_string(),
+                    upkeep: Resources {
+                        gold: stats.cost.gold / 10,
+                        food: stats.cost.manpower,
+                        materials: 0,
+                        manpower: 0,
+                    },
+                    stats,
+                    allowed_formations,
+                    tags: tags.iter().map(|t| t.to_string()).collect(),
+                }
+            })
+            .map(|d| (d.id.clone(), d))
+            .collect();
+
+        Self { definitions }
+    }
+
+    pub fn get(&self, id: &str) -> Result<&UnitDefinition, UnitRegistryError> {
+        self.definitions
+            .get(id)
+            .ok_or_else(|| UnitRegistryError::UnknownUnit(id.to_string()))
+    }
+
+    pub fn definition(&self, unit_type: &UnitType) -> Result<&UnitDefinition, UnitRegistryError> {
+        self.get(unit_type.id())
+    }
+
+    pub fn stats(&self, unit_type: &UnitType) -> Result<UnitStats, UnitRegistryError> {
+        self.definition(unit_type).map(|d| d.stats.clone())
+    }
+
+    /// Errors if `unit_type` is unknown or can't take up `formation`.
+    pub fn check_formation(&self, unit_type: &UnitType, formation: &Formation) -> Result<(), UnitRegistryError> {
+        if self.definition(unit_type)?.allows_formation(formation) {
+            Ok(())
+        } else {
+            Err(UnitRegistryError::FormationNotAllowed {
+                id: unit_type.id().to_string(),
+                formation: formation.clone(),
+            })
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.definitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.definitions.is_empty()
+    }
+}
+
+impl Default for UnitRegistry {
+    fn default() -> Self {
+        Self::built_in()
+    }
+}
+
+// ============================================================================
+// ASSET LOADING
+// ============================================================================
+
+#[derive(Default)]
+pub struct UnitDefinitionsLoader;
+
+impl AssetLoader for UnitDefinitionsLoader {
+    type Asset = UnitDefinitions;
+    type Settings = ();
+    type Error = UnitRegistryError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        ron::de::from_bytes(&bytes).map_err(|e| UnitRegistryError::Parse(e.to_string()))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["units.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct UnitDefinitionsHandle(pub Handle<UnitDefinitions>);
+
+pub(crate) fn load_unit_definitions(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(UnitDefinitionsHandle(asset_server.load(UNIT_DEFINITIONS_PATH)));
+}
+
+/// Rebuilds the registry whenever the definitions file is loaded or, with
+/// the `dev` feature, edited on disk. A broken file keeps the old registry.
+pub(crate) fn sync_unit_registry(
+    mut events: EventReader<AssetEvent<UnitDefinitions>>,
+    handle: Res<UnitDefinitionsHandle>,
+    definitions: Res<Assets<UnitDefinitions>>,
+    mut registry: ResMut<UnitRegistry>,
+) {
+    for event in events.read() {
+        if !event.is_loaded_with_dependencies(&handle.0) && !event.is_modified(&handle.0) {
+            continue;
+        }
+
+        let Some(file) = definitions.get(&handle.0) else {
+            continue;
+        };
+
+        match UnitRegistry::from_definitions(file.units.clone()) {
+            Ok(loaded) => {
+                *registry = loaded;
+                println!("Unit definitions loaded - {} units registered", registry.len());
+            }
+            Err(error) => println!("Unit definitions rejected: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNITS_RON: &str = include_str!("../../../assets/units/units.ron");
+
+    fn definition(id: &str) -> UnitDefinition {
+        UnitDefinition {
+            id: id.to_string(),
+            name: id.to_string(),
+            stats: UnitType::Infantry.stats(),
+            upkeep: Resources::default(),
+            allowed_formations: vec![Formation::Line],
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn the_definitions_file_parses() {
+        let registry = UnitRegistry::from_ron_str(UNITS_RON).unwrap();
+        assert_eq!(registry.len(), 6);
+        for unit_type in [UnitType::Infantry, UnitType::Cavalry, UnitType::Archers, UnitType::Artillery] {
+            assert!(registry.definition(&unit_type).is_ok(), "{} missing", unit_type.id());
+        }
+        assert!(registry.get("Sipahi").unwrap().has_tag("cavalry"));
+    }
+
+    #[test]
+    fn special_units_get_their_own_stats() {
+        let registry = UnitRegistry::from_ron_str(UNITS_RON).unwrap();
+        let janissary = registry.stats(&UnitType::Special("Janissary".to_string())).unwrap();
+        let sipahi = registry.stats(&UnitType::Special("Sipahi".to_string())).unwrap();
+
+        assert_eq!((janissary.attack, janissary.range, janissary.ammunition), (24, 120.0, 15));
+        assert_eq!((sipahi.attack, sipahi.speed), (26, 110.0));
+        assert_ne!(janissary.attack, UnitType::Special("Janissary".to_string()).stats().attack);
+    }
+
+    #[test]
+    fn unlisted_units_are_unknown() {
+        let registry = UnitRegistry::from_ron_str(UNITS_RON).unwrap();
+        let error = registry.stats(&UnitType::Special("Mameluke".to_string())).unwrap_err();
+        assert!(matches!(&error, UnitRegistryError::UnknownUnit(id) if id == "Mameluke"));
+        assert_eq!(error.to_string(), "unknown unit id 'Mameluke'");
+
+        let built_in = UnitRegistry::built_in();
+        assert!(built_in.definition(&UnitType::Special("Janissary".to_string())).is_err());
+    }
+
+    #[test]
+    fn duplicate_and_invalid_definitions_are_rejected() {
+        let duplicate = UnitRegistry::from_definitions(vec![definition("Infantry"), definition("Infantry")]);
+        assert!(matches!(duplicate, Err(UnitRegistryError::DuplicateUnit(id)) if id == "Infantry"));
+
+        let mut no_formations = definition("Militia");
+        no_formations.allowed_formations.clear();
+        let mut sharpshooters = definition("Sharpshooters");
+        sharpshooters.stats.accuracy = 1.5;
+        for broken in [no_formations, sharpshooters, definition("")] {
+            let id = broken.id.clone();
+            let result = UnitRegistry::from_definitions(vec![broken]);
+            assert!(matches!(result, Err(UnitRegistryError::InvalidDefinition { id: rejected, .. }) if rejected == id));
+        }
+
+        let unparsable = UnitRegistry::from_ron_str("(units: [(id: \"Infantry\")])");
+        assert!(matches!(unparsable, Err(UnitRegistryError::Parse(_))));
+    }
+
+    #[test]
+    fn formations_outside_the_definition_are_refused() {
+        let registry = UnitRegistry::from_ron_str(UNITS_RON).unwrap();
+        registry.check_formation(&UnitType::Infantry, &Formation::Square).unwrap();
+
+        let error = registry.check_formation(&UnitType::Artillery, &Formation::Square).unwrap_err();
+        assert!(matches!(
+            &error,
+            UnitRegistryError::FormationNotAllowed { id, formation: Formation::Square } if id == "Artillery"
+        ));
+        assert!(matches!(
+            registry.check_formation(&UnitType::Special("Mameluke".to_string()), &Formation::Line),
+            Err(UnitRegistryError::UnknownUnit(_))
+        ));
+
+        let mut unit = crate::testing::unit(UnitType::Archers, FactionId::default(), 10);
+        unit.formation = Formation::Wedge;
+        let refused = BattleUnit::from_registry(&registry, unit, BattleSide::Attacker, Position::new(0.0, 0.0));
+        assert!(matches!(refused, Err(UnitRegistryError::FormationNotAllowed { .. })));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::*;
+
+// ============================================================================
+// REINFORCEMENTS
+// ============================================================================
+
+/// Seconds of battle an army needs to cover one unit of map distance over
+/// ground with a movement cost of 1.0.
+pub const REINFORCEMENT_SECONDS_PER_DISTANCE: f32 = 0.6;
+
+/// Gap between reinforcing units as they come onto the field side by side.
+const ARRIVAL_SPACING: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BattlefieldEdge {
+    North, // y = height, where the defender deploys
+    South, // y = 0, where the attacker deploys
+    East,  // x = width
+    West,  // x = 0
+}
+
+impl BattlefieldEdge {
+    /// The edge an army coming from `origin` marches in on when the battle
+    /// is fought at `battle`. The battlefield is laid out the same way round
+    /// as the map.
+    pub fn from_direction(battle: &Position, origin: &Position) -> Self {
+        let (dx, dy) = (origin.x - battle.x, origin.y - battle.y);
+        if dx.abs() > dy.abs() {
+            if dx > 0.0 {
+                BattlefieldEdge::East
+            } else {
+                BattlefieldEdge::West
+            }
+        } else if dy > 0.0 {
+            BattlefieldEdge::North
+        } else {
+            BattlefieldEdge::South
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BattlefieldEdge::North => "north",
+            BattlefieldEdge::South => "south",
+            BattlefieldEdge::East => "east",
+            BattlefieldEdge::West => "west",
+        }
+    }
+}
+
+/// Troops on their way to a battle that is already being fought. They come
+/// onto the field at their edge once `arrival_time` seconds of combat have
+/// passed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reinforcement {
+    pub faction: FactionId,
+    pub units: Vec<BattleUnit>,
+    pub arrival_time: f32,
+    pub edge: BattlefieldEdge,
+}
+
+impl Reinforcement {
+    pub fn new(faction: FactionId, units: Vec<BattleUnit>, arrival_time: f32, edge: BattlefieldEdge) -> Self {
+        Self {
+            faction,
+            units,
+            arrival_time,
+            edge,
+        }
+    }
+
+    /// Troops marching from `origin` to a battle in `destination`, at the
+    /// pace of the ground between the two.
+    pub fn march(
+        faction: FactionId,
+        units: Vec<BattleUnit>,
+        origin: &Province,
+        destination: &Province,
+        movement_multiplier: f32,
+    ) -> Self {
+        let arrival_time = Self::marching_time(origin, destination, movement_multiplier);
+        let edge = BattlefieldEdge::from_direction(&destination.position, &origin.position);
+        Self::new(faction, units, arrival_time, edge)
+    }
+
+    /// Seconds of battle it takes to march from `origin` to `destination`.
+    /// The going is as hard as the average of the two provinces' terrain,
+    /// and a general who marches fast brings the men in sooner.
+    pub fn marching_time(origin: &Province, destination: &Province, movement_multiplier: f32) -> f32 {
+        let distance = origin.position.distance_to(&destination.position);
+        let movement_cost = (origin.terrain_type.movement_cost() + destination.terrain_type.movement_cost()) / 2.0;
+        distance * movement_cost * REINFORCEMENT_SECONDS_PER_DISTANCE / movement_multiplier.max(0.1)
+    }
+
+    pub fn men(&self) -> u32 {
+        self.units.iter().map(|u| u.unit.count).sum()
+    }
+}
+
+/// Reinforcements that came onto the field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReinforcementArrival {
+    pub faction: FactionId,
+    pub side: BattleSide,
+    pub edge: BattlefieldEdge,
+    pub men: u32,
+}
+
+/// Sent by the battle plugin for every arrival, for the UI to announce.
+#[derive(Event, Debug, Clone)]
+pub struct ReinforcementsArrived(pub ReinforcementArrival);
+
+impl Battlefield {
+    /// Where troops marching in on `edge` first stand, the middle of the
+    /// edge, and the heading that points them into the field.
+    pub fn edge_entry(&self, edge: BattlefieldEdge) -> (Position, f32) {
+        use std::f32::consts::{FRAC_PI_2, PI};
+
+        match edge {
+            BattlefieldEdge::North => (Position::new(self.width / 2.0, self.height), -FRAC_PI_2),
+            BattlefieldEdge::South => (Position::new(self.width / 2.0, 0.0), FRAC_PI_2),
+            BattlefieldEdge::East => (Position::new(self.width, self.height / 2.0), PI),
+            BattlefieldEdge::West => (Position::new(0.0, self.height / 2.0), 0.0),
+        }
+    }
+}
+
+impl BattleSimulation {
+    /// Sends troops to join the battle later on. They fight for whichever
+    /// side their faction is on, so the faction has to be in the battle.
+    pub fn schedule_reinforcement(&mut self, reinforcement: Reinforcement) -> Result<(), AllianceError> {
+        let side = self
+            .battle
+            .side_of(reinforcement.faction)
+            .ok_or(AllianceError::NotInBattle(reinforcement.faction))?;
+
+        let mut reinforcement = reinforcement;
+        for unit in reinforcement.units.iter_mut() {
+            unit.side = side;
+        }
+        self.reinforcements.push(reinforcement);
+        Ok(())
+    }
+
+    /// The next troops still on their way to `side`.
+    pub fn next_reinforcement(&self, side: BattleSide) -> Option<&Reinforcement> {
+        self.reinforcements
+            .iter()
+            .filter(|r| self.battle.side_of(r.faction) == Some(side))
+            .min_by(|a, b| a.arrival_time.total_cmp(&b.arrival_time))
+    }
+
+    /// Brings on every reinforcement whose time has come, lined up along
+    /// its edge facing into the field.
+    pub(crate) fn arrive_reinforcements(&mut self) {
+        self.last_arrivals.clear();
+        if !self.reinforcements.iter().any(|r| r.arrival_time <= self.elapsed) {
+            return;
+        }
+
+        let (arriving, waiting) = std::mem::take(&mut self.reinforcements)
+            .into_iter()
+            .partition(|r| r.arrival_time <= self.elapsed);
+        self.reinforcements = waiting;
+
+        for reinforcement in arriving {
+            let Some(side) = self.battle.side_of(reinforcement.faction) else {
+                continue;
+            };
+            self.last_arrivals.push(ReinforcementArrival {
+                faction: reinforcement.faction,
+                side,
+                edge: reinforcement.edge,
+                men: reinforcement.men(),
+            });
+
+            let (centre, facing) = self.battle.battlefield.edge_entry(reinforcement.edge);
+            let along = facing + std::f32::consts::FRAC_PI_2;
+            let width: f32 = reinforcement
+                .units
+                .iter()
+                .map(|u| u.footprint_radius() * 2.0 + ARRIVAL_SPACING)
+                .sum();
+
+            let mut offset = -width / 2.0;
+            for mut unit in reinforcement.units {
+                let radius = unit.footprint_radius();
+                offset += radius;
+                let position = Position::new(
+                    centre.x + along.cos() * offset + facing.cos() * radius,
+                    centre.y + along.sin() * offset + facing.sin() * radius,
+                );
+                offset += radius + ARRIVAL_SPACING;
+
+                unit.position = self.battle.battlefield.clamp(&position);
+                unit.facing = facing;
+                self.add_unit(unit);
+            }
+        }
+    }
+}
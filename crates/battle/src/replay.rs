@@ -0,0 +1,395 @@
+use crate::*;
+use std::path::Path;
+
+// ============================================================================
+// REPLAY RECORDING
+// ============================================================================
+
+pub const REPLAY_VERSION: u32 = 1;
+pub const REPLAY_DIRECTORY: &str = "replays";
+
+/// How often, in ticks, the recorder stores a state checksum.
+pub const DEFAULT_CHECKSUM_INTERVAL: u64 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BattleReplay {
+    pub version: u32,
+    pub seed: u64,
+    pub initial_state: BattleSimulation,
+    pub commands: Vec<TimedCommand>,
+    pub checksum_interval: u64,
+    pub checksums: Vec<(u64, u64)>, // (tick, checksum)
+    pub final_tick: u64,
+}
+
+impl BattleReplay {
+    pub fn to_ron_string(&self) -> Result<String, ron::Error> {
+        ron::to_string(self)
+    }
+
+    pub fn from_ron_str(data: &str) -> Result<Self, ron::error::SpannedError> {
+        ron::from_str(data)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, self.to_ron_string()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let data = std::fs::read_to_string(path)?;
+        Ok(Self::from_ron_str(&data)?)
+    }
+}
+
+/// Captures a battle as it is played: the starting state once, then only
+/// the commands carried out each tick and a checksum every few ticks.
+#[derive(Resource, Debug, Clone)]
+pub struct BattleRecorder {
+    pub replay: BattleReplay,
+}
+
+impl BattleRecorder {
+    pub fn start(simulation: &BattleSimulation) -> Self {
+        Self::with_checksum_interval(simulation, DEFAULT_CHECKSUM_INTERVAL)
+    }
+
+    pub fn with_checksum_interval(simulation: &BattleSimulation, checksum_interval: u64) -> Self {
+        // Commands already queued get recorded by `capture` once they are carried out
+        let mut initial_state = simulation.clone();
+        initial_state.pending_commands.clear();
+        initial_state.last_commands.clear();
+
+        Self {
+            replay: BattleReplay {
+                version: REPLAY_VERSION,
+                seed: simulation.seed,
+                initial_state,
+                commands: Vec::new(),
+                checksum_interval: checksum_interval.max(1),
+                checksums: Vec::new(),
+                final_tick: simulation.tick,
+            },
+        }
+    }
+
+    /// Call before every `BattleSimulation::step`. Deployment moves units
+    /// around without any commands, so until the first tick has been fought
+    /// the starting state is taken again from the battle as it stands.
+    pub fn prepare(&mut self, simulation: &BattleSimulation) {
+        let nothing_fought = self.replay.final_tick == self.replay.initial_state.tick;
+        if nothing_fought && simulation.tick == self.replay.final_tick {
+            *self = Self::with_checksum_interval(simulation, self.replay.checksum_interval);
+        }
+    }
+
+    /// Call after every `BattleSimulation::step`.
+    pub fn capture(&mut self, simulation: &BattleSimulation) {
+        if simulation.tick == self.replay.final_tick {
+            return; // The simulation didn't advance, e.g. it's still deploying
+        }
+
+        self.replay.commands.extend(simulation.last_commands.iter().cloned());
+        if simulation.tick.is_multiple_of(self.replay.checksum_interval) {
+            self.replay.checksums.push((simulation.tick, simulation.checksum()));
+        }
+        self.replay.final_tick = simulation.tick;
+    }
+
+    pub fn finish(self) -> BattleReplay {
+        self.replay
+    }
+}
+
+impl BattleSimulation {
+    /// FNV-1a hash over everything that decides how the battle goes on.
+    /// Two runs that produce different checksums at the same tick have
+    /// diverged.
+    pub fn checksum(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |value: u64| {
+            for byte in value.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+
+        feed(self.tick);
+        feed(self.rng.state());
+        feed(self.projectiles.len() as u64);
+        for unit in &self.units {
+            feed(unit.unit.count as u64);
+            feed(unit.unit.morale.to_bits() as u64);
+            feed(unit.position.x.to_bits() as u64);
+            feed(unit.position.y.to_bits() as u64);
+            feed(unit.facing.to_bits() as u64);
+            feed(unit.ammunition as u64);
+            feed(unit.state as u64);
+            feed(unit.active_general().is_some() as u64);
+            feed(unit.stamina.to_bits() as u64);
+            feed(unit.run_up.to_bits() as u64);
+            feed(unit.cohesion.to_bits() as u64);
+            feed(unit.formation_change.is_some() as u64);
+            feed(unit.waypoints.len() as u64);
+        }
+        for wall in &self.battle.battlefield.walls {
+            feed(wall.strength.to_bits() as u64);
+        }
+
+        hash
+    }
+}
+
+// ============================================================================
+// REPLAY PLAYBACK
+// ============================================================================
+
+pub const MIN_REPLAY_SPEED: f32 = 0.25;
+pub const MAX_REPLAY_SPEED: f32 = 8.0;
+
+/// Re-simulates a recorded battle from its starting state, feeding the
+/// recorded commands back in on the ticks they were given.
+#[derive(Resource, Debug, Clone)]
+pub struct ReplayPlayer {
+    pub replay: BattleReplay,
+    pub simulation: BattleSimulation,
+    pub paused: bool,
+    pub speed: f32,
+    /// First tick where the re-simulated state didn't match the recording.
+    pub divergence: Option<u64>,
+    next_command: usize,
+    next_checksum: usize,
+    accumulator: f32,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: BattleReplay) -> Self {
+        Self {
+            simulation: replay.initial_state.clone(),
+            replay,
+            paused: false,
+            speed: 1.0,
+            divergence: None,
+            next_command: 0,
+            next_checksum: 0,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn current_tick(&self) -> u64 {
+        self.simulation.tick
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.simulation.tick >= self.replay.final_tick
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_REPLAY_SPEED, MAX_REPLAY_SPEED);
+    }
+
+    /// Advances playback by `real_delta` seconds of wall-clock time.
+    pub fn update(&mut self, real_delta: f32) {
+        if self.paused {
+            return;
+        }
+
+        self.accumulator += real_delta * self.speed;
+        while self.accumulator >= BATTLE_TICK && !self.is_finished() {
+            self.accumulator -= BATTLE_TICK;
+            let before = self.simulation.tick;
+            self.step_tick();
+            if self.simulation.tick == before {
+                self.accumulator = 0.0;
+                break;
+            }
+        }
+    }
+
+    /// Jumps to `tick`. Going backwards restarts from the initial state and
+    /// re-simulates, since the simulation can't run in reverse. A divergence
+    /// at or after `tick` is forgotten and found again on the way forward.
+    pub fn seek(&mut self, tick: u64) {
+        if tick < self.simulation.tick {
+            self.simulation = self.replay.initial_state.clone();
+            self.next_command = 0;
+            self.next_checksum = 0;
+            if self.divergence.is_some_and(|divergence| tick <= divergence) {
+                self.divergence = None;
+            }
+        }
+
+        let tick = tick.min(self.replay.final_tick);
+        while self.simulation.tick < tick {
+            let before = self.simulation.tick;
+            self.step_tick();
+            if self.simulation.tick == before {
+                break; // A recording that never left deployment
+            }
+        }
+        self.accumulator = 0.0;
+    }
+
+    pub fn step_tick(&mut self) {
+        let tick = self.simulation.tick;
+        while let Some(recorded) = self.replay.commands.get(self.next_command) {
+            if recorded.tick > tick {
+                break;
+            }
+            self.simulation.queue_command(recorded.command.clone());
+            self.next_command += 1;
+        }
+
+        self.simulation.step(BATTLE_TICK);
+
+        while let Some(&(checksum_tick, checksum)) = self.replay.checksums.get(self.next_checksum) {
+            if checksum_tick > self.simulation.tick {
+                break;
+            }
+            if checksum_tick == self.simulation.tick && checksum != self.simulation.checksum() && self.divergence.is_none() {
+                self.divergence = Some(checksum_tick);
+            }
+            self.next_checksum += 1;
+        }
+    }
+}
+
+pub(crate) fn advance_replay(time: Res<Time<Real>>, mut player: ResMut<ReplayPlayer>) {
+    player.update(time.delta_seconds());
+}
+
+/// Saves the replay once the battle is over. The ticks themselves are
+/// captured as they are stepped, see `step_battle_simulation`.
+pub(crate) fn record_battle(mut commands: Commands, simulation: Res<BattleSimulation>, recorder: Res<BattleRecorder>) {
+    // Keep every finished battle around so it can be watched again
+    if simulation.outcome.is_some() {
+        let path = Path::new(REPLAY_DIRECTORY).join(format!("{}.replay.ron", simulation.battle.id));
+        match recorder.replay.save(&path) {
+            Ok(()) => println!("Battle replay saved to {}", path.display()),
+            Err(error) => println!("Could not save battle replay: {}", error),
+        }
+        commands.remove_resource::<BattleRecorder>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    /// Plays a scripted battle with a recorder running, checksumming every
+    /// tick. Returns the recording and the checksum after each tick.
+    fn record(seed: u64) -> (BattleReplay, Vec<(u64, u64)>) {
+        let mut simulation = skirmish(seed);
+        let script = command_script(&simulation);
+        let mut recorder = BattleRecorder::with_checksum_interval(&simulation, 1);
+        let mut checksums = Vec::new();
+        run_script(&mut simulation, &script, 900, |simulation| {
+            recorder.capture(simulation);
+            checksums.push((simulation.tick, simulation.checksum()));
+        });
+        (recorder.finish(), checksums)
+    }
+
+    #[test]
+    fn playback_matches_the_recording_tick_for_tick() {
+        let (replay, checksums) = record(7);
+        assert!(!replay.commands.is_empty());
+        assert_eq!(replay.checksums, checksums);
+
+        let mut player = ReplayPlayer::new(replay);
+        for &(tick, checksum) in &checksums {
+            player.step_tick();
+            assert_eq!(player.current_tick(), tick);
+            assert_eq!(player.simulation.checksum(), checksum, "diverged at tick {tick}");
+        }
+        assert!(player.is_finished());
+        assert_eq!(player.divergence, None);
+        assert!(player.simulation.units.iter().any(|u| u.unit.count < u.initial_count));
+    }
+
+    #[test]
+    fn playback_survives_a_round_trip_through_the_file_format() {
+        let (replay, checksums) = record(11);
+        let loaded = BattleReplay::from_ron_str(&replay.to_ron_string().unwrap()).unwrap();
+
+        let mut player = ReplayPlayer::new(loaded);
+        player.seek(u64::MAX);
+        assert_eq!(player.divergence, None);
+        assert_eq!(Some(&(player.current_tick(), player.simulation.checksum())), checksums.last());
+    }
+
+    #[test]
+    fn a_battle_recorded_from_deployment_plays_back() {
+        let mut simulation = undeployed_skirmish(5);
+        let mut recorder = BattleRecorder::with_checksum_interval(&simulation, 1);
+        deploy(&mut simulation);
+        let script = command_script(&simulation);
+
+        let mut checksums = Vec::new();
+        while simulation.tick < 600 && simulation.outcome.is_none() {
+            let tick = simulation.tick;
+            simulation.queue_commands(script.iter().filter(|(t, _)| *t == tick).map(|(_, c)| c.clone()));
+            recorder.prepare(&simulation);
+            simulation.step(BATTLE_TICK);
+            recorder.capture(&simulation);
+            checksums.push((simulation.tick, simulation.checksum()));
+        }
+        let replay = recorder.finish();
+        assert!(matches!(replay.initial_state.battle.phase, BattlePhase::Combat));
+        assert_eq!(replay.final_tick, simulation.tick);
+
+        let mut player = ReplayPlayer::new(replay);
+        player.seek(u64::MAX);
+        assert_eq!(player.current_tick(), simulation.tick);
+        assert_eq!(player.divergence, None);
+        assert_eq!(Some(&(player.current_tick(), player.simulation.checksum())), checksums.last());
+
+        player.seek(100);
+        assert_eq!(player.simulation.checksum(), checksums[99].1);
+    }
+
+    #[test]
+    fn a_recording_stuck_in_deployment_does_not_hang_playback() {
+        let simulation = undeployed_skirmish(6);
+        let mut replay = BattleRecorder::start(&simulation).finish();
+        replay.final_tick = 10;
+
+        let mut player = ReplayPlayer::new(replay);
+        player.seek(u64::MAX);
+        player.update(1.0);
+        assert_eq!(player.current_tick(), 0);
+    }
+
+    #[test]
+    fn seeking_back_before_a_divergence_forgets_it() {
+        let (mut replay, _) = record(3);
+        let tampered = replay.checksums[20].0;
+        replay.checksums[20].1 ^= 1;
+
+        let mut player = ReplayPlayer::new(replay);
+        player.seek(u64::MAX);
+        assert_eq!(player.divergence, Some(tampered));
+
+        player.seek(tampered - 5);
+        assert_eq!(player.divergence, None);
+        player.seek(tampered);
+        assert_eq!(player.divergence, Some(tampered));
+
+        // Seeking back past the divergence keeps it
+        player.seek(u64::MAX);
+        player.seek(tampered + 5);
+        assert_eq!(player.divergence, Some(tampered));
+    }
+}
@@ -0,0 +1,351 @@
+use crate::*;
+
+// ============================================================================
+// SELECTION AND CONTROL GROUPS
+// ============================================================================
+
+/// Control groups 1 to 9, saved with Ctrl+number and called back up with
+/// the number alone.
+pub const CONTROL_GROUPS: usize = 9;
+
+/// The units a player has picked out on the battlefield, and the control
+/// groups they saved. Nothing here touches the battle itself: the UI turns
+/// clicks and key presses into calls on this, and every order comes back
+/// out as plain `BattleCommand`s for `BattleSimulation::queue_commands`, so
+/// replays record them like any other.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct BattleSelection {
+    pub side: BattleSide,
+    pub faction: Option<FactionId>, // None lets the player order the whole side
+    pub selected: Vec<UnitId>,
+    pub groups: [Vec<UnitId>; CONTROL_GROUPS],
+}
+
+impl BattleSelection {
+    pub fn new(side: BattleSide, faction: Option<FactionId>) -> Self {
+        Self {
+            side,
+            faction,
+            selected: Vec::new(),
+            groups: Default::default(),
+        }
+    }
+
+    /// The selection of whoever plays the battle. They take the side the AI
+    /// doesn't command, the attacker if it commands neither, and only their
+    /// own faction's units when an ally's contingent is left to the AI.
+    pub fn for_player(simulation: &BattleSimulation) -> Self {
+        let commands_side = |side: BattleSide| {
+            simulation
+                .tactical_ai
+                .iter()
+                .any(|ai| ai.side == side && ai.faction.is_none())
+        };
+        let side = if commands_side(BattleSide::Attacker) && !commands_side(BattleSide::Defender) {
+            BattleSide::Defender
+        } else {
+            BattleSide::Attacker
+        };
+        let contingents = simulation.tactical_ai.iter().any(|ai| ai.side == side && ai.faction.is_some());
+        Self::new(side, contingents.then(|| simulation.battle.leader(side)))
+    }
+
+    /// True for units the player can give orders to.
+    pub fn controls(&self, unit: &BattleUnit) -> bool {
+        unit.side == self.side && unit.can_fight() && self.faction.is_none_or(|f| f == unit.unit.faction)
+    }
+
+    /// Selects the player's units standing inside the box between two
+    /// opposite corners, in addition to the current selection if `add` is
+    /// set (Shift-drag).
+    pub fn select_box(&mut self, simulation: &BattleSimulation, corner: &Position, opposite: &Position, add: bool) {
+        let (min_x, max_x) = (corner.x.min(opposite.x), corner.x.max(opposite.x));
+        let (min_y, max_y) = (corner.y.min(opposite.y), corner.y.max(opposite.y));
+        let inside: Vec<UnitId> = simulation
+            .units
+            .iter()
+            .filter(|u| self.controls(u))
+            .filter(|u| (min_x..=max_x).contains(&u.position.x) && (min_y..=max_y).contains(&u.position.y))
+            .map(|u| u.id())
+            .collect();
+
+        if !add {
+            self.selected.clear();
+        }
+        self.extend(inside);
+    }
+
+    /// Selects the player's unit under `position`, the nearest one if their
+    /// footprints overlap. Returns false if there is none there.
+    pub fn select_at(&mut self, simulation: &BattleSimulation, position: &Position, add: bool) -> bool {
+        let hit = simulation
+            .units
+            .iter()
+            .filter(|u| self.controls(u) && u.position.distance_to(position) <= u.footprint_radius())
+            .min_by(|a, b| a.position.distance_to(position).total_cmp(&b.position.distance_to(position)))
+            .map(|u| u.id());
+
+        if !add {
+            self.selected.clear();
+        }
+        self.extend(hit);
+        hit.is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.selected.clear();
+    }
+
+    fn extend(&mut self, units: impl IntoIterator<Item = UnitId>) {
+        for id in units {
+            if !self.selected.contains(&id) {
+                self.selected.push(id);
+            }
+        }
+    }
+
+    /// Forgets units that died, fled or broke, in the selection and in every
+    /// control group.
+    pub fn prune(&mut self, simulation: &BattleSimulation) {
+        let controllable = |id: &UnitId| simulation.unit(*id).is_some_and(|u| self.controls(u));
+        let selected = self.selected.iter().copied().filter(controllable).collect();
+        let groups = self
+            .groups
+            .clone()
+            .map(|group| group.into_iter().filter(controllable).collect());
+        self.selected = selected;
+        self.groups = groups;
+    }
+
+    fn slot(group: usize) -> Option<usize> {
+        (1..=CONTROL_GROUPS).contains(&group).then(|| group - 1)
+    }
+
+    /// Saves the selection as control group `group` (Ctrl+number),
+    /// replacing what was in it.
+    pub fn assign_group(&mut self, group: usize) {
+        if let Some(slot) = Self::slot(group) {
+            self.groups[slot] = self.selected.clone();
+        }
+    }
+
+    /// Adds the selection to control group `group` (Ctrl+Shift+number).
+    pub fn add_to_group(&mut self, group: usize) {
+        if let Some(slot) = Self::slot(group) {
+            for &id in &self.selected {
+                if !self.groups[slot].contains(&id) {
+                    self.groups[slot].push(id);
+                }
+            }
+        }
+    }
+
+    /// Selects control group `group` (number key). Returns false if there
+    /// is nobody left in it.
+    pub fn recall_group(&mut self, simulation: &BattleSimulation, group: usize) -> bool {
+        self.prune(simulation);
+        let Some(slot) = Self::slot(group) else {
+            return false;
+        };
+        self.selected = self.groups[slot].clone();
+        !self.selected.is_empty()
+    }
+
+    /// The selected units that can still take orders.
+    pub fn selected_units(&self, simulation: &BattleSimulation) -> Vec<UnitId> {
+        self.selected
+            .iter()
+            .copied()
+            .filter(|&id| simulation.unit(id).is_some_and(|u| self.controls(u)))
+            .collect()
+    }
+
+    /// Sends the selection to `destination` keeping its shape, see
+    /// `BattleSimulation::group_move`. With `queued` set (Shift) the move
+    /// goes in after the orders the units already have.
+    pub fn move_to(&self, simulation: &BattleSimulation, destination: &Position, queued: bool) -> Vec<BattleCommand> {
+        simulation.group_move(&self.selected_units(simulation), destination, queued)
+    }
+
+    pub fn attack(&self, simulation: &BattleSimulation, target: UnitId, queued: bool) -> Vec<BattleCommand> {
+        self.selected_units(simulation)
+            .into_iter()
+            .map(|unit| {
+                if queued {
+                    BattleCommand::QueueAttack { unit, target }
+                } else {
+                    BattleCommand::Attack { unit, target }
+                }
+            })
+            .collect()
+    }
+
+    pub fn stop(&self, simulation: &BattleSimulation) -> Vec<BattleCommand> {
+        self.each(simulation, |unit| BattleCommand::Halt { unit })
+    }
+
+    pub fn hold_position(&self, simulation: &BattleSimulation) -> Vec<BattleCommand> {
+        self.each(simulation, |unit| BattleCommand::HoldPosition { unit })
+    }
+
+    pub fn set_formation(&self, simulation: &BattleSimulation, formation: Formation) -> Vec<BattleCommand> {
+        self.each(simulation, |unit| BattleCommand::SetFormation {
+            unit,
+            formation: formation.clone(),
+        })
+    }
+
+    pub fn withdraw(&self, simulation: &BattleSimulation) -> Vec<BattleCommand> {
+        self.each(simulation, |unit| BattleCommand::Withdraw { unit })
+    }
+
+    fn each(&self, simulation: &BattleSimulation, command: impl Fn(UnitId) -> BattleCommand) -> Vec<BattleCommand> {
+        self.selected_units(simulation).into_iter().map(command).collect()
+    }
+}
+
+impl BattleUnit {
+    /// Where the unit will stand once it has carried out its move orders.
+    pub fn final_destination(&self) -> Position {
+        self.waypoints
+            .iter()
+            .rev()
+            .find_map(|waypoint| match waypoint {
+                Waypoint::Move(destination) => Some(destination.clone()),
+                Waypoint::Attack(_) => None,
+            })
+            .or_else(|| self.move_target.clone())
+            .unwrap_or_else(|| self.position.clone())
+    }
+}
+
+impl BattleSimulation {
+    /// Move orders taking `units` to `destination` as a body: the middle of
+    /// the group ends up there and every unit keeps its place relative to
+    /// the others. Queued moves start from where the units will be once
+    /// their earlier moves are done, so a chain of waypoints keeps the shape
+    /// too.
+    pub fn group_move(&self, units: &[UnitId], destination: &Position, queued: bool) -> Vec<BattleCommand> {
+        let members: Vec<(UnitId, Position)> = units
+            .iter()
+            .filter_map(|&id| self.unit(id))
+            .filter(|u| u.can_fight())
+            .map(|u| {
+                let start = if queued { u.final_destination() } else { u.position.clone() };
+                (u.id(), start)
+            })
+            .collect();
+        if members.is_empty() {
+            return Vec::new();
+        }
+
+        let count = members.len() as f32;
+        let centre_x = members.iter().map(|(_, p)| p.x).sum::<f32>() / count;
+        let centre_y = members.iter().map(|(_, p)| p.y).sum::<f32>() / count;
+
+        members
+            .into_iter()
+            .map(|(unit, start)| {
+                let destination = Position::new(destination.x + start.x - centre_x, destination.y + start.y - centre_y);
+                if queued {
+                    BattleCommand::QueueMove { unit, destination }
+                } else {
+                    BattleCommand::Move { unit, destination }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn box_and_click_select_only_the_players_units() {
+        let mut simulation = open_field(1);
+        let left = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 40, 300.0, 100.0);
+        let right = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 40, 600.0, 100.0);
+        let enemy = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 40, 400.0, 150.0);
+        let mut selection = BattleSelection::new(BattleSide::Attacker, None);
+
+        selection.select_box(&simulation, &Position::new(250.0, 50.0), &Position::new(450.0, 200.0), false);
+        assert_eq!(selection.selected, vec![left]);
+
+        assert!(selection.select_at(&simulation, &Position::new(600.0, 100.0), true));
+        assert_eq!(selection.selected, vec![left, right]);
+
+        assert!(!selection.select_at(&simulation, &simulation.unit(enemy).unwrap().position.clone(), false));
+        assert!(selection.selected.is_empty());
+    }
+
+    #[test]
+    fn control_groups_are_saved_extended_and_recalled() {
+        let mut simulation = open_field(2);
+        let first = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 40, 300.0, 100.0);
+        let second = place(&mut simulation, BattleSide::Attacker, UnitType::Archers, 40, 600.0, 100.0);
+        let mut selection = BattleSelection::new(BattleSide::Attacker, None);
+
+        selection.selected = vec![first];
+        selection.assign_group(1);
+        selection.selected = vec![second];
+        selection.add_to_group(1);
+        selection.assign_group(CONTROL_GROUPS + 1);
+
+        selection.clear();
+        assert!(selection.recall_group(&simulation, 1));
+        assert_eq!(selection.selected, vec![first, second]);
+        assert!(!selection.recall_group(&simulation, 2));
+
+        simulation.unit_mut(second).unwrap().unit.count = 0;
+        assert!(selection.recall_group(&simulation, 1));
+        assert_eq!(selection.selected, vec![first]);
+    }
+
+    #[test]
+    fn shift_queues_moves_from_where_the_units_will_be() {
+        let mut simulation = open_field(3);
+        let left = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 40, 300.0, 100.0);
+        let right = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 40, 500.0, 100.0);
+        let enemy = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 40, 400.0, 700.0);
+        let mut selection = BattleSelection::new(BattleSide::Attacker, None);
+        selection.selected = vec![left, right];
+
+        let moves = selection.move_to(&simulation, &Position::new(400.0, 300.0), false);
+        simulation.queue_commands(moves);
+        simulation.step(BATTLE_TICK);
+
+        let queued = selection.move_to(&simulation, &Position::new(400.0, 500.0), true);
+        let destinations: Vec<(UnitId, f32, f32)> = queued
+            .iter()
+            .map(|command| match command {
+                BattleCommand::QueueMove { unit, destination } => (*unit, destination.x, destination.y),
+                other => panic!("not a queued move: {other:?}"),
+            })
+            .collect();
+        assert_eq!(destinations, vec![(left, 300.0, 500.0), (right, 500.0, 500.0)]);
+
+        assert!(selection
+            .attack(&simulation, enemy, true)
+            .iter()
+            .all(|c| matches!(c, BattleCommand::QueueAttack { target, .. } if *target == enemy)));
+        assert_eq!(selection.stop(&simulation).len(), 2);
+    }
+
+    #[test]
+    fn the_player_takes_the_side_the_ai_leaves_them() {
+        let mut simulation = open_field(4);
+        assert_eq!(BattleSelection::for_player(&simulation).side, BattleSide::Attacker);
+
+        simulation.enable_ai(BattleSide::Attacker);
+        let selection = BattleSelection::for_player(&simulation);
+        assert_eq!((selection.side, selection.faction), (BattleSide::Defender, None));
+
+        let ally = FactionId::default();
+        simulation.battle.join(BattleSide::Defender, ally).unwrap();
+        simulation.enable_contingent_ai(ally).unwrap();
+        let selection = BattleSelection::for_player(&simulation);
+        assert_eq!(selection.faction, Some(simulation.battle.defender));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::*;
+
+// ============================================================================
+// SIEGE ASSAULTS
+// ============================================================================
+
+/// Walls are built in stretches of this length, each one breached on its own.
+const WALL_SEGMENT_LENGTH: f32 = 100.0;
+
+/// Gates have this share of the strength of the wall around them.
+const GATE_STRENGTH_SHARE: f32 = 0.5;
+
+/// Men hammering on a gate do this much more than men hacking at stone,
+/// before battering rams are counted.
+const GATE_MELEE_MULTIPLIER: f32 = 4.0;
+
+/// Scales `attack * men` down to wall hit points per attack.
+const WALL_DAMAGE_DIVISOR: f32 = 50.0;
+
+/// Attackers line up this far in front of a breach before pushing through it.
+const BREACH_APPROACH_DISTANCE: f32 = 30.0;
+
+/// Morale lost by nearby defenders when a stretch of wall comes down.
+const WALL_BREACH_MORALE_LOSS: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WallKind {
+    Wall,
+    Gate,
+}
+
+/// A straight stretch of wall running along the x axis at `y`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WallSegment {
+    pub kind: WallKind,
+    pub min_x: f32,
+    pub max_x: f32,
+    pub y: f32,
+    pub strength: f32,
+    pub max_strength: f32,
+}
+
+impl WallSegment {
+    pub fn is_standing(&self) -> bool {
+        self.strength > 0.0
+    }
+
+    pub fn centre(&self) -> Position {
+        Position::new((self.min_x + self.max_x) / 2.0, self.y)
+    }
+
+    /// True when a straight walk from `from` to `to` passes through this stretch.
+    pub fn crosses(&self, from: &Position, to: &Position) -> bool {
+        if from.y == to.y || (from.y - self.y) * (to.y - self.y) > 0.0 {
+            return false;
+        }
+
+        let t = (self.y - from.y) / (to.y - from.y);
+        let x = from.x + (to.x - from.x) * t;
+        x >= self.min_x && x <= self.max_x
+    }
+
+    /// Returns true if this hit brought the segment down.
+    pub fn damage(&mut self, amount: f32) -> bool {
+        if !self.is_standing() {
+            return false;
+        }
+        self.strength = (self.strength - amount).max(0.0);
+        !self.is_standing()
+    }
+}
+
+/// Equipment the besiegers built before storming the walls.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiegeEquipment {
+    pub battering_rams: u32, // Each one adds the gate damage of a whole extra unit
+    pub siege_towers: u32,   // Each one carries one melee unit over the wall
+}
+
+impl UnitType {
+    /// How well this kind of unit knocks down walls. Only artillery does it
+    /// properly, infantry can at best break down a gate.
+    pub fn wall_damage_multiplier(&self) -> f32 {
+        match self {
+            UnitType::Infantry => 0.05,
+            UnitType::Cavalry => 0.02,
+            UnitType::Archers => 0.0,
+            UnitType::Artillery => 1.0,
+            UnitType::Special(_) => 0.1,
+        }
+    }
+}
+
+/// Wall hit points a force knocks down in `seconds` of bombardment. Only
+/// units with a ranged attack take part, and only some of their shots land.
+/// Errors if a unit isn't in the registry.
+pub fn bombardment_damage(units: &[Unit], registry: &UnitRegistry, seconds: f32) -> Result<f32, UnitRegistryError> {
+    let volleys = seconds / RANGED_ATTACK_INTERVAL;
+    let mut damage = 0.0;
+    for u in units {
+        let stats = registry.stats(&u.unit_type)?;
+        if stats.range > MELEE_RANGE {
+            damage += stats.attack as f32 * u.count as f32 * u.unit_type.wall_damage_multiplier() / WALL_DAMAGE_DIVISOR
+                * stats.accuracy;
+        }
+    }
+    Ok(damage * volleys)
+}
+
+/// Gate hit points a force's melee units break down in `seconds`, helped
+/// by however many battering rams they brought. Errors if a unit isn't in
+/// the registry.
+pub fn gate_damage(
+    units: &[Unit],
+    registry: &UnitRegistry,
+    battering_rams: u32,
+    seconds: f32,
+) -> Result<f32, UnitRegistryError> {
+    let blows = seconds / MELEE_ATTACK_INTERVAL;
+    let rams = GATE_MELEE_MULTIPLIER * (1 + battering_rams) as f32;
+    let mut damage = 0.0;
+    for u in units {
+        let stats = registry.stats(&u.unit_type)?;
+        if stats.range <= MELEE_RANGE {
+            damage += stats.attack as f32 * u.count as f32 * u.unit_type.wall_damage_multiplier() / WALL_DAMAGE_DIVISOR;
+        }
+    }
+    Ok(damage * rams * blows)
+}
+
+impl Battlefield {
+    pub fn has_walls(&self) -> bool {
+        !self.walls.is_empty()
+    }
+
+    /// Index of the first standing wall segment on the straight path from
+    /// `from` to `to`.
+    pub fn blocking_wall(&self, from: &Position, to: &Position) -> Option<usize> {
+        self.walls.iter().position(|w| w.is_standing() && w.crosses(from, to))
+    }
+}
+
+impl BattlefieldGenerator {
+    /// Battlefield for storming a fortified province. A wall runs across the
+    /// whole field in front of the fortifications, with a gate in front of
+    /// each of them. `wall_strength` is what each segment has left, so damage
+    /// done during the siege carries over into the assault.
+    pub fn siege_assault(province: &Province, seed: u64, wall_strength: f32) -> Battlefield {
+        let mut battlefield = Self::from_province(province, seed);
+        if wall_strength <= 0.0 {
+            return battlefield;
+        }
+
+        let fortifications: Vec<&TerrainEffect> = battlefield
+            .terrain_effects
+            .iter()
+            .filter(|e| matches!(e.effect_type, TerrainEffectType::Fortification { .. }))
+            .collect();
+        if fortifications.is_empty() {
+            return battlefield;
+        }
+
+        // The wall faces the attacker, so it follows the front edge of the works
+        let wall_y = fortifications
+            .iter()
+            .map(|e| e.position.y - e.radius)
+            .fold(battlefield.height, f32::min);
+        let gates: Vec<f32> = fortifications.iter().map(|e| e.position.x).collect();
+
+        let mut walls = Vec::new();
+        let mut min_x = 0.0;
+        while min_x < battlefield.width {
+            let max_x = (min_x + WALL_SEGMENT_LENGTH).min(battlefield.width);
+            let is_gate = gates.iter().any(|&x| x >= min_x && x < max_x);
+            let (kind, strength) = if is_gate {
+                (WallKind::Gate, wall_strength * GATE_STRENGTH_SHARE)
+            } else {
+                (WallKind::Wall, wall_strength)
+            };
+
+            walls.push(WallSegment {
+                kind,
+                min_x,
+                max_x,
+                y: wall_y,
+                strength,
+                max_strength: strength,
+            });
+            min_x = max_x;
+        }
+
+        battlefield.walls = walls;
+        battlefield
+    }
+}
+
+impl BattleSimulation {
+    /// Melee attackers walk to a breach if there is one, otherwise to the
+    /// nearest gate. Defenders and ranged units go where they like. Attackers
+    /// come from the low y edge, so "through" a wall means towards higher y.
+    pub(crate) fn route_around_walls(&self, index: usize, destination: Position) -> Position {
+        let unit = &self.units[index];
+        let battlefield = &self.battle.battlefield;
+        if !self.is_held_by_walls(index) || unit.side != BattleSide::Attacker || unit.has_ranged_attack() {
+            return destination;
+        }
+        if battlefield.blocking_wall(&unit.position, &destination).is_none() {
+            return destination;
+        }
+
+        let by_distance = |a: &&WallSegment, b: &&WallSegment| {
+            let a = a.centre().distance_to(&unit.position);
+            let b = b.centre().distance_to(&unit.position);
+            a.total_cmp(&b)
+        };
+        let breach = battlefield.walls.iter().filter(|w| !w.is_standing()).min_by(by_distance);
+        let gate = battlefield.walls.iter().filter(|w| w.kind == WallKind::Gate).min_by(by_distance);
+
+        if let Some(breach) = breach {
+            // Line up in front of the breach unless already able to walk through it
+            let centre = breach.centre();
+            let behind = Position::new(centre.x, centre.y + BREACH_APPROACH_DISTANCE);
+            if battlefield.blocking_wall(&unit.position, &behind).is_none() {
+                return behind;
+            }
+            return Position::new(centre.x, centre.y - BREACH_APPROACH_DISTANCE);
+        }
+
+        gate.map(|w| w.centre()).unwrap_or(destination)
+    }
+
+    /// Stops a unit that would walk through a standing wall this tick, the
+    /// defenders hold their walls rather than march out. A free siege tower
+    /// carries an attacking melee unit over instead.
+    pub(crate) fn stop_at_walls(&mut self, index: usize, moved: Position) -> Position {
+        if !self.is_held_by_walls(index) {
+            self.units[index].wall_target = None;
+            return moved;
+        }
+
+        let Some(segment) = self.battle.battlefield.blocking_wall(&self.units[index].position, &moved) else {
+            self.units[index].wall_target = None;
+            return moved;
+        };
+
+        let towers_in_use = self.units.iter().filter(|u| u.on_siege_tower).count() as u32;
+        let unit = &mut self.units[index];
+        let is_wall = self.battle.battlefield.walls[segment].kind == WallKind::Wall;
+        let tower_free = towers_in_use < self.siege_equipment.siege_towers;
+        if is_wall && unit.side == BattleSide::Attacker && !unit.has_ranged_attack() && tower_free {
+            unit.on_siege_tower = true;
+            unit.wall_target = None;
+            return moved;
+        }
+
+        unit.wall_target = Some(segment);
+        unit.position.clone()
+    }
+
+    fn is_held_by_walls(&self, index: usize) -> bool {
+        let unit = &self.units[index];
+        self.battle.battlefield.has_walls()
+            && unit.state == UnitState::Fighting
+            && !unit.on_siege_tower
+    }
+
+    /// Lets a unit that is ready to attack hit the walls instead of an enemy:
+    /// melee units stuck at a wall with nobody to fight, and artillery whose
+    /// target is behind one. Returns the cooldown used, if it attacked.
+    pub(crate) fn attack_walls(&mut self, index: usize) -> Option<f32> {
+        let unit = &self.units[index];
+        if unit.side != BattleSide::Attacker || !self.battle.battlefield.has_walls() {
+            return None;
+        }
+
+        if let Some(segment) = unit.wall_target {
+            let target_index = unit.target.and_then(|id| self.index_of(id));
+            let fighting = target_index.is_some_and(|t| self.can_reach_in_melee(index, t));
+            let standing = self.battle.battlefield.walls[segment].is_standing();
+            if standing && !unit.has_ranged_attack() && !fighting {
+                let damage = self.wall_damage(index, segment);
+                self.damage_wall(segment, damage);
+                return Some(MELEE_ATTACK_INTERVAL);
+            }
+        }
+
+        let unit = &self.units[index];
+        if !unit.has_ranged_attack() || unit.unit.unit_type.wall_damage_multiplier() <= 0.0 {
+            return None;
+        }
+        let target = unit.target.and_then(|id| self.unit(id))?;
+        let distance = unit.position.distance_to(&target.position);
+        if !unit.can_fire_at(distance) {
+            return None;
+        }
+        let segment = self.battle.battlefield.blocking_wall(&unit.position, &target.position)?;
+
+        let hit_chance = unit.stats.accuracy;
+        let damage = self.wall_damage(index, segment);
+        self.units[index].ammunition -= 1;
+        if self.rng.chance(hit_chance) {
+            self.damage_wall(segment, damage);
+        }
+        Some(RANGED_ATTACK_INTERVAL)
+    }
+
+    /// Close enough to fight hand to hand, with no standing wall in between.
+    pub(crate) fn can_reach_in_melee(&self, attacker_index: usize, defender_index: usize) -> bool {
+        let attacker = &self.units[attacker_index].position;
+        let defender = &self.units[defender_index].position;
+        attacker.distance_to(defender) <= MELEE_CONTACT_DISTANCE
+            && self.battle.battlefield.blocking_wall(attacker, defender).is_none()
+    }
+
+    fn wall_damage(&self, index: usize, segment: usize) -> f32 {
+        let unit = &self.units[index];
+        let wall = &self.battle.battlefield.walls[segment];
+        let mut damage = unit.stats.attack * unit.unit.count as f32 * unit.unit.unit_type.wall_damage_multiplier()
+            / WALL_DAMAGE_DIVISOR;
+
+        if wall.kind == WallKind::Gate && !unit.has_ranged_attack() {
+            damage *= GATE_MELEE_MULTIPLIER * (1 + self.siege_equipment.battering_rams) as f32;
+        }
+        damage
+    }
+
+    fn damage_wall(&mut self, segment: usize, damage: f32) {
+        let wall = &mut self.battle.battlefield.walls[segment];
+        if wall.damage(damage) {
+            let centre = wall.centre();
+            self.morale_shock(BattleSide::Defender, &centre, MORALE_CONTAGION_RADIUS, WALL_BREACH_MORALE_LOSS);
+        }
+    }
+}
@@ -0,0 +1,760 @@
+use crate::*;
+
+// ============================================================================
+// BATTLE SIMULATION
+// ============================================================================
+
+pub const BATTLE_TICK_RATE: f64 = 60.0;
+pub const BATTLE_TICK: f32 = 1.0 / BATTLE_TICK_RATE as f32;
+
+/// Two units closer than this are fighting hand to hand.
+pub const MELEE_CONTACT_DISTANCE: f32 = 20.0;
+
+pub(crate) const MELEE_ATTACK_INTERVAL: f32 = 1.0;
+pub(crate) const RANGED_ATTACK_INTERVAL: f32 = 3.0;
+
+/// A volley that lands further than this from its target misses, no matter
+/// what the hit roll says.
+const PROJECTILE_IMPACT_RADIUS: f32 = 30.0;
+
+/// Share of missed volleys into a melee that land on the shooter's own side.
+const FRIENDLY_FIRE_CHANCE: f32 = 0.3;
+
+/// Radians per second a unit in `Formation::Line` can wheel.
+const BASE_TURN_RATE: f32 = std::f32::consts::FRAC_PI_2;
+
+/// Units weigh up their choice of target again this often, in ticks.
+const RETARGET_INTERVAL: u64 = 30;
+
+/// A unit holding its position only steps out to fight enemies this close.
+const HOLD_ENGAGE_DISTANCE: f32 = 40.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnitState {
+    Fighting,
+    Routing,
+    Fled, // Ran off the battlefield, takes no further part in the battle
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BattleUnit {
+    pub unit: Unit,
+    pub stats: CombatStats,
+    pub side: BattleSide,
+    pub state: UnitState,
+    pub initial_count: u32,
+    pub position: Position,
+    pub facing: f32, // Radians, 0.0 faces +x
+    pub speed: f32,
+    pub range: f32,
+    pub min_range: f32,
+    pub ammunition: u32,
+    pub target: Option<UnitId>,
+    pub target_locked: bool, // Ordered to attack this target, the AI leaves it alone
+    pub move_target: Option<Position>,
+    pub hold_position: bool,
+    pub withdrawing: bool, // Falling back off the field in good order
+    pub attack_cooldown: f32,
+    pub wall_target: Option<usize>, // Wall segment the unit is stuck at during an assault
+    pub on_siege_tower: bool,
+    pub general: Option<General>, // Commands the units around this one
+    #[serde(default = "stamina::full_stamina")]
+    pub stamina: f32, // 0.0 to MAX_STAMINA
+    #[serde(default)]
+    pub run_up: f32, // Ground covered at charging speed without slowing down
+    #[serde(default)]
+    pub momentum: f32, // Speed of the run-up
+    #[serde(default = "cohesion::full_cohesion")]
+    pub cohesion: f32, // 0.0 to MAX_COHESION, how much of its formation's bonuses the unit gets
+    #[serde(default)]
+    pub formation_change: Option<FormationChange>,
+    #[serde(default)]
+    pub waypoints: VecDeque<Waypoint>, // Orders queued up after the current one
+    #[serde(default)]
+    pub allowed_formations: Vec<Formation>, // Empty for units not built from the registry, which may take up any
+    #[serde(default)]
+    pub tags: Vec<String>, // From the unit's definition
+}
+
+impl BattleUnit {
+    /// Creates a battle unit of a built-in type in any formation, for tests.
+    /// Everything else builds units from the registry with `from_registry`.
+    #[cfg(test)]
+    pub fn new(unit: Unit, side: BattleSide, position: Position) -> Self {
+        let registry = UnitRegistry::built_in();
+        let definition = registry.definition(&unit.unit_type).expect("a built-in unit type");
+        let mut battle_unit = Self::with_stats(unit, &definition.stats, side, position);
+        battle_unit.tags = definition.tags.clone();
+        battle_unit
+    }
+
+    pub fn from_registry(
+        registry: &UnitRegistry,
+        unit: Unit,
+        side: BattleSide,
+        position: Position,
+    ) -> Result<Self, UnitRegistryError> {
+        registry.check_formation(&unit.unit_type, &unit.formation)?;
+        let definition = registry.definition(&unit.unit_type)?;
+        let mut battle_unit = Self::with_stats(unit, &definition.stats, side, position);
+        battle_unit.allowed_formations = definition.allowed_formations.clone();
+        battle_unit.tags = definition.tags.clone();
+        Ok(battle_unit)
+    }
+
+    pub fn with_stats(unit: Unit, unit_stats: &UnitStats, side: BattleSide, position: Position) -> Self {
+        // Both armies start out facing each other across the field
+        let facing = match side {
+            BattleSide::Attacker => std::f32::consts::FRAC_PI_2,
+            BattleSide::Defender => -std::f32::consts::FRAC_PI_2,
+        };
+
+        Self {
+            stats: CombatStats::from_stats(unit_stats),
+            side,
+            state: UnitState::Fighting,
+            initial_count: unit.count,
+            position,
+            facing,
+            speed: unit_stats.speed,
+            range: unit_stats.range,
+            min_range: unit_stats.min_range,
+            ammunition: unit_stats.ammunition,
+            target: None,
+            target_locked: false,
+            move_target: None,
+            hold_position: false,
+            withdrawing: false,
+            attack_cooldown: 0.0,
+            wall_target: None,
+            on_siege_tower: false,
+            general: None,
+            stamina: MAX_STAMINA,
+            run_up: 0.0,
+            momentum: 0.0,
+            cohesion: MAX_COHESION,
+            formation_change: None,
+            waypoints: VecDeque::new(),
+            allowed_formations: Vec::new(),
+            tags: Vec::new(),
+            unit,
+        }
+    }
+
+    pub fn id(&self) -> UnitId {
+        self.unit.id
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.unit.count > 0
+    }
+
+    /// Still on the battlefield, either fighting or running away.
+    pub fn is_active(&self) -> bool {
+        self.is_alive() && self.state != UnitState::Fled
+    }
+
+    pub fn can_fight(&self) -> bool {
+        self.is_alive() && self.state == UnitState::Fighting
+    }
+
+    pub fn casualties(&self) -> u32 {
+        self.initial_count.saturating_sub(self.unit.count)
+    }
+
+    /// Ranged units that ran out of ammunition fight in melee.
+    pub fn has_ranged_attack(&self) -> bool {
+        self.range > MELEE_RANGE && self.ammunition > 0
+    }
+
+    pub fn can_fire_at(&self, distance: f32) -> bool {
+        self.has_ranged_attack() && distance >= self.min_range && distance <= self.range
+    }
+
+    /// Heavier, slower formations take longer to wheel around.
+    pub fn turn_rate(&self) -> f32 {
+        BASE_TURN_RATE * self.formation_modifiers().speed_modifier
+    }
+
+    /// Rotates towards `heading`, by at most what the unit can turn in `delta_time`.
+    pub fn turn_towards(&mut self, heading: f32, delta_time: f32) {
+        let difference = normalize_angle(heading - self.facing);
+        let max_turn = self.turn_rate() * delta_time;
+        self.facing = normalize_angle(self.facing + difference.clamp(-max_turn, max_turn));
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Projectile {
+    pub shooter: UnitId,
+    pub side: BattleSide,
+    pub target: UnitId,
+    pub position: Position,
+    pub destination: Position,
+    pub speed: f32,
+    pub direction: AttackDirection,
+    pub damage: u32,
+    pub hit_chance: f32,
+}
+
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct BattleSimulation {
+    pub battle: Battle,
+    pub units: Vec<BattleUnit>,
+    pub projectiles: Vec<Projectile>,
+    pub seed: u64,
+    pub rng: BattleRng,
+    pub tick: u64,
+    pub elapsed: f32,
+    pub deployment: DeploymentState,
+    pub outcome: Option<BattleOutcome>,
+    pub siege_equipment: SiegeEquipment,
+    pub pending_commands: Vec<BattleCommand>,
+    pub last_commands: Vec<TimedCommand>, // Carried out during the latest tick
+    pub tactical_ai: Vec<TacticalAI>,
+    #[serde(default)]
+    pub reinforcements: Vec<Reinforcement>, // Still on their way
+    #[serde(default)]
+    pub last_arrivals: Vec<ReinforcementArrival>, // Came onto the field during the latest tick
+    #[serde(default)]
+    pub last_charges: Vec<ChargeImpact>, // Went in during the latest tick
+    #[serde(default)]
+    pub prisoners: Vec<PrisonerGroup>, // Taken so far
+    #[serde(skip)]
+    pub(crate) spatial: SpatialIndex,
+}
+
+impl BattleSimulation {
+    pub fn new(battle: Battle, seed: u64) -> Self {
+        Self {
+            battle,
+            units: Vec::new(),
+            projectiles: Vec::new(),
+            seed,
+            rng: BattleRng::new(seed),
+            tick: 0,
+            elapsed: 0.0,
+            deployment: DeploymentState::default(),
+            outcome: None,
+            siege_equipment: SiegeEquipment::default(),
+            pending_commands: Vec::new(),
+            last_commands: Vec::new(),
+            tactical_ai: Vec::new(),
+            reinforcements: Vec::new(),
+            last_arrivals: Vec::new(),
+            last_charges: Vec::new(),
+            prisoners: Vec::new(),
+            spatial: SpatialIndex::default(),
+        }
+    }
+
+    pub fn add_unit(&mut self, unit: BattleUnit) {
+        match unit.side {
+            BattleSide::Attacker => self.battle.attacker_units.push(unit.id()),
+            BattleSide::Defender => self.battle.defender_units.push(unit.id()),
+        }
+        self.units.push(unit);
+    }
+
+    pub fn unit(&self, id: UnitId) -> Option<&BattleUnit> {
+        self.index_of(id).map(|i| &self.units[i])
+    }
+
+    pub fn unit_mut(&mut self, id: UnitId) -> Option<&mut BattleUnit> {
+        self.index_of(id).map(|i| &mut self.units[i])
+    }
+
+    pub(crate) fn index_of(&self, id: UnitId) -> Option<usize> {
+        self.lookup_index(id)
+    }
+
+    /// Advances the battle by one tick. Units are always processed in the same
+    /// order and every random roll goes through `rng`, so a battle started
+    /// from the same state and seed plays out identically.
+    pub fn step(&mut self, delta_time: f32) {
+        if !matches!(self.battle.phase, BattlePhase::Combat) {
+            return;
+        }
+
+        self.arrive_reinforcements();
+        self.rebuild_spatial_index();
+        self.apply_pending_commands();
+        self.follow_waypoints();
+        self.run_tactical_ai();
+        self.acquire_targets();
+        self.move_units(delta_time);
+        self.rebuild_spatial_index();
+        self.finish_withdrawals();
+        self.update_formations(delta_time);
+        self.resolve_attacks(delta_time);
+        self.update_projectiles(delta_time);
+        self.update_morale(delta_time);
+        self.update_capture_points(delta_time);
+
+        self.tick += 1;
+        self.elapsed += delta_time;
+
+        self.check_battle_end();
+    }
+
+    fn acquire_targets(&mut self) {
+        // How many units are going for each unit, so a side can spread out
+        let mut attackers = vec![0usize; self.units.len()];
+        for unit in self.units.iter().filter(|u| u.can_fight()) {
+            if let Some(target) = unit.target.and_then(|id| self.index_of(id)) {
+                attackers[target] += 1;
+            }
+        }
+
+        for i in 0..self.units.len() {
+            if !self.units[i].can_fight() {
+                continue;
+            }
+
+            let current_target = self.units[i].target.and_then(|id| self.index_of(id));
+            let current_target_active = current_target.is_some_and(|t| self.units[t].is_active());
+            if !current_target_active {
+                self.units[i].target_locked = false;
+            }
+
+            // Ordered targets are kept, and so are enemies already fought
+            // hand to hand. The rest are reconsidered now and then, a few
+            // units each tick. Units holding their ground keep watching for
+            // anyone closing in.
+            let unit = &self.units[i];
+            let in_melee = current_target.is_some_and(|t| {
+                !unit.has_ranged_attack() && self.units[t].can_fight() && self.can_reach_in_melee(i, t)
+            });
+            let due = (self.tick + i as u64).is_multiple_of(RETARGET_INTERVAL);
+            let retarget = (due || unit.hold_position) && !unit.target_locked && !in_melee;
+            if current_target_active && !retarget {
+                continue;
+            }
+
+            // Routing enemies stay valid targets, that's how pursuit happens
+            let mut candidates = self.target_candidates(i);
+
+            // A unit holding its ground deals with whoever comes up to it first
+            let close = |&c: &usize| self.units[c].position.distance_to(&unit.position) <= HOLD_ENGAGE_DISTANCE;
+            if unit.hold_position && candidates.iter().any(close) {
+                candidates.retain(close);
+            }
+
+            let enemies: Vec<&BattleUnit> = candidates.iter().map(|&c| &self.units[c]).collect();
+            let target = BattleAI::choose_target(unit, &enemies, |id| {
+                let others = self.index_of(id).map_or(0, |t| attackers[t]);
+                others - usize::from(current_target.is_some_and(|c| self.units[c].id() == id))
+            });
+
+            if let Some(old) = current_target {
+                attackers[old] -= 1;
+            }
+            if let Some(new) = target.and_then(|id| self.index_of(id)) {
+                attackers[new] += 1;
+            }
+            self.units[i].target = target;
+        }
+    }
+
+    fn move_units(&mut self, delta_time: f32) {
+        for i in 0..self.units.len() {
+            if !self.units[i].is_active() {
+                continue;
+            }
+
+            let destination = self.desired_position(i);
+            if let Some(heading) = self.desired_heading(i, destination.as_ref()) {
+                self.units[i].turn_towards(heading, delta_time);
+            }
+
+            let mut covered = 0.0;
+            if let Some(destination) = destination {
+                let destination = self.route_around_walls(i, destination);
+                let unit = &self.units[i];
+                let mut speed = unit.speed * unit.formation_modifiers().speed_modifier;
+                if unit.state == UnitState::Routing {
+                    speed *= ROUT_SPEED_MULTIPLIER;
+                }
+                speed *= unit.fatigue().speed_multiplier * self.conditions().movement;
+                let moved = self.battle.battlefield.move_towards(&unit.position, &destination, speed, delta_time);
+                let moved = self.stop_at_walls(i, moved);
+                let moved = self.battle.battlefield.clamp(&moved);
+                covered = moved.distance_to(&self.units[i].position);
+                self.units[i].position = moved;
+
+                let arrived = self.units[i]
+                    .move_target
+                    .as_ref()
+                    .is_some_and(|t| t.distance_to(&self.units[i].position) < 1.0);
+                if arrived {
+                    self.units[i].move_target = None;
+                }
+            }
+            self.exert_moving(i, covered > 0.0, delta_time);
+            self.keep_ranks(i, covered > 0.0, delta_time);
+            self.build_momentum(i, covered, delta_time);
+        }
+    }
+
+    /// Where a unit wants to go this tick: an explicit move order first,
+    /// otherwise into firing range (and out of minimum range) or into melee.
+    /// A unit holding its position stays put unless an enemy comes right up
+    /// to it.
+    fn desired_position(&self, index: usize) -> Option<Position> {
+        let unit = &self.units[index];
+        if unit.state == UnitState::Routing || unit.withdrawing {
+            return Some(self.rout_destination(index));
+        }
+
+        // Men shuffling into their new places stand where they are
+        if unit.is_reforming() {
+            return None;
+        }
+
+        if let Some(move_target) = &unit.move_target {
+            return Some(move_target.clone());
+        }
+
+        let target = unit.target.and_then(|id| self.unit(id))?;
+        let distance = unit.position.distance_to(&target.position);
+
+        if unit.hold_position {
+            let engage = !unit.has_ranged_attack() && distance <= HOLD_ENGAGE_DISTANCE;
+            return engage.then(|| target.position.clone());
+        }
+
+        if unit.has_ranged_attack() {
+            // Out of range or behind a ridge: go and find a spot to shoot from
+            if distance > self.firing_range(index, &target.position) || !self.can_see(index, &target.position) {
+                return Some(target.position.clone());
+            }
+            if distance < unit.min_range {
+                // Step straight back, away from the target
+                let away = Position::new(
+                    unit.position.x * 2.0 - target.position.x,
+                    unit.position.y * 2.0 - target.position.y,
+                );
+                return Some(away);
+            }
+            return None;
+        }
+
+        if distance > MELEE_CONTACT_DISTANCE {
+            Some(target.position.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Units face an enemy they can already strike, otherwise the way they walk.
+    fn desired_heading(&self, index: usize, destination: Option<&Position>) -> Option<f32> {
+        let unit = &self.units[index];
+
+        let target = unit.target.and_then(|id| self.unit(id)).filter(|_| unit.can_fight());
+        if let Some(target) = target {
+            let reach = if unit.has_ranged_attack() {
+                self.firing_range(index, &target.position)
+            } else {
+                MELEE_CONTACT_DISTANCE
+            };
+            if unit.position.distance_to(&target.position) <= reach {
+                return Some(heading_between(&unit.position, &target.position));
+            }
+        }
+
+        destination
+            .filter(|d| d.distance_to(&unit.position) > 0.0)
+            .map(|d| heading_between(&unit.position, d))
+    }
+
+    fn resolve_attacks(&mut self, delta_time: f32) {
+        self.last_charges.clear();
+        for i in 0..self.units.len() {
+            if !self.units[i].can_fight() || self.units[i].is_reforming() {
+                continue;
+            }
+
+            self.units[i].attack_cooldown = (self.units[i].attack_cooldown - delta_time).max(0.0);
+            if self.units[i].attack_cooldown > 0.0 {
+                continue;
+            }
+
+            if let Some(cooldown) = self.attack_walls(i) {
+                self.units[i].attack_cooldown = cooldown;
+                continue;
+            }
+
+            let Some(target_index) = self.units[i].target.and_then(|id| self.index_of(id)) else {
+                continue;
+            };
+            if !self.units[target_index].is_active() {
+                continue;
+            }
+
+            let target = &self.units[target_index].position;
+            let distance = self.units[i].position.distance_to(target);
+            let in_sight = distance <= self.firing_range(i, target) && self.can_see(i, target);
+            if self.units[i].can_fire_at(distance) && in_sight {
+                self.fire_volley(i, target_index, distance);
+                self.units[i].attack_cooldown = RANGED_ATTACK_INTERVAL;
+            } else if !self.units[i].has_ranged_attack() && self.can_reach_in_melee(i, target_index) {
+                let direction = self.attack_direction(i, target_index);
+                let casualties = self.damage_between(i, target_index, direction);
+                let casualties = self.resolve_charge(i, target_index, casualties);
+                let count_before = self.units[target_index].unit.count;
+                self.apply_hit(Some(i), target_index, casualties, direction);
+                self.take_prisoners(i, target_index, count_before - self.units[target_index].unit.count);
+                self.exert_fighting(i);
+                self.melee_disorder(i, target_index);
+                self.units[i].attack_cooldown = MELEE_ATTACK_INTERVAL;
+            }
+        }
+    }
+
+    fn attack_direction(&self, attacker_index: usize, defender_index: usize) -> AttackDirection {
+        let attacker = &self.units[attacker_index];
+        let defender = &self.units[defender_index];
+        AttackDirection::from_positions(defender.facing, &defender.position, &attacker.position)
+    }
+
+    fn damage_between(&self, attacker_index: usize, defender_index: usize, direction: AttackDirection) -> u32 {
+        let attacker = &self.units[attacker_index];
+        let defender = &self.units[defender_index];
+        let battlefield = &self.battle.battlefield;
+
+        // Holding the high ground counts against attacks from below it
+        let mut defender_terrain = battlefield.modifiers_at(&defender.position);
+        defender_terrain.defense_bonus += battlefield.elevation_defense_bonus(&attacker.position, &defender.position);
+
+        let damage = CombatSystem::calculate_damage_on_terrain(
+            &attacker.unit,
+            &self.commanded_stats(attacker_index),
+            &battlefield.modifiers_at(&attacker.position),
+            &defender.unit,
+            &self.commanded_stats(defender_index),
+            &defender_terrain,
+        );
+
+        let flank = defender.unit.formation.flank_modifiers(direction);
+        let mut damage = damage as f32 * flank.damage_multiplier;
+
+        // A routing unit doesn't defend itself, it gets cut down as it runs
+        if defender.state == UnitState::Routing {
+            damage *= ROUTING_DAMAGE_MULTIPLIER;
+        }
+        if defender.is_reforming() {
+            damage *= REFORMING_DAMAGE_MULTIPLIER;
+        }
+
+        damage as u32
+    }
+
+    pub(crate) fn apply_hit(&mut self, attacker_index: Option<usize>, defender_index: usize, casualties: u32, direction: AttackDirection) {
+        let command = self.commander_bonuses(defender_index).map_or(1.0, |b| b.morale_multiplier);
+        let defender = &mut self.units[defender_index].unit;
+        let count_before = defender.count;
+        let morale_before = defender.morale;
+        let killed = casualties.min(defender.count);
+        CombatSystem::apply_casualties(defender, casualties);
+
+        // Being hit in the flank or rear shakes a unit more than the losses alone
+        let flank = defender.formation.flank_modifiers(direction);
+        defender.morale = (defender.morale - flank.morale_loss).max(0.0);
+
+        // Men who can see their general take it better
+        defender.morale = morale_before - (morale_before - defender.morale) / command;
+
+        if let Some(attacker_index) = attacker_index {
+            self.units[attacker_index].unit.gain_experience(killed * EXPERIENCE_PER_KILL);
+        }
+        self.check_general_casualty(defender_index, killed, count_before);
+    }
+
+    fn fire_volley(&mut self, shooter_index: usize, target_index: usize, distance: f32) {
+        let direction = self.attack_direction(shooter_index, target_index);
+        let damage = self.damage_between(shooter_index, target_index, direction);
+        let shooter = &self.units[shooter_index];
+        let target = &self.units[target_index];
+
+        let hit_chance = CombatSystem::hit_chance(
+            &shooter.stats,
+            &target.stats,
+            &self.battle.battlefield.modifiers_at(&target.position),
+            distance,
+            shooter.range,
+        );
+
+        // Rain, fog and darkness spoil the aim, and wet powder the guns too
+        let conditions = self.conditions();
+        let artillery = match shooter.unit.unit_type {
+            UnitType::Artillery => conditions.artillery,
+            _ => 1.0,
+        };
+        let elevation = self
+            .battle
+            .battlefield
+            .elevation_accuracy_multiplier(&shooter.position, &target.position);
+        let hit_chance = hit_chance * conditions.ranged_accuracy * artillery * elevation;
+        let damage = (damage as f32 * artillery) as u32;
+
+        let projectile = Projectile {
+            shooter: shooter.id(),
+            side: shooter.side,
+            target: target.id(),
+            position: shooter.position.clone(),
+            destination: target.position.clone(),
+            speed: Self::projectile_speed(&shooter.unit.unit_type),
+            direction,
+            damage,
+            hit_chance,
+        };
+
+        self.units[shooter_index].ammunition -= 1;
+        self.projectiles.push(projectile);
+    }
+
+    fn projectile_speed(unit_type: &UnitType) -> f32 {
+        match unit_type {
+            UnitType::Artillery => 400.0,
+            _ => 250.0,
+        }
+    }
+
+    fn update_projectiles(&mut self, delta_time: f32) {
+        let mut impacts = Vec::new();
+        let mut flying = Vec::with_capacity(self.projectiles.len());
+
+        for mut projectile in std::mem::take(&mut self.projectiles) {
+            let distance = projectile.position.distance_to(&projectile.destination);
+            let step = projectile.speed * delta_time;
+            if distance <= step {
+                projectile.position = projectile.destination.clone();
+                impacts.push(projectile);
+            } else {
+                let ratio = step / distance;
+                projectile.position.x += (projectile.destination.x - projectile.position.x) * ratio;
+                projectile.position.y += (projectile.destination.y - projectile.position.y) * ratio;
+                flying.push(projectile);
+            }
+        }
+        self.projectiles = flying;
+
+        // Resolve in firing order to keep the rng sequence stable
+        for projectile in impacts {
+            self.resolve_impact(&projectile);
+        }
+    }
+
+    fn resolve_impact(&mut self, projectile: &Projectile) {
+        let Some(target_index) = self.index_of(projectile.target) else {
+            return;
+        };
+
+        let on_target = self.units[target_index].is_active()
+            && self.units[target_index].position.distance_to(&projectile.destination) <= PROJECTILE_IMPACT_RADIUS;
+        if on_target && self.rng.chance(projectile.hit_chance) {
+            let shooter_index = self.index_of(projectile.shooter);
+            self.apply_hit(shooter_index, target_index, projectile.damage, projectile.direction);
+            return;
+        }
+
+        // A volley that misses a target locked in melee may come down on the
+        // friendly troops it is fighting
+        if !self.is_engaged_in_melee(projectile.target) {
+            return;
+        }
+        let engaged_friend = self
+            .units_within(
+                projectile.side,
+                &projectile.destination,
+                MELEE_CONTACT_DISTANCE + PROJECTILE_IMPACT_RADIUS,
+            )
+            .into_iter()
+            .filter(|&i| self.units[i].is_active())
+            .min();
+        if let Some(friend_index) = engaged_friend {
+            if self.rng.chance(FRIENDLY_FIRE_CHANCE) {
+                CombatSystem::apply_casualties(&mut self.units[friend_index].unit, projectile.damage);
+            }
+        }
+    }
+
+    /// True when an enemy is close enough to be fighting this unit hand to hand.
+    pub fn is_engaged_in_melee(&self, id: UnitId) -> bool {
+        let Some(unit) = self.unit(id) else {
+            return false;
+        };
+
+        self.units_within(unit.side.opponent(), &unit.position, MELEE_CONTACT_DISTANCE)
+            .into_iter()
+            .any(|i| self.units[i].can_fight())
+    }
+}
+
+// ============================================================================
+// BATTLE PLUGIN
+// ============================================================================
+
+pub struct BattlePlugin;
+
+impl Plugin for BattlePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(BATTLE_TICK_RATE))
+            .init_resource::<UnitRegistry>()
+            .init_resource::<BattleClock>()
+            .init_asset::<UnitDefinitions>()
+            .init_asset_loader::<UnitDefinitionsLoader>()
+            .add_event::<ReinforcementsArrived>()
+            .add_systems(Startup, load_unit_definitions)
+            .add_systems(Update, sync_unit_registry)
+            .add_systems(Update, advance_replay.run_if(resource_exists::<ReplayPlayer>))
+            .add_systems(
+                FixedUpdate,
+                (
+                    begin_battle.run_if(resource_added::<BattleSimulation>),
+                    step_battle_simulation,
+                    record_battle.run_if(resource_exists::<BattleRecorder>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Battle))
+                    .run_if(resource_exists::<BattleSimulation>),
+            );
+
+        println!("Battle Plugin loaded - Battle simulation systems initialized");
+    }
+}
+
+/// Starts recording a battle as soon as it is put in place, so every
+/// battle fought can be watched again (the recorder keeps its starting
+/// state current until deployment is over), and hands the player a fresh
+/// selection for it. The clock starts running at normal speed whatever
+/// the last battle was left at.
+fn begin_battle(mut commands: Commands, simulation: Res<BattleSimulation>) {
+    commands.insert_resource(BattleRecorder::start(&simulation));
+    commands.insert_resource(BattleSelection::for_player(&simulation));
+    commands.insert_resource(BattleClock::default());
+}
+
+/// Steps the battle as many ticks as `BattleClock` says are due. The
+/// recorder captures each one, so nothing given in a tick goes unrecorded
+/// when several run in one update.
+fn step_battle_simulation(
+    mut simulation: ResMut<BattleSimulation>,
+    mut clock: ResMut<BattleClock>,
+    mut recorder: Option<ResMut<BattleRecorder>>,
+    mut arrivals: EventWriter<ReinforcementsArrived>,
+) {
+    for _ in 0..clock.ticks_due() {
+        if simulation.outcome.is_some() {
+            break;
+        }
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.prepare(&simulation);
+        }
+        simulation.step(BATTLE_TICK);
+        arrivals.send_batch(simulation.last_arrivals.iter().cloned().map(ReinforcementsArrived));
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.capture(&simulation);
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use crate::*;
+
+// ============================================================================
+// SPATIAL INDEX
+// ============================================================================
+
+/// Side length of a grid cell. A little over twice melee contact distance,
+/// so most melee lookups only touch the nine cells around a unit.
+pub const GRID_CELL_SIZE: f32 = 50.0;
+
+/// How many of the closest enemies a unit weighs up when picking a target.
+const MAX_TARGET_CANDIDATES: usize = 16;
+
+/// Uniform grid over the battlefield holding the index of every unit in the
+/// cell its position falls in. Points are stored cell by cell in the order
+/// they were added, so queries visit them in the same order on every run.
+#[derive(Debug, Clone, Default)]
+pub struct SpatialGrid {
+    columns: usize,
+    rows: usize,
+    cell_starts: Vec<u32>, // Where each cell's entries begin, plus one past the end
+    entries: Vec<u32>,     // Slots into `indices` and `positions`, cell by cell
+    indices: Vec<usize>,
+    positions: Vec<Position>,
+}
+
+impl SpatialGrid {
+    /// Rebuilds the grid for a `width` by `height` field from `(index,
+    /// position)` pairs. Points outside the field are filed in the nearest
+    /// edge cell.
+    pub fn rebuild<'a>(&mut self, width: f32, height: f32, points: impl Iterator<Item = (usize, &'a Position)>) {
+        self.columns = ((width / GRID_CELL_SIZE).ceil() as usize).max(1);
+        self.rows = ((height / GRID_CELL_SIZE).ceil() as usize).max(1);
+        self.indices.clear();
+        self.positions.clear();
+        for (index, position) in points {
+            self.indices.push(index);
+            self.positions.push(position.clone());
+        }
+
+        // Counting sort: count per cell, turn counts into offsets, then fill
+        let cells = self.columns * self.rows;
+        self.cell_starts.clear();
+        self.cell_starts.resize(cells + 1, 0);
+        for slot in 0..self.positions.len() {
+            let cell = self.cell_index(&self.positions[slot]);
+            self.cell_starts[cell + 1] += 1;
+        }
+        for cell in 0..cells {
+            self.cell_starts[cell + 1] += self.cell_starts[cell];
+        }
+
+        let mut next = self.cell_starts.clone();
+        self.entries.clear();
+        self.entries.resize(self.positions.len(), 0);
+        for slot in 0..self.positions.len() {
+            let cell = self.cell_index(&self.positions[slot]);
+            self.entries[next[cell] as usize] = slot as u32;
+            next[cell] += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Indices of every point within `radius` of `position`.
+    pub fn within_radius<'a>(&'a self, position: &'a Position, radius: f32) -> impl Iterator<Item = usize> + 'a {
+        self.slots_within(position, radius).map(|slot| self.indices[slot])
+    }
+
+    /// Indices of every point within `radius` of `position` and no more than
+    /// `half_angle` radians either side of `heading`.
+    pub fn within_cone<'a>(
+        &'a self,
+        position: &'a Position,
+        heading: f32,
+        half_angle: f32,
+        radius: f32,
+    ) -> impl Iterator<Item = usize> + 'a {
+        self.slots_within(position, radius)
+            .filter(move |&slot| in_cone(position, &self.positions[slot], heading, half_angle))
+            .map(|slot| self.indices[slot])
+    }
+
+    /// Closest point to `position` that `filter` accepts, no further away
+    /// than `max_radius`. Ties go to the lower index.
+    pub fn nearest(&self, position: &Position, max_radius: f32, filter: impl FnMut(usize) -> bool) -> Option<usize> {
+        self.nearest_n(position, max_radius, 1, filter).into_iter().next()
+    }
+
+    /// Up to `count` of the points closest to `position` that `filter`
+    /// accepts, closest first. Searches outwards ring by ring, so only the
+    /// cells that can still hold something closer get looked at.
+    pub fn nearest_n(
+        &self,
+        position: &Position,
+        max_radius: f32,
+        count: usize,
+        mut filter: impl FnMut(usize) -> bool,
+    ) -> Vec<usize> {
+        let mut found: Vec<(f32, usize)> = Vec::new();
+        if count == 0 || self.is_empty() {
+            return Vec::new();
+        }
+
+        let (column, row) = self.cell_of(position);
+        let max_ring = self.columns.max(self.rows);
+        for ring in 0..=max_ring {
+            // A point in this ring can be as close as the far side of the
+            // ring inside it, anything in a further ring at least this far
+            if ring.saturating_sub(1) as f32 * GRID_CELL_SIZE > max_radius {
+                break;
+            }
+            let closest_unsearched = ring as f32 * GRID_CELL_SIZE;
+
+            for slot in self.ring(column, row, ring) {
+                let distance = self.positions[slot].distance_to(position);
+                let index = self.indices[slot];
+                if distance <= max_radius && filter(index) {
+                    found.push((distance, index));
+                }
+            }
+
+            // Keep the closest `count`, ties broken by index so the pick is
+            // the same whichever order the cells were visited in
+            if found.len() > count {
+                found.select_nth_unstable_by(count - 1, closer);
+                found.truncate(count);
+            }
+            if found.len() == count && found.iter().all(|&(distance, _)| distance <= closest_unsearched) {
+                break;
+            }
+        }
+
+        found.sort_by(closer);
+        found.into_iter().map(|(_, index)| index).collect()
+    }
+
+    fn slots_within<'a>(&'a self, position: &'a Position, radius: f32) -> impl Iterator<Item = usize> + 'a {
+        let (min_column, min_row) = self.cell_of(&Position::new(position.x - radius, position.y - radius));
+        let (max_column, max_row) = self.cell_of(&Position::new(position.x + radius, position.y + radius));
+
+        (min_row..=max_row)
+            .flat_map(move |row| (min_column..=max_column).flat_map(move |column| self.cell(column, row)))
+            .filter(move |&slot| self.positions[slot].distance_to(position) <= radius)
+    }
+
+    fn cell_of(&self, position: &Position) -> (usize, usize) {
+        let column = (position.x / GRID_CELL_SIZE).floor().clamp(0.0, (self.columns - 1) as f32) as usize;
+        let row = (position.y / GRID_CELL_SIZE).floor().clamp(0.0, (self.rows - 1) as f32) as usize;
+        (column, row)
+    }
+
+    fn cell_index(&self, position: &Position) -> usize {
+        let (column, row) = self.cell_of(position);
+        row * self.columns + column
+    }
+
+    fn cell(&self, column: usize, row: usize) -> impl Iterator<Item = usize> + '_ {
+        let cell = row * self.columns + column;
+        let (start, end) = (self.cell_starts[cell] as usize, self.cell_starts[cell + 1] as usize);
+        self.entries[start..end].iter().map(|&slot| slot as usize)
+    }
+
+    /// Points in the cells exactly `ring` cells away from the given one.
+    fn ring(&self, column: usize, row: usize, ring: usize) -> impl Iterator<Item = usize> + '_ {
+        let (column, row, ring) = (column as isize, row as isize, ring as isize);
+        let (columns, rows) = (self.columns as isize, self.rows as isize);
+
+        (row - ring..=row + ring)
+            .filter(move |r| (0..rows).contains(r))
+            .flat_map(move |r| {
+                // Edge rows take the whole span, the rows between only the two ends
+                let edge = (r - row).abs() == ring;
+                let step = if edge || ring == 0 { 1 } else { (2 * ring) as usize };
+                (column - ring..=column + ring)
+                    .step_by(step)
+                    .filter(move |c| (0..columns).contains(c))
+                    .map(move |c| (c as usize, r as usize))
+            })
+            .flat_map(move |(c, r)| self.cell(c, r))
+    }
+}
+
+fn closer(a: &(f32, usize), b: &(f32, usize)) -> std::cmp::Ordering {
+    a.0.total_cmp(&b.0).then(a.1.cmp(&b.1))
+}
+
+fn in_cone(origin: &Position, point: &Position, heading: f32, half_angle: f32) -> bool {
+    point.distance_to(origin) == 0.0 || normalize_angle(heading_between(origin, point) - heading).abs() <= half_angle
+}
+
+/// Lookups the simulation rebuilds every tick so it doesn't have to scan
+/// every unit for every unit: a grid per side, unit ids to indices, and
+/// which units carry a general. Most questions are about one side only, so
+/// keeping the sides apart saves wading through a unit's own ranks to find
+/// the enemy.
+#[derive(Debug, Clone, Default)]
+pub struct SpatialIndex {
+    pub attackers: SpatialGrid,
+    pub defenders: SpatialGrid,
+    by_id: HashMap<UnitId, usize>,
+    commanders: Vec<usize>,
+}
+
+impl SpatialIndex {
+    pub fn grid(&self, side: BattleSide) -> &SpatialGrid {
+        match side {
+            BattleSide::Attacker => &self.attackers,
+            BattleSide::Defender => &self.defenders,
+        }
+    }
+}
+
+impl BattleSimulation {
+    /// Files every unit under its current position. `step` does this
+    /// itself, call it after moving units by hand to query the grid.
+    pub fn rebuild_spatial_index(&mut self) {
+        let (width, height) = (self.battle.battlefield.width, self.battle.battlefield.height);
+        let index = &mut self.spatial;
+        for (grid, side) in [
+            (&mut index.attackers, BattleSide::Attacker),
+            (&mut index.defenders, BattleSide::Defender),
+        ] {
+            let points = self.units.iter().enumerate().filter(|(_, u)| u.side == side);
+            grid.rebuild(width, height, points.map(|(i, u)| (i, &u.position)));
+        }
+
+        if index.by_id.len() != self.units.len() {
+            index.by_id = self.units.iter().enumerate().map(|(i, u)| (u.id(), i)).collect();
+        }
+        index.commanders.clear();
+        index
+            .commanders
+            .extend((0..self.units.len()).filter(|&i| self.units[i].general.is_some()));
+    }
+
+    /// The index is up to date with the unit list, though not necessarily
+    /// with where the units stand.
+    fn spatial_index_current(&self) -> bool {
+        self.spatial.attackers.len() + self.spatial.defenders.len() == self.units.len()
+    }
+
+    pub(crate) fn lookup_index(&self, id: UnitId) -> Option<usize> {
+        match self.spatial.by_id.get(&id) {
+            Some(&index) if self.units.get(index).is_some_and(|u| u.id() == id) => Some(index),
+            _ => self.units.iter().position(|u| u.id() == id),
+        }
+    }
+
+    /// Indices of the units that carry a general.
+    pub(crate) fn commanders(&self) -> impl Iterator<Item = usize> + '_ {
+        let current = self.spatial_index_current();
+        let cached = current.then(|| self.spatial.commanders.iter().copied());
+        let scanned = (!current).then(|| (0..self.units.len()).filter(|&i| self.units[i].general.is_some()));
+        cached.into_iter().flatten().chain(scanned.into_iter().flatten())
+    }
+
+    /// Units of `side` within `radius` of `position`, by the grid when it
+    /// is current.
+    pub fn units_within(&self, side: BattleSide, position: &Position, radius: f32) -> Vec<usize> {
+        if self.spatial_index_current() {
+            return self.spatial.grid(side).within_radius(position, radius).collect();
+        }
+
+        (0..self.units.len())
+            .filter(|&i| self.units[i].side == side && self.units[i].position.distance_to(position) <= radius)
+            .collect()
+    }
+
+    /// Units of `side` within `radius` of `position` that lie no more than
+    /// `half_angle` either side of `heading`.
+    pub fn units_in_cone(
+        &self,
+        side: BattleSide,
+        position: &Position,
+        heading: f32,
+        half_angle: f32,
+        radius: f32,
+    ) -> Vec<usize> {
+        if self.spatial_index_current() {
+            return self
+                .spatial
+                .grid(side)
+                .within_cone(position, heading, half_angle, radius)
+                .collect();
+        }
+
+        self.units_within(side, position, radius)
+            .into_iter()
+            .filter(|&i| in_cone(position, &self.units[i].position, heading, half_angle))
+            .collect()
+    }
+
+    /// Up to `count` of the closest units of `side` to `position` that
+    /// `filter` accepts, closest first.
+    pub fn nearest_units(
+        &self,
+        side: BattleSide,
+        position: &Position,
+        count: usize,
+        filter: impl Fn(&BattleUnit) -> bool,
+    ) -> Vec<usize> {
+        if self.spatial_index_current() {
+            return self
+                .spatial
+                .grid(side)
+                .nearest_n(position, f32::INFINITY, count, |i| filter(&self.units[i]));
+        }
+
+        let mut found: Vec<(f32, usize)> = (0..self.units.len())
+            .filter(|&i| self.units[i].side == side && filter(&self.units[i]))
+            .map(|i| (self.units[i].position.distance_to(position), i))
+            .collect();
+        found.sort_by(closer);
+        found.into_iter().take(count).map(|(_, i)| i).collect()
+    }
+
+    pub fn nearest_unit(&self, side: BattleSide, position: &Position, filter: impl Fn(&BattleUnit) -> bool) -> Option<usize> {
+        self.nearest_units(side, position, 1, filter).into_iter().next()
+    }
+
+    /// The enemies a unit weighs up as targets: the nearest ones it can
+    /// see. With nobody in sight it makes for the nearest enemy the rest of
+    /// its side has spotted, the way an army marches to the sound of the
+    /// guns. Enemies nobody has seen stay hidden.
+    pub(crate) fn target_candidates(&self, index: usize) -> Vec<usize> {
+        let unit = &self.units[index];
+        let visible = self.nearest_units(unit.side.opponent(), &unit.position, MAX_TARGET_CANDIDATES, |u| {
+            u.is_active() && self.can_see(index, &u.position)
+        });
+        if !visible.is_empty() {
+            return visible;
+        }
+        self.nearest_unit(unit.side.opponent(), &unit.position, |u| {
+            u.is_active() && self.spotted_by(unit.side, &u.position)
+        })
+        .into_iter()
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: f32 = 1200.0;
+    const HEIGHT: f32 = 800.0;
+
+    /// Points scattered over the field and a margin around it, with a few
+    /// stacked on top of each other and on cell boundaries.
+    fn scatter(seed: u64, count: usize) -> Vec<Position> {
+        let mut rng = BattleRng::new(seed);
+        let mut points: Vec<Position> = (0..count)
+            .map(|_| Position::new(rng.range_f32(-300.0, WIDTH + 300.0), rng.range_f32(-300.0, HEIGHT + 300.0)))
+            .collect();
+        points.extend([Position::new(100.0, 100.0), Position::new(100.0, 100.0), Position::new(150.0, 50.0)]);
+        points
+    }
+
+    fn grid_of(points: &[Position]) -> SpatialGrid {
+        let mut grid = SpatialGrid::default();
+        grid.rebuild(WIDTH, HEIGHT, points.iter().enumerate());
+        grid
+    }
+
+    fn queries(seed: u64) -> Vec<Position> {
+        let mut queries = scatter(seed, 40);
+        queries.extend([Position::new(-1000.0, -1000.0), Position::new(WIDTH + 500.0, 400.0), Position::new(49.0, 49.0)]);
+        queries
+    }
+
+    fn sorted(mut indices: Vec<usize>) -> Vec<usize> {
+        indices.sort_unstable();
+        indices
+    }
+
+    #[test]
+    fn radius_and_cone_find_what_a_full_scan_finds() {
+        let points = scatter(1, 300);
+        let grid = grid_of(&points);
+
+        for query in queries(2) {
+            for radius in [0.0, 10.0, 49.0, 50.0, 120.0, 400.0, 5000.0] {
+                let scanned: Vec<usize> = (0..points.len()).filter(|&i| points[i].distance_to(&query) <= radius).collect();
+                assert_eq!(sorted(grid.within_radius(&query, radius).collect()), scanned);
+
+                for (heading, half_angle) in [(0.0, 0.5), (2.5, 1.0), (-1.2, std::f32::consts::PI)] {
+                    let scanned: Vec<usize> = scanned
+                        .iter()
+                        .copied()
+                        .filter(|&i| in_cone(&query, &points[i], heading, half_angle))
+                        .collect();
+                    assert_eq!(sorted(grid.within_cone(&query, heading, half_angle, radius).collect()), scanned);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_finds_what_a_full_scan_finds() {
+        let points = scatter(3, 300);
+        let grid = grid_of(&points);
+        let accepted = |i: usize| !i.is_multiple_of(3);
+
+        for query in queries(4) {
+            for radius in [0.0, 10.0, 49.0, 75.0, 300.0, f32::INFINITY] {
+                let mut scanned: Vec<(f32, usize)> = (0..points.len())
+                    .filter(|&i| accepted(i))
+                    .map(|i| (points[i].distance_to(&query), i))
+                    .filter(|&(distance, _)| distance <= radius)
+                    .collect();
+                scanned.sort_by(closer);
+
+                for count in [1, 5, 16, 1000] {
+                    let expected: Vec<usize> = scanned.iter().take(count).map(|&(_, i)| i).collect();
+                    assert_eq!(grid.nearest_n(&query, radius, count, accepted), expected);
+                }
+                assert_eq!(grid.nearest(&query, radius, accepted), scanned.first().map(|&(_, i)| i));
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_looks_into_the_next_cell_for_a_short_radius() {
+        let grid = grid_of(&[Position::new(52.0, 25.0)]);
+        assert_eq!(grid.nearest(&Position::new(48.0, 25.0), 10.0, |_| true), Some(0));
+    }
+}
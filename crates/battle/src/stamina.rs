@@ -0,0 +1,142 @@
+use crate::*;
+
+// ============================================================================
+// STAMINA
+// ============================================================================
+
+pub const MAX_STAMINA: f32 = 100.0;
+
+/// Units come onto the field fresh.
+pub(crate) fn full_stamina() -> f32 {
+    MAX_STAMINA
+}
+
+/// Stamina a unit in `Formation::Line` loses per second on the move over
+/// open ground.
+const MARCH_DRAIN: f32 = 0.8;
+
+/// Units running at an enemy this close tire this many times faster.
+const CHARGE_DISTANCE: f32 = 120.0;
+const CHARGE_DRAIN_MULTIPLIER: f32 = 2.5;
+
+/// Stamina lost with every blow struck in melee.
+const MELEE_DRAIN: f32 = 1.0;
+
+/// Stamina regained per second standing still out of the fight.
+const RECOVERY_RATE: f32 = 3.0;
+
+/// Tiredness starts to tell below this much stamina, and at none left a
+/// unit is down to these shares of its attack, defense and speed. Worn out
+/// men stop guarding themselves before they stop swinging, so two
+/// exhausted units still grind each other down.
+const TIRED_STAMINA: f32 = 60.0;
+const EXHAUSTED_ATTACK: f32 = 0.7;
+const EXHAUSTED_DEFENSE: f32 = 0.5;
+const EXHAUSTED_SPEED: f32 = 0.5;
+
+/// How much a unit's tiredness holds it back.
+#[derive(Debug, Clone)]
+pub struct FatigueModifiers {
+    pub attack_multiplier: f32,
+    pub defense_multiplier: f32,
+    pub speed_multiplier: f32,
+}
+
+impl Formation {
+    /// How fast a formation tires its men on the move compared to a line.
+    pub fn stamina_drain(&self) -> f32 {
+        match self {
+            Formation::Line => 1.0,
+            Formation::Column => 1.4, // Forced marching
+            Formation::Square => 1.2,
+            Formation::Wedge => 1.3,
+            Formation::Skirmish => 0.9,
+        }
+    }
+}
+
+impl Battlefield {
+    /// How much harder than open ground it is to move and fight at
+    /// `position`. Overlapping zones don't stack, the worst one counts.
+    pub fn going_at(&self, position: &Position) -> f32 {
+        self.terrain_effects
+            .iter()
+            .filter(|e| e.contains(position))
+            .map(|e| match e.effect_type {
+                TerrainEffectType::River { movement_penalty } => 1.0 + movement_penalty * 2.0,
+                TerrainEffectType::Forest { .. } => 1.5,
+                TerrainEffectType::HighGround { .. } => 1.3,
+                TerrainEffectType::Fortification { .. } => 1.0,
+            })
+            .fold(1.0, f32::max)
+    }
+}
+
+impl BattleUnit {
+    pub fn fatigue(&self) -> FatigueModifiers {
+        let tiredness = (1.0 - self.stamina / TIRED_STAMINA).clamp(0.0, 1.0);
+        let penalty = |exhausted: f32| 1.0 - (1.0 - exhausted) * tiredness;
+
+        FatigueModifiers {
+            attack_multiplier: penalty(EXHAUSTED_ATTACK),
+            defense_multiplier: penalty(EXHAUSTED_DEFENSE),
+            speed_multiplier: penalty(EXHAUSTED_SPEED),
+        }
+    }
+
+    /// Combat stats as worn down by tiredness.
+    pub fn tired_stats(&self) -> CombatStats {
+        let fatigue = self.fatigue();
+        CombatStats {
+            attack: self.stats.attack * fatigue.attack_multiplier,
+            defense: self.stats.defense * fatigue.defense_multiplier,
+            ..self.stats.clone()
+        }
+    }
+
+    fn tire(&mut self, amount: f32) {
+        self.stamina = (self.stamina - amount).max(0.0);
+    }
+
+    fn rest(&mut self, amount: f32) {
+        self.stamina = (self.stamina + amount).min(MAX_STAMINA);
+    }
+}
+
+impl BattleSimulation {
+    /// Wears a unit out for a tick on the move, or lets it get its breath
+    /// back if it stood still out of the fight.
+    pub(crate) fn exert_moving(&mut self, index: usize, moving: bool, delta_time: f32) {
+        let unit = &self.units[index];
+        if !moving {
+            if !self.is_engaged_in_melee(unit.id()) {
+                self.units[index].rest(RECOVERY_RATE * delta_time);
+            }
+            return;
+        }
+
+        let charging = unit.move_target.is_none()
+            && !unit.has_ranged_attack()
+            && unit
+                .target
+                .and_then(|id| self.unit(id))
+                .is_some_and(|t| t.position.distance_to(&unit.position) < CHARGE_DISTANCE);
+        let pace = if charging { CHARGE_DRAIN_MULTIPLIER } else { 1.0 };
+
+        let drain = MARCH_DRAIN
+            * pace
+            * self.conditions().stamina_drain
+            * unit.unit.formation.stamina_drain()
+            * self.battle.battlefield.going_at(&unit.position)
+            * delta_time;
+        self.units[index].tire(drain);
+    }
+
+    /// Every blow struck in melee costs the unit some stamina, more when
+    /// fighting in heavy going or bad weather.
+    pub(crate) fn exert_fighting(&mut self, index: usize) {
+        let going = self.battle.battlefield.going_at(&self.units[index].position);
+        let weather = self.conditions().stamina_drain;
+        self.units[index].tire(MELEE_DRAIN * going * weather);
+    }
+}
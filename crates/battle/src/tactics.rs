@@ -0,0 +1,433 @@
+use crate::*;
+
+// ============================================================================
+// GROUP TACTICS
+// ============================================================================
+
+/// Sides under AI control rethink their plan this often, in ticks.
+const THINK_INTERVAL: u64 = 30;
+
+/// A side down to this share of the enemy's strength withdraws.
+const RETREAT_STRENGTH_RATIO: f32 = 0.35;
+
+/// A defender that isn't at least this much stronger than the attacker
+/// holds its line and lets the attacker come, until enemy melee troops get
+/// this close to it and the whole line goes in.
+const HOLD_STRENGTH_RATIO: f32 = 1.5;
+const LINE_CONTACT_DISTANCE: f32 = 100.0;
+
+/// Cavalry further than this from a target that is pinned down facing
+/// other troops rides around to the target's flank before going in.
+const FLANK_TRIGGER_DISTANCE: f32 = 150.0;
+const FLANK_OFFSET: f32 = 120.0;
+
+/// Missile troops fall back behind their infantry once enemy melee troops
+/// get this close, and stand this far behind it.
+const SCREEN_DISTANCE: f32 = 120.0;
+const SCREEN_DEPTH: f32 = 40.0;
+const SCREEN_ARC: f32 = std::f32::consts::FRAC_PI_3; // Either side of the threat
+
+/// Troops looking for an enemy nobody has seen yet push forward this far at
+/// a time, so they stop to take a look around now and then.
+const SEARCH_STEP: f32 = 150.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupTactic {
+    Advance,
+    HoldLine,
+    Retreat,
+}
+
+/// The AI commanding one side of a battle, or just one faction's
+/// contingent on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TacticalAI {
+    pub side: BattleSide,
+    pub tactic: GroupTactic,
+    #[serde(default)]
+    pub faction: Option<FactionId>, // None commands the whole side
+}
+
+impl BattleSimulation {
+    /// Hands `side` over to the AI. Its orders are worked out from the
+    /// battle state during `step`, so replays reproduce them without
+    /// recording them.
+    pub fn enable_ai(&mut self, side: BattleSide) {
+        if !self.tactical_ai.iter().any(|ai| ai.side == side && ai.faction.is_none()) {
+            self.tactical_ai.push(TacticalAI {
+                side,
+                tactic: GroupTactic::Advance,
+                faction: None,
+            });
+        }
+    }
+
+    pub fn tactic(&self, side: BattleSide) -> Option<GroupTactic> {
+        self.tactical_ai.iter().find(|ai| ai.side == side).map(|ai| ai.tactic)
+    }
+
+    /// Rough fighting strength of the units of `side` still in the fight.
+    pub fn side_strength(&self, side: BattleSide) -> f32 {
+        self.units
+            .iter()
+            .filter(|u| u.side == side && u.can_fight())
+            .map(Self::strength)
+            .sum()
+    }
+
+    /// Rough fighting strength of the enemies `side` has spotted, all its
+    /// AI has to go on.
+    pub fn spotted_strength(&self, side: BattleSide) -> f32 {
+        self.units
+            .iter()
+            .filter(|u| u.side == side.opponent() && u.can_fight() && self.spotted_by(side, &u.position))
+            .map(Self::strength)
+            .sum()
+    }
+
+    fn strength(unit: &BattleUnit) -> f32 {
+        unit.stats.attack * unit.unit.count as f32 * unit.unit.morale / 100.0
+    }
+
+    pub(crate) fn run_tactical_ai(&mut self) {
+        if !self.tick.is_multiple_of(THINK_INTERVAL) {
+            return;
+        }
+
+        for i in 0..self.tactical_ai.len() {
+            let (side, faction) = (self.tactical_ai[i].side, self.tactical_ai[i].faction);
+            let tactic = self.choose_tactic(side);
+            self.tactical_ai[i].tactic = tactic;
+
+            for command in self.plan(side, faction, tactic) {
+                self.apply_command(&command);
+            }
+        }
+    }
+
+    /// Units that withdrew all the way to their edge leave the battle.
+    pub(crate) fn finish_withdrawals(&mut self) {
+        for unit in self.units.iter_mut().filter(|u| u.withdrawing && u.can_fight()) {
+            let edge = self.battle.battlefield.deployment_edge(unit.side);
+            if (unit.position.y - edge).abs() < 1.0 {
+                unit.state = UnitState::Fled;
+            }
+        }
+    }
+
+    /// Sizes up the enemy it can see. With nobody in sight the attacker
+    /// goes looking and the defender waits for it.
+    fn choose_tactic(&self, side: BattleSide) -> GroupTactic {
+        let enemy = self.spotted_strength(side);
+        if enemy <= 0.0 {
+            return match side {
+                BattleSide::Attacker => GroupTactic::Advance,
+                BattleSide::Defender => GroupTactic::HoldLine,
+            };
+        }
+
+        let ratio = self.side_strength(side) / enemy;
+        if ratio < RETREAT_STRENGTH_RATIO {
+            GroupTactic::Retreat
+        } else if side == BattleSide::Defender && ratio < HOLD_STRENGTH_RATIO && !self.line_in_contact(side) {
+            GroupTactic::HoldLine
+        } else {
+            GroupTactic::Advance
+        }
+    }
+
+    fn line_in_contact(&self, side: BattleSide) -> bool {
+        self.units.iter().filter(|u| u.side == side && u.can_fight()).any(|unit| {
+            self.units_within(side.opponent(), &unit.position, LINE_CONTACT_DISTANCE)
+                .into_iter()
+                .any(|i| self.units[i].can_fight() && !self.units[i].has_ranged_attack())
+        })
+    }
+
+    fn plan(&self, side: BattleSide, faction: Option<FactionId>, tactic: GroupTactic) -> Vec<BattleCommand> {
+        let mut commands = Vec::new();
+
+        for (i, unit) in self.units.iter().enumerate() {
+            if unit.side != side || !unit.can_fight() || faction.is_some_and(|f| f != unit.unit.faction) {
+                continue;
+            }
+
+            let command = if tactic == GroupTactic::Retreat {
+                (!unit.withdrawing).then_some(BattleCommand::Withdraw { unit: unit.id() })
+            } else if matches!(unit.unit.unit_type, UnitType::Cavalry) {
+                self.flank_order(i)
+            } else if unit.has_ranged_attack() {
+                self.screen_order(i)
+            } else {
+                self.line_order(i, tactic)
+            };
+            let command = command
+                .or_else(|| (tactic != GroupTactic::Retreat).then(|| self.objective_order(i)).flatten())
+                .or_else(|| (tactic == GroupTactic::Advance).then(|| self.search_order(i)).flatten());
+            commands.extend(command);
+        }
+        commands
+    }
+
+    /// Foot troops stand fast when holding the line and are let loose again
+    /// when the side goes over to the attack. Missile troops never hold,
+    /// they have to get within range.
+    fn line_order(&self, index: usize, tactic: GroupTactic) -> Option<BattleCommand> {
+        let unit = &self.units[index];
+        if unit.move_target.is_some() {
+            return None;
+        }
+
+        match tactic {
+            GroupTactic::HoldLine if !unit.hold_position => Some(BattleCommand::HoldPosition { unit: unit.id() }),
+            GroupTactic::Advance if unit.hold_position || unit.withdrawing => {
+                Some(BattleCommand::Halt { unit: unit.id() })
+            }
+            _ => None,
+        }
+    }
+
+    /// Troops with nobody to fight go and take the nearest capture point
+    /// their side doesn't hold yet.
+    fn objective_order(&self, index: usize) -> Option<BattleCommand> {
+        let unit = &self.units[index];
+        if unit.target.is_some() || unit.move_target.is_some() || unit.hold_position {
+            return None;
+        }
+
+        let destination = self.nearest_open_capture_point(index)?;
+        Some(BattleCommand::Move {
+            unit: unit.id(),
+            destination,
+        })
+    }
+
+    /// Troops with nobody in sight and nothing to take push on towards the
+    /// enemy's edge of the field until they run into someone.
+    fn search_order(&self, index: usize) -> Option<BattleCommand> {
+        let unit = &self.units[index];
+        if unit.target.is_some() || unit.move_target.is_some() || unit.hold_position || unit.withdrawing {
+            return None;
+        }
+
+        let remaining = self.battle.battlefield.deployment_edge(unit.side.opponent()) - unit.position.y;
+        if remaining.abs() < 1.0 {
+            return None;
+        }
+        Some(BattleCommand::Move {
+            unit: unit.id(),
+            destination: Position::new(unit.position.x, unit.position.y + remaining.clamp(-SEARCH_STEP, SEARCH_STEP)),
+        })
+    }
+
+    /// Cavalry heading for the front of a target that friendly foot has
+    /// pinned in melee swings out to the nearer side of it first, and
+    /// charges in from there. A target that is still moving would just
+    /// turn to face it, so against those cavalry goes straight in.
+    fn flank_order(&self, index: usize) -> Option<BattleCommand> {
+        let unit = &self.units[index];
+        if unit.move_target.is_some() || unit.target_locked {
+            return None;
+        }
+
+        let target = unit.target.and_then(|id| self.unit(id)).filter(|t| t.can_fight())?;
+        if unit.position.distance_to(&target.position) < FLANK_TRIGGER_DISTANCE {
+            return None;
+        }
+        let direction = AttackDirection::from_positions(target.facing, &target.position, &unit.position);
+        if direction != AttackDirection::Front {
+            return None;
+        }
+        let pinned = self
+            .units_within(unit.side, &target.position, MELEE_CONTACT_DISTANCE)
+            .into_iter()
+            .any(|i| self.units[i].can_fight());
+        if !pinned {
+            return None;
+        }
+
+        let beside = |angle: f32| {
+            Position::new(
+                target.position.x + angle.cos() * FLANK_OFFSET,
+                target.position.y + angle.sin() * FLANK_OFFSET,
+            )
+        };
+        let left = beside(target.facing + std::f32::consts::FRAC_PI_2);
+        let right = beside(target.facing - std::f32::consts::FRAC_PI_2);
+        let destination = if unit.position.distance_to(&left) <= unit.position.distance_to(&right) {
+            left
+        } else {
+            right
+        };
+
+        Some(BattleCommand::Move {
+            unit: unit.id(),
+            destination,
+        })
+    }
+
+    /// Missile troops threatened by enemy melee troops fall back behind the
+    /// nearest friendly foot standing towards the enemy.
+    fn screen_order(&self, index: usize) -> Option<BattleCommand> {
+        let unit = &self.units[index];
+        if unit.move_target.is_some() {
+            return None;
+        }
+
+        let threat = self.nearest_unit(unit.side.opponent(), &unit.position, |u| {
+            u.can_fight() && !u.has_ranged_attack() && self.can_see(index, &u.position)
+        })?;
+        let threat = &self.units[threat];
+        let distance = unit.position.distance_to(&threat.position);
+        if distance > SCREEN_DISTANCE {
+            return None;
+        }
+
+        // Friendly foot in the direction the threat comes from
+        let towards_threat = heading_between(&unit.position, &threat.position);
+        let screen = self
+            .units_in_cone(unit.side, &unit.position, towards_threat, SCREEN_ARC, distance)
+            .into_iter()
+            .map(|i| &self.units[i])
+            .filter(|u| {
+                u.id() != unit.id()
+                    && u.can_fight()
+                    && matches!(u.unit.unit_type, UnitType::Infantry | UnitType::Special(_))
+            })
+            .min_by(|a, b| {
+                let a = a.position.distance_to(&unit.position);
+                let b = b.position.distance_to(&unit.position);
+                a.total_cmp(&b)
+            })?;
+
+        let away = heading_between(&threat.position, &screen.position);
+        let destination = Position::new(
+            screen.position.x + away.cos() * SCREEN_DEPTH,
+            screen.position.y + away.sin() * SCREEN_DEPTH,
+        );
+        if unit.position.distance_to(&destination) < SCREEN_DEPTH / 2.0 {
+            return None;
+        }
+
+        Some(BattleCommand::Move {
+            unit: unit.id(),
+            destination,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn enemies(simulation: &BattleSimulation, side: BattleSide) -> Vec<&BattleUnit> {
+        simulation.units.iter().filter(|u| u.side == side).collect()
+    }
+
+    #[test]
+    fn artillery_shells_infantry_in_range_over_distant_cavalry() {
+        let mut simulation = open_field(1);
+        let gun = place(&mut simulation, BattleSide::Attacker, UnitType::Artillery, 50, 600.0, 150.0);
+        let infantry = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 100, 600.0, 400.0);
+        place(&mut simulation, BattleSide::Defender, UnitType::Cavalry, 20, 600.0, 750.0);
+
+        let gun = simulation.unit(gun).unwrap();
+        let target = BattleAI::choose_target(gun, &enemies(&simulation, BattleSide::Defender), |_| 0);
+        assert_eq!(target, Some(infantry));
+    }
+
+    #[test]
+    fn melee_troops_fight_the_enemy_at_hand_rather_than_the_weakest() {
+        let mut simulation = open_field(2);
+        let infantry = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 100, 600.0, 300.0);
+        let near = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 100, 600.0, 340.0);
+        place(&mut simulation, BattleSide::Defender, UnitType::Archers, 10, 100.0, 750.0);
+
+        let infantry = simulation.unit(infantry).unwrap();
+        let target = BattleAI::choose_target(infantry, &enemies(&simulation, BattleSide::Defender), |_| 0);
+        assert_eq!(target, Some(near));
+    }
+
+    #[test]
+    fn cavalry_rides_down_missile_troops() {
+        let mut simulation = open_field(3);
+        let cavalry = place(&mut simulation, BattleSide::Attacker, UnitType::Cavalry, 60, 600.0, 300.0);
+        place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 60, 500.0, 500.0);
+        let archers = place(&mut simulation, BattleSide::Defender, UnitType::Archers, 60, 700.0, 500.0);
+
+        let cavalry = simulation.unit(cavalry).unwrap();
+        let target = BattleAI::choose_target(cavalry, &enemies(&simulation, BattleSide::Defender), |_| 0);
+        assert_eq!(target, Some(archers));
+    }
+
+    #[test]
+    fn units_spread_out_over_equal_targets() {
+        let mut simulation = open_field(4);
+        let infantry = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 100, 600.0, 300.0);
+        let left = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 100, 550.0, 400.0);
+        let right = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 100, 650.0, 400.0);
+
+        let infantry = simulation.unit(infantry).unwrap();
+        let defenders = enemies(&simulation, BattleSide::Defender);
+        let crowded = |busy: UnitId| move |id: UnitId| if id == busy { 2 } else { 0 };
+        assert_eq!(BattleAI::choose_target(infantry, &defenders, crowded(left)), Some(right));
+        assert_eq!(BattleAI::choose_target(infantry, &defenders, crowded(right)), Some(left));
+    }
+
+    #[test]
+    fn outmatched_side_withdraws() {
+        let mut simulation = open_field(5);
+        let lone = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 20, 600.0, 300.0);
+        for x in [300.0, 450.0, 600.0, 750.0] {
+            place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 100, x, 650.0);
+        }
+        simulation.enable_ai(BattleSide::Attacker);
+        simulation.run_tactical_ai();
+
+        assert_eq!(simulation.tactic(BattleSide::Attacker), Some(GroupTactic::Retreat));
+        assert!(simulation.unit(lone).unwrap().withdrawing);
+    }
+
+    #[test]
+    fn defender_holds_its_line_against_an_even_attacker() {
+        let mut simulation = open_field(6);
+        for x in [500.0, 700.0] {
+            place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 100, x, 300.0);
+        }
+        let line: Vec<UnitId> = [500.0, 700.0]
+            .into_iter()
+            .map(|x| place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 100, x, 750.0))
+            .collect();
+        simulation.enable_ai(BattleSide::Defender);
+        simulation.run_tactical_ai();
+
+        assert_eq!(simulation.tactic(BattleSide::Defender), Some(GroupTactic::HoldLine));
+        assert!(line.iter().all(|&id| simulation.unit(id).unwrap().hold_position));
+    }
+
+    #[test]
+    fn threatened_archers_fall_back_behind_their_infantry() {
+        let mut simulation = open_field(7);
+        place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 100, 600.0, 600.0);
+        place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 100, 600.0, 640.0);
+        let archers = place(&mut simulation, BattleSide::Defender, UnitType::Archers, 60, 630.0, 690.0);
+        simulation.enable_ai(BattleSide::Defender);
+        simulation.run_tactical_ai();
+
+        let destination = simulation.unit(archers).unwrap().move_target.clone().expect("archers should move");
+        assert!(destination.y > 640.0, "archers should end up behind the infantry, not at {destination:?}");
+    }
+
+    #[test]
+    fn ai_battles_play_out_the_same_every_time() {
+        let run = || {
+            let mut simulation = skirmish(9);
+            simulation.enable_ai(BattleSide::Attacker);
+            simulation.enable_ai(BattleSide::Defender);
+            run_script(&mut simulation, &[], 1800, |_| {});
+            (simulation.tick, simulation.checksum())
+        };
+        assert_eq!(run(), run());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::*;
+
+// ============================================================================
+// TEST BATTLES
+// ============================================================================
+
+pub(crate) fn unit(unit_type: UnitType, faction: FactionId, men: u32) -> Unit {
+    Unit {
+        id: UnitId::default(),
+        unit_type,
+        count: men,
+        max_count: men,
+        morale: 100.0,
+        experience: 0,
+        formation: Formation::Line,
+        faction,
+    }
+}
+
+pub(crate) fn plains(owner: FactionId) -> Province {
+    Province {
+        id: ProvinceId::default(),
+        name: "Test Plains".to_string(),
+        owner: Some(owner),
+        position: Position::new(0.0, 0.0),
+        population: 0,
+        resources: Resources::new(),
+        buildings: Vec::new(),
+        adjacent_provinces: Vec::new(),
+        terrain_type: TerrainType::Plains,
+    }
+}
+
+/// A small battle on open plains, two infantry, a cavalry and an archer
+/// unit a side, deployed and ready to fight.
+pub(crate) fn skirmish(seed: u64) -> BattleSimulation {
+    let mut simulation = undeployed_skirmish(seed);
+    deploy(&mut simulation);
+    simulation
+}
+
+/// The units of `skirmish` still waiting to be deployed.
+pub(crate) fn undeployed_skirmish(seed: u64) -> BattleSimulation {
+    let attacker = FactionId::default();
+    let defender = FactionId::default();
+    let battlefield = BattlefieldGenerator::from_province(&plains(defender), seed);
+    let mut simulation = BattleSimulation::new(Battle::new(format!("test-{seed}"), attacker, defender, battlefield), seed);
+
+    for (side, faction) in [(BattleSide::Attacker, attacker), (BattleSide::Defender, defender)] {
+        for unit_type in [UnitType::Infantry, UnitType::Infantry, UnitType::Cavalry, UnitType::Archers] {
+            simulation.add_unit(BattleUnit::new(unit(unit_type, faction, 40), side, Position::new(0.0, 0.0)));
+        }
+    }
+    simulation
+}
+
+/// An empty battle on flat, open ground already in combat, for placing
+/// units by hand with `place`.
+pub(crate) fn open_field(seed: u64) -> BattleSimulation {
+    let battlefield = Battlefield {
+        width: DEFAULT_BATTLEFIELD_WIDTH,
+        height: DEFAULT_BATTLEFIELD_HEIGHT,
+        terrain_effects: Vec::new(),
+        walls: Vec::new(),
+        heightmap: Heightmap::default(),
+    };
+    let battle = Battle::new(format!("test-{seed}"), FactionId::default(), FactionId::default(), battlefield);
+    let mut simulation = BattleSimulation::new(battle, seed);
+    simulation.battle.phase = BattlePhase::Combat;
+    simulation
+}
+
+/// Puts a unit of `men` for the leader of `side` at (x, y).
+pub(crate) fn place(
+    simulation: &mut BattleSimulation,
+    side: BattleSide,
+    unit_type: UnitType,
+    men: u32,
+    x: f32,
+    y: f32,
+) -> UnitId {
+    let faction = simulation.battle.leader(side);
+    let battle_unit = BattleUnit::new(unit(unit_type, faction, men), side, Position::new(x, y));
+    let id = battle_unit.id();
+    simulation.add_unit(battle_unit);
+    id
+}
+
+pub(crate) fn deploy(simulation: &mut BattleSimulation) {
+    for side in [BattleSide::Attacker, BattleSide::Defender] {
+        simulation.auto_deploy(side).unwrap();
+        simulation.mark_ready(side).unwrap();
+    }
+}
+
+/// Orders for the attacker given on fixed ticks: advance, change
+/// formation, then charge the nearest defender.
+pub(crate) fn command_script(simulation: &BattleSimulation) -> Vec<(u64, BattleCommand)> {
+    let attackers: Vec<UnitId> = simulation.battle.attacker_units.clone();
+    let target = simulation.battle.defender_units[0];
+    let mut script = Vec::new();
+    for &unit in &attackers {
+        script.push((5, BattleCommand::Move { unit, destination: Position::new(500.0, 300.0) }));
+    }
+    script.push((40, BattleCommand::SetFormation { unit: attackers[0], formation: Formation::Column }));
+    script.push((40, BattleCommand::Halt { unit: attackers[1] }));
+    for &unit in &attackers {
+        script.push((90, BattleCommand::Attack { unit, target }));
+    }
+    script
+}
+
+/// Steps the battle until it ends or `ticks` have gone by, queueing the
+/// script's orders on their ticks. Calls `after_tick` after every step.
+pub(crate) fn run_script(
+    simulation: &mut BattleSimulation,
+    script: &[(u64, BattleCommand)],
+    ticks: u64,
+    mut after_tick: impl FnMut(&BattleSimulation),
+) {
+    while simulation.tick < ticks && simulation.outcome.is_none() {
+        let tick = simulation.tick;
+        simulation.queue_commands(script.iter().filter(|(t, _)| *t == tick).map(|(_, c)| c.clone()));
+        simulation.step(BATTLE_TICK);
+        after_tick(simulation);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::*;
+
+// ============================================================================
+// VETERANCY
+// ============================================================================
+
+/// Experience for every enemy soldier a unit brings down.
+pub const EXPERIENCE_PER_KILL: u32 = 1;
+
+/// Experience for every surviving unit once a battle is over.
+pub const EXPERIENCE_PER_BATTLE: u32 = 25;
+pub const EXPERIENCE_VICTORY_BONUS: u32 = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum VeterancyRank {
+    Recruit,
+    Regular,
+    Veteran,
+    Elite,
+    Legendary,
+}
+
+impl VeterancyRank {
+    pub fn from_experience(experience: u32) -> Self {
+        match experience {
+            0..=99 => VeterancyRank::Recruit,
+            100..=299 => VeterancyRank::Regular,
+            300..=699 => VeterancyRank::Veteran,
+            700..=1499 => VeterancyRank::Elite,
+            _ => VeterancyRank::Legendary,
+        }
+    }
+
+    /// Experience needed to reach the next rank, `None` at the top.
+    pub fn next_threshold(&self) -> Option<u32> {
+        match self {
+            VeterancyRank::Recruit => Some(100),
+            VeterancyRank::Regular => Some(300),
+            VeterancyRank::Veteran => Some(700),
+            VeterancyRank::Elite => Some(1500),
+            VeterancyRank::Legendary => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VeterancyRank::Recruit => "Recruit",
+            VeterancyRank::Regular => "Regular",
+            VeterancyRank::Veteran => "Veteran",
+            VeterancyRank::Elite => "Elite",
+            VeterancyRank::Legendary => "Legendary",
+        }
+    }
+
+    pub fn bonuses(&self) -> VeterancyBonuses {
+        match self {
+            VeterancyRank::Recruit => VeterancyBonuses {
+                attack_multiplier: 1.0,
+                defense_multiplier: 1.0,
+                morale_multiplier: 1.0,
+                reform_time_multiplier: 1.0,
+            },
+            VeterancyRank::Regular => VeterancyBonuses {
+                attack_multiplier: 1.05,
+                defense_multiplier: 1.05,
+                morale_multiplier: 1.1,
+                reform_time_multiplier: 0.9,
+            },
+            VeterancyRank::Veteran => VeterancyBonuses {
+                attack_multiplier: 1.1,
+                defense_multiplier: 1.1,
+                morale_multiplier: 1.25,
+                reform_time_multiplier: 0.75,
+            },
+            VeterancyRank::Elite => VeterancyBonuses {
+                attack_multiplier: 1.2,
+                defense_multiplier: 1.15,
+                morale_multiplier: 1.4,
+                reform_time_multiplier: 0.6,
+            },
+            VeterancyRank::Legendary => VeterancyBonuses {
+                attack_multiplier: 1.3,
+                defense_multiplier: 1.2,
+                morale_multiplier: 1.6,
+                reform_time_multiplier: 0.5,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VeterancyBonuses {
+    pub attack_multiplier: f32,
+    pub defense_multiplier: f32,
+    pub morale_multiplier: f32, // Divides morale losses
+    pub reform_time_multiplier: f32,
+}
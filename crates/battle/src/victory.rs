@@ -0,0 +1,378 @@
+use crate::*;
+
+// ============================================================================
+// VICTORY CONDITIONS
+// ============================================================================
+
+/// Default radius of a capture point.
+pub const CAPTURE_POINT_RADIUS: f32 = 60.0;
+
+/// Battles are won by breaking the enemy unless told otherwise.
+pub(crate) fn default_victory_conditions() -> Vec<VictoryCondition> {
+    vec![VictoryCondition::Rout]
+}
+
+/// A place on the battlefield a side takes by standing on it with fighting
+/// troops while no enemy does. It stays theirs until the enemy does the
+/// same, an empty or contested point doesn't change hands.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapturePoint {
+    pub position: Position,
+    pub radius: f32,
+    #[serde(default)]
+    pub owner: Option<BattleSide>,
+}
+
+impl CapturePoint {
+    pub fn new(position: Position) -> Self {
+        Self {
+            position,
+            radius: CAPTURE_POINT_RADIUS,
+            owner: None,
+        }
+    }
+}
+
+/// One way a battle can be won. A battle ends as soon as any of its
+/// conditions is met.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum VictoryCondition {
+    Rout,       // No enemy unit is left fighting
+    Annihilate, // No enemy soldier is left on the field, dead or driven off
+    CapturePoints {
+        points: Vec<CapturePoint>,
+        hold_time: f32, // Seconds a side has to hold every point at once
+        #[serde(default)]
+        holder: Option<BattleSide>,
+        #[serde(default)]
+        held_for: f32,
+    },
+    TimeLimit {
+        seconds: f32, // The defender wins once this much combat has passed
+    },
+}
+
+impl VictoryCondition {
+    pub fn capture_points(positions: Vec<Position>, hold_time: f32) -> Self {
+        VictoryCondition::CapturePoints {
+            points: positions.into_iter().map(CapturePoint::new).collect(),
+            hold_time,
+            holder: None,
+            held_for: 0.0,
+        }
+    }
+
+    pub fn kind(&self) -> VictoryKind {
+        match self {
+            VictoryCondition::Rout => VictoryKind::Rout,
+            VictoryCondition::Annihilate => VictoryKind::Annihilation,
+            VictoryCondition::CapturePoints { .. } => VictoryKind::CapturePoints,
+            VictoryCondition::TimeLimit { .. } => VictoryKind::TimeLimit,
+        }
+    }
+}
+
+/// Which kind of condition decided a battle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VictoryKind {
+    Rout,
+    Annihilation,
+    CapturePoints,
+    TimeLimit,
+}
+
+impl VictoryKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VictoryKind::Rout => "Rout",
+            VictoryKind::Annihilation => "Annihilation",
+            VictoryKind::CapturePoints => "Capture Points",
+            VictoryKind::TimeLimit => "Time Limit",
+        }
+    }
+}
+
+/// How close each side is to winning by one of the battle's conditions,
+/// from 0.0 to 1.0.
+#[derive(Debug, Clone)]
+pub struct VictoryProgress {
+    pub kind: VictoryKind,
+    pub attacker: f32,
+    pub defender: f32,
+}
+
+impl VictoryProgress {
+    pub fn of(&self, side: BattleSide) -> f32 {
+        match side {
+            BattleSide::Attacker => self.attacker,
+            BattleSide::Defender => self.defender,
+        }
+    }
+}
+
+impl BattleSimulation {
+    /// Progress towards every victory condition, in the order they were
+    /// set on the battle.
+    pub fn victory_progress(&self) -> Vec<VictoryProgress> {
+        self.battle
+            .victory_conditions
+            .iter()
+            .map(|condition| {
+                let progress = |side: BattleSide| match condition {
+                    VictoryCondition::Rout => 1.0 - self.fighting_share(side.opponent()),
+                    VictoryCondition::Annihilate => 1.0 - self.remaining_share(side.opponent()),
+                    VictoryCondition::CapturePoints {
+                        hold_time,
+                        holder,
+                        held_for,
+                        ..
+                    } => {
+                        if *holder == Some(side) {
+                            (held_for / hold_time.max(f32::EPSILON)).min(1.0)
+                        } else {
+                            0.0
+                        }
+                    }
+                    VictoryCondition::TimeLimit { seconds } if side == BattleSide::Defender => {
+                        (self.elapsed / seconds.max(f32::EPSILON)).min(1.0)
+                    }
+                    VictoryCondition::TimeLimit { .. } => 0.0,
+                };
+
+                VictoryProgress {
+                    kind: condition.kind(),
+                    attacker: progress(BattleSide::Attacker),
+                    defender: progress(BattleSide::Defender),
+                }
+            })
+            .collect()
+    }
+
+    /// Share of the men `side` brought that are still fighting.
+    fn fighting_share(&self, side: BattleSide) -> f32 {
+        self.men_share(side, |u| u.can_fight())
+    }
+
+    /// Share of the men `side` brought that are still on the field.
+    fn remaining_share(&self, side: BattleSide) -> f32 {
+        self.men_share(side, |u| u.is_active())
+    }
+
+    fn men_share(&self, side: BattleSide, counted: impl Fn(&BattleUnit) -> bool) -> f32 {
+        let units = self.units.iter().filter(|u| u.side == side);
+        let initial: u32 = units.clone().map(|u| u.initial_count).sum();
+        let left: u32 = units.filter(|u| counted(u)).map(|u| u.unit.count).sum();
+        left as f32 / initial.max(1) as f32
+    }
+
+    /// True once `side` has no soldier left on the field.
+    pub fn side_annihilated(&self, side: BattleSide) -> bool {
+        !self.units.iter().any(|u| u.side == side && u.is_active())
+    }
+
+    /// Hands capture points to whoever stands on them alone and runs the
+    /// clock for a side holding all of them.
+    pub(crate) fn update_capture_points(&mut self, delta_time: f32) {
+        let mut conditions = std::mem::take(&mut self.battle.victory_conditions);
+        for condition in conditions.iter_mut() {
+            let VictoryCondition::CapturePoints {
+                points,
+                holder,
+                held_for,
+                ..
+            } = condition
+            else {
+                continue;
+            };
+
+            for point in points.iter_mut() {
+                let present = |side: BattleSide| {
+                    self.units_within(side, &point.position, point.radius)
+                        .into_iter()
+                        .any(|i| self.units[i].can_fight())
+                };
+                match (present(BattleSide::Attacker), present(BattleSide::Defender)) {
+                    (true, false) => point.owner = Some(BattleSide::Attacker),
+                    (false, true) => point.owner = Some(BattleSide::Defender),
+                    _ => {}
+                }
+            }
+
+            let owner = points.first().and_then(|p| p.owner);
+            let holds_all = owner.filter(|&side| points.iter().all(|p| p.owner == Some(side)));
+            if holds_all.is_some() && holds_all == *holder {
+                *held_for += delta_time;
+            } else {
+                *holder = holds_all;
+                *held_for = 0.0;
+            }
+        }
+        self.battle.victory_conditions = conditions;
+    }
+
+    /// The winner, if any, and the condition that decided the battle, once
+    /// one of them is met. A battle in which neither side has anyone left
+    /// fighting is over whatever its conditions say.
+    pub(crate) fn decide_battle(&self) -> Option<(Option<BattleSide>, VictoryKind)> {
+        let attacker_broken = self.side_broken(BattleSide::Attacker);
+        let defender_broken = self.side_broken(BattleSide::Defender);
+        if attacker_broken && defender_broken {
+            return Some((None, VictoryKind::Rout));
+        }
+
+        self.battle.victory_conditions.iter().find_map(|condition| {
+            let winner = match condition {
+                VictoryCondition::Rout if defender_broken => Some(BattleSide::Attacker),
+                VictoryCondition::Rout if attacker_broken => Some(BattleSide::Defender),
+                VictoryCondition::Annihilate if self.side_annihilated(BattleSide::Defender) => Some(BattleSide::Attacker),
+                VictoryCondition::Annihilate if self.side_annihilated(BattleSide::Attacker) => Some(BattleSide::Defender),
+                VictoryCondition::CapturePoints {
+                    hold_time,
+                    holder,
+                    held_for,
+                    ..
+                } if *held_for >= *hold_time => *holder,
+                VictoryCondition::TimeLimit { seconds } if self.elapsed >= *seconds => Some(BattleSide::Defender),
+                _ => None,
+            };
+            winner.map(|side| (Some(side), condition.kind()))
+        })
+    }
+
+    /// The nearest capture point not held by the unit's side, for AI
+    /// troops with nobody to fight to go and take.
+    pub(crate) fn nearest_open_capture_point(&self, index: usize) -> Option<Position> {
+        let unit = &self.units[index];
+        self.battle
+            .victory_conditions
+            .iter()
+            .filter_map(|condition| match condition {
+                VictoryCondition::CapturePoints { points, .. } => Some(points),
+                _ => None,
+            })
+            .flatten()
+            .filter(|p| p.owner != Some(unit.side))
+            .min_by(|a, b| {
+                let a = a.position.distance_to(&unit.position);
+                let b = b.position.distance_to(&unit.position);
+                a.total_cmp(&b)
+            })
+            .map(|p| p.position.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    /// An infantry unit a side, standing their ground far apart.
+    fn standoff(conditions: Vec<VictoryCondition>) -> (BattleSimulation, UnitId, UnitId) {
+        let mut simulation = open_field(1);
+        simulation.battle.victory_conditions = conditions;
+        let attacker = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 60, 300.0, 100.0);
+        let defender = place(&mut simulation, BattleSide::Defender, UnitType::Infantry, 60, 900.0, 700.0);
+        for unit in simulation.units.iter_mut() {
+            unit.hold_position = true;
+        }
+        (simulation, attacker, defender)
+    }
+
+    fn break_unit(simulation: &mut BattleSimulation, id: UnitId) {
+        let unit = simulation.unit_mut(id).unwrap();
+        unit.state = UnitState::Routing;
+        unit.unit.morale = 0.0;
+    }
+
+    fn run_until_decided(simulation: &mut BattleSimulation, seconds: f32) {
+        while simulation.outcome.is_none() && simulation.elapsed < seconds {
+            simulation.step(BATTLE_TICK);
+        }
+    }
+
+    #[test]
+    fn breaking_the_enemy_wins_by_rout() {
+        let (mut simulation, _, defender) = standoff(vec![VictoryCondition::Rout]);
+        simulation.step(BATTLE_TICK);
+        assert!(simulation.outcome.is_none());
+
+        break_unit(&mut simulation, defender);
+        simulation.step(BATTLE_TICK);
+
+        let outcome = simulation.outcome.as_ref().expect("battle over");
+        assert_eq!(outcome.winner, Some(BattleSide::Attacker));
+        assert_eq!(outcome.decided_by, Some(VictoryKind::Rout));
+        assert!(matches!(simulation.battle.phase, BattlePhase::Resolved));
+    }
+
+    #[test]
+    fn annihilation_waits_for_the_enemy_to_leave_the_field() {
+        let (mut simulation, _, defender) = standoff(vec![VictoryCondition::Annihilate]);
+        break_unit(&mut simulation, defender);
+        simulation.step(BATTLE_TICK);
+        assert!(simulation.outcome.is_none());
+        assert_eq!(simulation.victory_progress()[0].attacker, 0.0);
+
+        simulation.unit_mut(defender).unwrap().state = UnitState::Fled;
+        assert_eq!(simulation.victory_progress()[0].attacker, 1.0);
+        simulation.step(BATTLE_TICK);
+
+        let outcome = simulation.outcome.as_ref().expect("battle over");
+        assert_eq!(outcome.winner, Some(BattleSide::Attacker));
+        assert_eq!(outcome.decided_by, Some(VictoryKind::Annihilation));
+    }
+
+    #[test]
+    fn holding_every_capture_point_wins_once_the_hold_time_is_up() {
+        let points = vec![Position::new(300.0, 100.0), Position::new(600.0, 100.0)];
+        let (mut simulation, _, _) = standoff(vec![VictoryCondition::capture_points(points, 2.0)]);
+        let second = place(&mut simulation, BattleSide::Attacker, UnitType::Infantry, 60, 600.0, 100.0);
+        simulation.unit_mut(second).unwrap().hold_position = true;
+
+        run_until_decided(&mut simulation, 1.0);
+        assert!(simulation.outcome.is_none());
+        let progress = &simulation.victory_progress()[0];
+        assert_eq!(progress.kind, VictoryKind::CapturePoints);
+        assert!((progress.of(BattleSide::Attacker) - 0.5).abs() < 0.05, "{}", progress.attacker);
+        assert_eq!(progress.of(BattleSide::Defender), 0.0);
+
+        run_until_decided(&mut simulation, 5.0);
+        let outcome = simulation.outcome.as_ref().expect("battle over");
+        assert_eq!(outcome.winner, Some(BattleSide::Attacker));
+        assert_eq!(outcome.decided_by, Some(VictoryKind::CapturePoints));
+        assert!(outcome.duration >= 2.0);
+    }
+
+    #[test]
+    fn a_contested_point_changes_no_hands() {
+        let points = vec![Position::new(300.0, 100.0)];
+        let (mut simulation, _, defender) = standoff(vec![VictoryCondition::capture_points(points, 0.5)]);
+        simulation.unit_mut(defender).unwrap().position = Position::new(320.0, 100.0);
+
+        run_until_decided(&mut simulation, 1.0);
+        assert!(simulation.outcome.is_none());
+        let VictoryCondition::CapturePoints { points, holder, .. } = &simulation.battle.victory_conditions[0] else {
+            panic!("not a capture point condition");
+        };
+        assert_eq!(points[0].owner, None);
+        assert_eq!(*holder, None);
+    }
+
+    #[test]
+    fn the_defender_wins_when_time_runs_out() {
+        let (mut simulation, _, _) =
+            standoff(vec![VictoryCondition::Rout, VictoryCondition::TimeLimit { seconds: 1.0 }]);
+
+        run_until_decided(&mut simulation, 0.5);
+        let progress = &simulation.victory_progress()[1];
+        assert_eq!(progress.kind, VictoryKind::TimeLimit);
+        assert!((progress.defender - 0.5).abs() < 0.05, "{}", progress.defender);
+        assert_eq!(progress.attacker, 0.0);
+
+        run_until_decided(&mut simulation, 2.0);
+        let outcome = simulation.outcome.as_ref().expect("battle over");
+        assert_eq!(outcome.winner, Some(BattleSide::Defender));
+        assert_eq!(outcome.decided_by, Some(VictoryKind::TimeLimit));
+        assert!(outcome.duration >= 1.0 - BATTLE_TICK);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::*;
+
+// ============================================================================
+// WEATHER AND TIME OF DAY
+// ============================================================================
+
+/// How far a unit can see in clear daylight. That covers the whole of a
+/// default battlefield.
+pub const CLEAR_VISIBILITY: f32 = 1500.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Weather {
+    #[default]
+    Clear,
+    Rain,
+    Fog,
+    Snow,
+}
+
+impl Weather {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Weather::Clear => "Clear",
+            Weather::Rain => "Rain",
+            Weather::Fog => "Fog",
+            Weather::Snow => "Snow",
+        }
+    }
+
+    /// Relative odds of each kind of weather, in the order clear, rain,
+    /// fog, snow. Deserts stay dry, mountains get snow outside the summer,
+    /// swamps and coasts are often misty.
+    fn odds(season: Season, terrain: &TerrainType) -> [f32; 4] {
+        let mut odds = match season {
+            Season::Spring => [0.5, 0.35, 0.15, 0.0],
+            Season::Summer => [0.75, 0.2, 0.05, 0.0],
+            Season::Autumn => [0.4, 0.35, 0.25, 0.0],
+            Season::Winter => [0.35, 0.1, 0.15, 0.4],
+        };
+
+        match terrain {
+            TerrainType::Desert => odds = [odds[0] + odds[1] + odds[3], 0.0, odds[2] * 0.5, 0.0],
+            TerrainType::Mountains if season != Season::Summer => odds[3] += 0.3,
+            TerrainType::Swamp | TerrainType::Coast => odds[2] *= 2.0,
+            _ => {}
+        }
+        odds
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TimeOfDay {
+    Dawn,
+    #[default]
+    Day,
+    Dusk,
+    Night,
+}
+
+impl TimeOfDay {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimeOfDay::Dawn => "Dawn",
+            TimeOfDay::Day => "Day",
+            TimeOfDay::Dusk => "Dusk",
+            TimeOfDay::Night => "Night",
+        }
+    }
+
+    /// Winter days are short, so more of its battles run into the dark.
+    fn odds(season: Season) -> [f32; 4] {
+        match season {
+            Season::Winter => [0.2, 0.45, 0.25, 0.1],
+            _ => [0.2, 0.6, 0.15, 0.05],
+        }
+    }
+}
+
+/// The weather and light a battle is fought in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BattleConditions {
+    pub weather: Weather,
+    pub time_of_day: TimeOfDay,
+}
+
+/// How the conditions change the fighting. Every multiplier is 1.0 on a
+/// clear day.
+#[derive(Debug, Clone)]
+pub struct ConditionModifiers {
+    pub ranged_accuracy: f32,
+    pub artillery: f32, // Accuracy and damage of guns on top of `ranged_accuracy`
+    pub visibility: f32, // Distance a unit can pick out enemies at
+    pub movement: f32,
+    pub stamina_drain: f32,
+}
+
+impl BattleConditions {
+    pub fn new(weather: Weather, time_of_day: TimeOfDay) -> Self {
+        Self { weather, time_of_day }
+    }
+
+    /// Rolls the conditions of a battle fought in `province` at the date
+    /// on `calendar`. The same seed always gives the same conditions.
+    pub fn for_province(province: &Province, calendar: &WorldCalendar, seed: u64) -> Self {
+        let mut rng = BattleRng::new(seed ^ 0x5745_4154_4845_5221);
+        let season = calendar.season();
+
+        let weather = [Weather::Clear, Weather::Rain, Weather::Fog, Weather::Snow];
+        let times = [TimeOfDay::Dawn, TimeOfDay::Day, TimeOfDay::Dusk, TimeOfDay::Night];
+        Self {
+            weather: weather[Self::roll(&Weather::odds(season, &province.terrain_type), &mut rng)],
+            time_of_day: times[Self::roll(&TimeOfDay::odds(season), &mut rng)],
+        }
+    }
+
+    fn roll(odds: &[f32; 4], rng: &mut BattleRng) -> usize {
+        let mut roll = rng.next_f32() * odds.iter().sum::<f32>();
+        for (i, &chance) in odds.iter().enumerate() {
+            if roll < chance {
+                return i;
+            }
+            roll -= chance;
+        }
+        odds.iter().rposition(|&chance| chance > 0.0).unwrap_or(0)
+    }
+
+    pub fn modifiers(&self) -> ConditionModifiers {
+        let mut modifiers = match self.weather {
+            Weather::Clear => ConditionModifiers {
+                ranged_accuracy: 1.0,
+                artillery: 1.0,
+                visibility: CLEAR_VISIBILITY,
+                movement: 1.0,
+                stamina_drain: 1.0,
+            },
+            Weather::Rain => ConditionModifiers {
+                ranged_accuracy: 0.75,
+                artillery: 0.6, // Wet powder
+                visibility: 600.0,
+                movement: 0.85,
+                stamina_drain: 1.2,
+            },
+            Weather::Fog => ConditionModifiers {
+                ranged_accuracy: 0.8,
+                artillery: 0.8,
+                visibility: 200.0,
+                movement: 0.95,
+                stamina_drain: 1.0,
+            },
+            Weather::Snow => ConditionModifiers {
+                ranged_accuracy: 0.8,
+                artillery: 0.85,
+                visibility: 400.0,
+                movement: 0.7,
+                stamina_drain: 1.5,
+            },
+        };
+
+        let (accuracy, visibility, movement) = match self.time_of_day {
+            TimeOfDay::Day => (1.0, 1.0, 1.0),
+            TimeOfDay::Dawn | TimeOfDay::Dusk => (0.9, 0.7, 1.0),
+            TimeOfDay::Night => (0.6, 0.3, 0.8),
+        };
+        modifiers.ranged_accuracy *= accuracy;
+        modifiers.visibility *= visibility;
+        modifiers.movement *= movement;
+        modifiers
+    }
+
+    pub fn description(&self) -> String {
+        format!("{}, {}", self.weather.name(), self.time_of_day.name())
+    }
+}
+
+impl BattleSimulation {
+    pub fn conditions(&self) -> ConditionModifiers {
+        self.battle.conditions.modifiers()
+    }
+
+    /// How far out a unit can shoot at someone standing at `target`: its
+    /// range, longer shooting downhill and shorter shooting uphill, unless
+    /// it can't see that far.
+    pub fn firing_range(&self, index: usize, target: &Position) -> f32 {
+        let unit = &self.units[index];
+        let elevation = self.battle.battlefield.elevation_range_multiplier(&unit.position, target);
+        (unit.range * elevation).min(self.conditions().visibility)
+    }
+}
@@ -31,6 +31,12 @@ pub struct UnitId(pub Uuid);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TechnologyId(pub Uuid);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ArmyId(pub Uuid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GeneralId(pub Uuid);
+
 impl Default for FactionId {
     fn default() -> Self {
         Self(Uuid::new_v4())
@@ -55,6 +61,18 @@ impl Default for TechnologyId {
     }
 }
 
+impl Default for ArmyId {
+    fn default() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for GeneralId {
+    fn default() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
 // ============================================================================
 // CORE COMPONENTS
 // ============================================================================
@@ -0,0 +1,147 @@
+use crate::*;
+
+// ============================================================================
+// ARMIES
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Army {
+    pub id: ArmyId,
+    pub name: String,
+    pub owner: FactionId,
+    pub province: ProvinceId,
+    pub units: Vec<Unit>,
+    pub general: Option<General>,
+}
+
+impl Army {
+    pub fn new(name: String, owner: FactionId, province: ProvinceId) -> Self {
+        Self {
+            id: ArmyId::default(),
+            name,
+            owner,
+            province,
+            units: Vec::new(),
+            general: None,
+        }
+    }
+
+    pub fn active_general(&self) -> Option<&General> {
+        self.general.as_ref().filter(|g| g.is_active())
+    }
+
+    /// How much faster than normal the army marches, from its general.
+    pub fn movement_multiplier(&self) -> f32 {
+        self.active_general().map_or(1.0, |g| g.bonuses().movement_multiplier)
+    }
+
+    pub fn total_men(&self) -> u32 {
+        self.units.iter().map(|u| u.count).sum()
+    }
+
+    pub fn is_destroyed(&self) -> bool {
+        self.total_men() == 0
+    }
+
+    /// Drops units that have no men left after a battle or starvation.
+    pub fn remove_dead_units(&mut self) {
+        self.units.retain(|u| u.count > 0);
+    }
+
+    /// The army marching from `origin` to join a tactical battle on `side`
+    /// in `destination`. Its general rides with the first unit. Errors if
+    /// one of its units isn't in the registry.
+    pub fn reinforcement(
+        &self,
+        origin: &Province,
+        destination: &Province,
+        side: BattleSide,
+        registry: &UnitRegistry,
+    ) -> Result<Reinforcement, UnitRegistryError> {
+        let mut units: Vec<BattleUnit> = self
+            .units
+            .iter()
+            .filter(|u| u.count > 0)
+            .map(|unit| BattleUnit::from_registry(registry, unit.clone(), side, origin.position.clone()))
+            .collect::<Result<_, _>>()?;
+        if let (Some(first), Some(general)) = (units.first_mut(), self.active_general()) {
+            first.attach_general(general.clone());
+        }
+
+        Ok(Reinforcement::march(self.owner, units, origin, destination, self.movement_multiplier()))
+    }
+}
+
+/// An army close enough to a battle to march to it while it is fought.
+#[derive(Debug, Clone)]
+pub struct ReinforcingArmy {
+    pub army: ArmyId,
+    pub arrival_time: f32, // Seconds into the battle
+    pub edge: BattlefieldEdge,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct Armies {
+    pub armies: HashMap<ArmyId, Army>,
+}
+
+impl Armies {
+    pub fn add(&mut self, army: Army) -> ArmyId {
+        let id = army.id;
+        self.armies.insert(id, army);
+        id
+    }
+
+    pub fn get(&self, id: ArmyId) -> Option<&Army> {
+        self.armies.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: ArmyId) -> Option<&mut Army> {
+        self.armies.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: ArmyId) -> Option<Army> {
+        self.armies.remove(&id)
+    }
+
+    /// Armies of `owner` standing in `province`, sorted by id so callers see
+    /// them in the same order every time.
+    pub fn owned_in_province(&self, province: ProvinceId, owner: FactionId) -> Vec<ArmyId> {
+        let mut ids: Vec<ArmyId> = self
+            .armies
+            .values()
+            .filter(|a| a.province == province && a.owner == owner)
+            .map(|a| a.id)
+            .collect();
+        ids.sort_by_key(|id| id.0);
+        ids
+    }
+
+    /// Armies of `owner` standing in the provinces next to `province`, in
+    /// the order they would reach a battle there.
+    pub fn reinforcements(&self, province: &Province, world: &WorldMap, owner: FactionId) -> Vec<ReinforcingArmy> {
+        let mut reinforcements: Vec<ReinforcingArmy> = province
+            .adjacent_provinces
+            .iter()
+            .filter_map(|&id| world.get_province(id))
+            .flat_map(|origin| {
+                self.owned_in_province(origin.id, owner).into_iter().filter_map(move |id| {
+                    let army = self.get(id).filter(|a| !a.is_destroyed())?;
+                    Some(ReinforcingArmy {
+                        army: id,
+                        arrival_time: Reinforcement::marching_time(origin, province, army.movement_multiplier()),
+                        edge: BattlefieldEdge::from_direction(&province.position, &origin.position),
+                    })
+                })
+            })
+            .collect();
+
+        reinforcements.sort_by(|a, b| a.arrival_time.total_cmp(&b.arrival_time).then(a.army.0.cmp(&b.army.0)));
+        reinforcements
+    }
+
+    /// Removes armies that lost every man.
+    pub fn remove_destroyed(&mut self) {
+        self.armies.retain(|_, army| !army.is_destroyed());
+    }
+}
@@ -0,0 +1,330 @@
+use crate::*;
+use std::fmt;
+
+// ============================================================================
+// DIPLOMACY
+// ============================================================================
+
+pub const MIN_RELATIONS: f32 = -100.0;
+pub const MAX_RELATIONS: f32 = 100.0;
+
+/// Concluding a deal warms relations between the two sides this much.
+const DEAL_RELATIONS_GAIN: f32 = 10.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiplomacyError {
+    SameFaction,
+    NotAParty(FactionId), // Named in a term without being either side of the deal
+    InvalidAmount(i32),   // Gold terms have to pay something
+    CannotAfford(FactionId),
+    NoPrisoners(FactionId), // Has nobody of the other side to hand over
+}
+
+impl fmt::Display for DiplomacyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiplomacyError::SameFaction => write!(f, "a faction cannot make a deal with itself"),
+            DiplomacyError::NotAParty(faction) => write!(f, "faction {:?} is not a side of the deal", faction.0),
+            DiplomacyError::InvalidAmount(amount) => write!(f, "a deal cannot pay {} gold", amount),
+            DiplomacyError::CannotAfford(faction) => write!(f, "faction {:?} cannot pay what the deal asks", faction.0),
+            DiplomacyError::NoPrisoners(faction) => {
+                write!(f, "faction {:?} holds no prisoners of the other side", faction.0)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DiplomacyError {}
+
+/// Something one or both sides of a deal give.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DealTerm {
+    Gold { from: FactionId, amount: i32 }, // Paid to the other side
+    ReleasePrisoners { by: FactionId },    // Everyone it holds of the other side goes home
+    PrisonerExchange,                      // Both sides hand back everyone they hold of the other
+}
+
+/// Terms two factions agreed on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deal {
+    pub proposer: FactionId,
+    pub recipient: FactionId,
+    pub terms: Vec<DealTerm>,
+}
+
+impl Deal {
+    pub fn new(proposer: FactionId, recipient: FactionId) -> Self {
+        Self {
+            proposer,
+            recipient,
+            terms: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, term: DealTerm) -> Self {
+        self.terms.push(term);
+        self
+    }
+
+    fn other(&self, faction: FactionId) -> FactionId {
+        if faction == self.proposer {
+            self.recipient
+        } else {
+            self.proposer
+        }
+    }
+
+    /// Gold `faction` has to pay under the deal.
+    fn gold_owed(&self, faction: FactionId) -> i32 {
+        self.terms
+            .iter()
+            .map(|term| match term {
+                DealTerm::Gold { from, amount } if *from == faction => *amount,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// How the factions feel about each other, from MIN_RELATIONS to
+/// MAX_RELATIONS. Factions that never dealt with each other stand at 0.
+#[derive(Resource, Debug, Default)]
+pub struct Diplomacy {
+    relations: HashMap<(FactionId, FactionId), f32>,
+}
+
+impl Diplomacy {
+    /// Relations go both ways, so each pair is kept once.
+    fn key(a: FactionId, b: FactionId) -> (FactionId, FactionId) {
+        if a.0 <= b.0 {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    pub fn relations(&self, a: FactionId, b: FactionId) -> f32 {
+        self.relations.get(&Self::key(a, b)).copied().unwrap_or(0.0)
+    }
+
+    pub fn change_relations(&mut self, a: FactionId, b: FactionId, amount: f32) {
+        if a == b {
+            return;
+        }
+
+        let relations = self.relations.entry(Self::key(a, b)).or_insert(0.0);
+        *relations = (*relations + amount).clamp(MIN_RELATIONS, MAX_RELATIONS);
+    }
+
+    /// Every faction `faction` has had dealings with, sorted by id.
+    pub fn contacts(&self, faction: FactionId) -> Vec<FactionId> {
+        let mut contacts: Vec<FactionId> = self
+            .relations
+            .keys()
+            .filter_map(|&(a, b)| {
+                if a == faction {
+                    Some(b)
+                } else if b == faction {
+                    Some(a)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        contacts.sort_by_key(|f| f.0);
+        contacts
+    }
+
+    /// Carries out a deal both sides agreed to. Either every term can be
+    /// met and the whole deal goes through, or nothing changes. Returns the
+    /// prisoners who went home.
+    pub fn conclude(
+        &mut self,
+        deal: &Deal,
+        factions: &mut FactionStates,
+        prisoners: &mut Prisoners,
+    ) -> Result<Vec<Captives>, DiplomacyError> {
+        let sides = [deal.proposer, deal.recipient];
+        if deal.proposer == deal.recipient {
+            return Err(DiplomacyError::SameFaction);
+        }
+        let outsider = deal.terms.iter().find_map(|term| match term {
+            DealTerm::Gold { from: faction, .. } | DealTerm::ReleasePrisoners { by: faction } => {
+                (!sides.contains(faction)).then_some(*faction)
+            }
+            DealTerm::PrisonerExchange => None,
+        });
+        if let Some(faction) = outsider {
+            return Err(DiplomacyError::NotAParty(faction));
+        }
+        let unpaid = deal.terms.iter().find_map(|term| match term {
+            DealTerm::Gold { amount, .. } if *amount <= 0 => Some(*amount),
+            _ => None,
+        });
+        if let Some(amount) = unpaid {
+            return Err(DiplomacyError::InvalidAmount(amount));
+        }
+        for faction in sides {
+            if factions.gold(faction) < deal.gold_owed(faction) {
+                return Err(DiplomacyError::CannotAfford(faction));
+            }
+        }
+        for term in &deal.terms {
+            let captors = match term {
+                DealTerm::ReleasePrisoners { by } => vec![*by],
+                DealTerm::PrisonerExchange => sides.to_vec(),
+                DealTerm::Gold { .. } => Vec::new(),
+            };
+            if let Some(&captor) = captors.iter().find(|&&c| !prisoners.holds(c, deal.other(c))) {
+                return Err(DiplomacyError::NoPrisoners(captor));
+            }
+        }
+
+        let mut released = Vec::new();
+        for term in &deal.terms {
+            match term {
+                DealTerm::Gold { from, amount } => {
+                    let paid = factions.pay(*from, deal.other(*from), *amount);
+                    debug_assert!(paid, "what each side owes was checked against its gold above");
+                }
+                DealTerm::ReleasePrisoners { by } => {
+                    released.extend(prisoners.free(*by, deal.other(*by), factions));
+                }
+                DealTerm::PrisonerExchange => {
+                    for captor in sides {
+                        released.extend(prisoners.free(captor, deal.other(captor), factions));
+                    }
+                }
+            }
+        }
+
+        self.change_relations(deal.proposer, deal.recipient, DEAL_RELATIONS_GAIN);
+        Ok(released)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captured(captor: FactionId, faction: FactionId, men: u32) -> Prisoners {
+        let mut prisoners = Prisoners::default();
+        PrisonerGroup {
+            faction,
+            captor,
+            unit_type: UnitType::Infantry,
+            men,
+        }
+        .add_to(&mut prisoners.soldiers);
+        prisoners
+    }
+
+    #[test]
+    fn gold_terms_have_to_pay_something() {
+        let (a, b) = (FactionId::default(), FactionId::default());
+        let mut diplomacy = Diplomacy::default();
+        let mut factions = FactionStates::default();
+        let mut prisoners = captured(a, b, 50);
+
+        for amount in [0, -100] {
+            let deal = Deal::new(a, b)
+                .with(DealTerm::ReleasePrisoners { by: a })
+                .with(DealTerm::Gold { from: b, amount });
+            assert_eq!(
+                diplomacy.conclude(&deal, &mut factions, &mut prisoners).unwrap_err(),
+                DiplomacyError::InvalidAmount(amount)
+            );
+        }
+        assert_eq!(prisoners.men_held(a, b), 50);
+        assert_eq!((factions.gold(a), factions.gold(b)), (1000, 1000));
+        assert_eq!(diplomacy.relations(a, b), 0.0);
+    }
+
+    #[test]
+    fn only_the_two_sides_can_give_anything() {
+        let (a, b, c) = (FactionId::default(), FactionId::default(), FactionId::default());
+        let mut diplomacy = Diplomacy::default();
+        let mut factions = FactionStates::default();
+        let mut prisoners = captured(a, b, 50);
+        PrisonerGroup {
+            faction: b,
+            captor: c,
+            unit_type: UnitType::Cavalry,
+            men: 20,
+        }
+        .add_to(&mut prisoners.soldiers);
+
+        let deals = [
+            Deal::new(a, b)
+                .with(DealTerm::ReleasePrisoners { by: a })
+                .with(DealTerm::Gold { from: c, amount: 300 }),
+            Deal::new(a, b)
+                .with(DealTerm::Gold { from: b, amount: 300 })
+                .with(DealTerm::ReleasePrisoners { by: c }),
+        ];
+        for deal in &deals {
+            assert_eq!(
+                diplomacy.conclude(deal, &mut factions, &mut prisoners).unwrap_err(),
+                DiplomacyError::NotAParty(c)
+            );
+        }
+        assert_eq!((prisoners.men_held(a, b), prisoners.men_held(c, b)), (50, 20));
+        assert_eq!((factions.gold(a), factions.gold(b), factions.gold(c)), (1000, 1000, 1000));
+        assert_eq!(diplomacy.relations(a, b), 0.0);
+    }
+
+    #[test]
+    fn a_deal_goes_through_whole_or_not_at_all() {
+        let (a, b) = (FactionId::default(), FactionId::default());
+        let mut diplomacy = Diplomacy::default();
+        let mut factions = FactionStates::default();
+        let mut prisoners = captured(a, b, 50);
+
+        let too_dear = Deal::new(a, b)
+            .with(DealTerm::ReleasePrisoners { by: a })
+            .with(DealTerm::Gold { from: b, amount: 5000 });
+        assert_eq!(
+            diplomacy.conclude(&too_dear, &mut factions, &mut prisoners).unwrap_err(),
+            DiplomacyError::CannotAfford(b)
+        );
+        assert_eq!(prisoners.men_held(a, b), 50);
+
+        let deal = Deal::new(a, b)
+            .with(DealTerm::ReleasePrisoners { by: a })
+            .with(DealTerm::Gold { from: b, amount: 300 });
+        let released = diplomacy.conclude(&deal, &mut factions, &mut prisoners).unwrap();
+        assert_eq!(released.iter().map(|c| c.men).sum::<u32>(), 50);
+        assert_eq!((factions.gold(a), factions.gold(b)), (1300, 700));
+        assert_eq!(factions.get(b).unwrap().treasury.manpower, 150);
+        assert!(diplomacy.relations(a, b) > 0.0);
+    }
+
+    #[test]
+    fn an_exchange_needs_prisoners_on_both_sides() {
+        let (a, b) = (FactionId::default(), FactionId::default());
+        let mut diplomacy = Diplomacy::default();
+        let mut factions = FactionStates::default();
+        let mut prisoners = captured(a, b, 30);
+        let exchange = Deal::new(a, b).with(DealTerm::PrisonerExchange);
+
+        assert_eq!(
+            diplomacy.conclude(&exchange, &mut factions, &mut prisoners).unwrap_err(),
+            DiplomacyError::NoPrisoners(b)
+        );
+
+        PrisonerGroup {
+            faction: a,
+            captor: b,
+            unit_type: UnitType::Archers,
+            men: 20,
+        }
+        .add_to(&mut prisoners.soldiers);
+        let released = diplomacy.conclude(&exchange, &mut factions, &mut prisoners).unwrap();
+        assert_eq!(released.len(), 2);
+        assert!(!prisoners.holds(a, b) && !prisoners.holds(b, a));
+        assert_eq!(
+            diplomacy.conclude(&Deal::new(a, a), &mut factions, &mut prisoners).unwrap_err(),
+            DiplomacyError::SameFaction
+        );
+    }
+}
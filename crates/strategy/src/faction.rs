@@ -0,0 +1,63 @@
+use crate::*;
+
+// ============================================================================
+// FACTION STATE
+// ============================================================================
+
+pub const MAX_STABILITY: f32 = 100.0;
+const STARTING_STABILITY: f32 = 75.0;
+
+/// What a faction has besides its provinces and armies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FactionState {
+    pub treasury: Resources,
+    pub stability: f32, // 0.0 to MAX_STABILITY
+}
+
+impl Default for FactionState {
+    fn default() -> Self {
+        Self {
+            treasury: Resources::new(),
+            stability: STARTING_STABILITY,
+        }
+    }
+}
+
+impl FactionState {
+    pub fn change_stability(&mut self, amount: f32) {
+        self.stability = (self.stability + amount).clamp(0.0, MAX_STABILITY);
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct FactionStates {
+    pub factions: HashMap<FactionId, FactionState>,
+}
+
+impl FactionStates {
+    pub fn get(&self, faction: FactionId) -> Option<&FactionState> {
+        self.factions.get(&faction)
+    }
+
+    /// The state of `faction`, set up with the starting treasury and
+    /// stability the first time it is needed.
+    pub fn get_mut(&mut self, faction: FactionId) -> &mut FactionState {
+        self.factions.entry(faction).or_default()
+    }
+
+    pub fn gold(&self, faction: FactionId) -> i32 {
+        self.get(faction).map_or(Resources::new().gold, |f| f.treasury.gold)
+    }
+
+    /// Moves `gold` from one treasury to the other. Returns false, and
+    /// moves nothing, if `from` can't pay it.
+    pub fn pay(&mut self, from: FactionId, to: FactionId, gold: i32) -> bool {
+        if self.gold(from) < gold {
+            return false;
+        }
+
+        self.get_mut(from).treasury.gold -= gold;
+        self.get_mut(to).treasury.gold += gold;
+        true
+    }
+}
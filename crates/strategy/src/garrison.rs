@@ -0,0 +1,528 @@
+use crate::*;
+
+// ============================================================================
+// GARRISONS
+// ============================================================================
+
+/// Men in a garrison company at full strength.
+pub const GARRISON_COMPANY_SIZE: u32 = 100;
+
+/// Companies a province keeps for each level of its Fort and City.
+const INFANTRY_PER_FORT_LEVEL: u32 = 2;
+const ARCHERS_PER_FORT_LEVEL: u32 = 1;
+const INFANTRY_PER_CITY_LEVEL: u32 = 1;
+
+/// Men and morale each company gets back on a turn it isn't under siege.
+const REGENERATION_PER_TURN: u32 = 10;
+const MORALE_RECOVERY_PER_TURN: f32 = 20.0;
+
+/// Share of the garrison that walks off on a turn its upkeep goes unpaid.
+const DESERTION_SHARE: f32 = 0.1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GarrisonEvent {
+    Reinforced { men: u32 },
+    Deserted { men: u32 }, // The owner couldn't pay the upkeep
+}
+
+/// Troops tied to a province rather than to an army. They never leave it,
+/// fight in every battle over it and have to be beaten or starved out
+/// before it can change hands.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Garrison {
+    pub province: ProvinceId,
+    pub units: Vec<Unit>,
+}
+
+impl Garrison {
+    pub fn new(province: ProvinceId) -> Self {
+        Self {
+            province,
+            units: Vec::new(),
+        }
+    }
+
+    /// Companies the province's Fort and City call for.
+    pub fn establishment(province: &Province) -> Vec<UnitType> {
+        let infantry = INFANTRY_PER_FORT_LEVEL * province.fort_level() + INFANTRY_PER_CITY_LEVEL * province.city_level();
+        let archers = ARCHERS_PER_FORT_LEVEL * province.fort_level();
+        std::iter::repeat_n(UnitType::Infantry, infantry as usize)
+            .chain(std::iter::repeat_n(UnitType::Archers, archers as usize))
+            .collect()
+    }
+
+    /// A garrison at full strength for the province's owner, for a new game.
+    pub fn full(province: &Province) -> Self {
+        let mut garrison = Self::new(province.id);
+        if let Some(owner) = province.owner {
+            garrison.units = Self::establishment(province)
+                .into_iter()
+                .map(|unit_type| Self::company(unit_type, owner, GARRISON_COMPANY_SIZE))
+                .collect();
+        }
+        garrison
+    }
+
+    fn company(unit_type: UnitType, owner: FactionId, men: u32) -> Unit {
+        Unit {
+            id: UnitId::default(),
+            unit_type,
+            count: men,
+            max_count: GARRISON_COMPANY_SIZE,
+            morale: 100.0,
+            experience: 0,
+            formation: Formation::Line,
+            faction: owner,
+        }
+    }
+
+    pub fn total_men(&self) -> u32 {
+        self.units.iter().map(|u| u.count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_men() == 0
+    }
+
+    pub fn remove_dead_units(&mut self) {
+        self.units.retain(|u| u.count > 0);
+    }
+
+    /// What the garrison costs its owner every turn. Companies below
+    /// strength cost their share of a full one. Errors if a company isn't
+    /// in the registry.
+    pub fn upkeep(&self, registry: &UnitRegistry) -> Result<Resources, UnitRegistryError> {
+        let mut upkeep = Resources {
+            gold: 0,
+            food: 0,
+            materials: 0,
+            manpower: 0,
+        };
+        for unit in &self.units {
+            let definition = registry.definition(&unit.unit_type)?;
+            let share = unit.count as f32 / unit.max_count.max(1) as f32;
+            let scaled = |amount: i32| (amount as f32 * share).round() as i32;
+            upkeep.add(&Resources {
+                gold: scaled(definition.upkeep.gold),
+                food: scaled(definition.upkeep.food),
+                materials: scaled(definition.upkeep.materials),
+                manpower: scaled(definition.upkeep.manpower),
+            });
+        }
+        Ok(upkeep)
+    }
+
+    /// Brings the garrison back up towards what the province calls for:
+    /// companies lost are raised anew and every company takes in a few men.
+    /// Troops of anyone but the owner are let go. Returns the men gained.
+    fn regenerate(&mut self, province: &Province) -> u32 {
+        let Some(owner) = province.owner else {
+            self.units.clear();
+            return 0;
+        };
+        self.units.retain(|u| u.faction == owner);
+
+        let before = self.total_men();
+        let mut missing = Self::establishment(province);
+        for unit in &self.units {
+            if let Some(index) = missing.iter().position(|t| *t == unit.unit_type) {
+                missing.swap_remove(index);
+            }
+        }
+        for unit_type in missing {
+            self.units.push(Self::company(unit_type, owner, 0));
+        }
+        for unit in self.units.iter_mut() {
+            unit.count = (unit.count + REGENERATION_PER_TURN).min(unit.max_count);
+            unit.morale = (unit.morale + MORALE_RECOVERY_PER_TURN).min(100.0);
+        }
+        self.total_men() - before
+    }
+
+    fn desert(&mut self) -> u32 {
+        let mut deserted = 0;
+        for unit in self.units.iter_mut() {
+            let lost = ((unit.count as f32 * DESERTION_SHARE).ceil() as u32).min(unit.count);
+            unit.count -= lost;
+            deserted += lost;
+        }
+        self.remove_dead_units();
+        deserted
+    }
+
+    /// The garrison as units of a tactical battle on `side`. They start out
+    /// holding their ground in the middle of the side's deployment zone.
+    /// Errors if a company isn't in the registry.
+    pub fn battle_units(
+        &self,
+        battlefield: &Battlefield,
+        side: BattleSide,
+        registry: &UnitRegistry,
+    ) -> Result<Vec<BattleUnit>, UnitRegistryError> {
+        let zone = battlefield.deployment_zone(side);
+        let centre = Position::new((zone.min_x + zone.max_x) / 2.0, (zone.min_y + zone.max_y) / 2.0);
+        self.units
+            .iter()
+            .filter(|u| u.count > 0)
+            .map(|unit| {
+                let mut battle_unit = BattleUnit::from_registry(registry, unit.clone(), side, centre.clone())?;
+                battle_unit.hold_position = true;
+                Ok(battle_unit)
+            })
+            .collect()
+    }
+}
+
+/// Every province's garrison, keyed by province.
+#[derive(Resource, Debug, Default)]
+pub struct Garrisons {
+    pub garrisons: HashMap<ProvinceId, Garrison>,
+}
+
+impl Garrisons {
+    pub fn get(&self, province: ProvinceId) -> Option<&Garrison> {
+        self.garrisons.get(&province)
+    }
+
+    pub fn get_mut(&mut self, province: ProvinceId) -> Option<&mut Garrison> {
+        self.garrisons.get_mut(&province)
+    }
+
+    pub fn men(&self, province: ProvinceId) -> u32 {
+        self.get(province).map_or(0, |g| g.total_men())
+    }
+
+    /// True while the province has a garrison that hasn't been beaten or
+    /// starved out.
+    pub fn is_defended(&self, province: ProvinceId) -> bool {
+        self.men(province) > 0
+    }
+
+    /// Puts a full garrison into every owned province, for a new game.
+    pub fn raise_all(&mut self, world: &WorldMap) {
+        for province in world.provinces.values().filter(|p| p.owner.is_some()) {
+            self.garrisons.insert(province.id, Garrison::full(province));
+        }
+    }
+
+    /// Takes the garrison out of a province that changed hands, leaving
+    /// the new owner to raise its own. Returns whoever was still in it.
+    pub fn surrender(&mut self, province: ProvinceId) -> Vec<Unit> {
+        self.garrisons.remove(&province).map(|g| g.units).unwrap_or_default()
+    }
+
+    /// Adds the province's garrison to a tactical battle fought in it, on
+    /// the side of the province's owner if the owner fights in it. Call
+    /// before deployment. Errors, adding nobody, if a company isn't in the
+    /// registry.
+    pub fn join_battle(
+        &self,
+        province: &Province,
+        simulation: &mut BattleSimulation,
+        registry: &UnitRegistry,
+    ) -> Result<(), UnitRegistryError> {
+        let (Some(owner), Some(garrison)) = (province.owner, self.get(province.id)) else {
+            return Ok(());
+        };
+        let Some(side) = simulation.battle.side_of(owner) else {
+            return Ok(());
+        };
+
+        for unit in garrison.battle_units(&simulation.battle.battlefield, side, registry)? {
+            simulation.add_unit(unit);
+        }
+        Ok(())
+    }
+
+    /// Hands the survivors of a tactical battle back to the garrison that
+    /// joined it with `join_battle`.
+    pub fn after_battle(&mut self, province: ProvinceId, simulation: &BattleSimulation) {
+        let Some(garrison) = self.garrisons.get_mut(&province) else {
+            return;
+        };
+
+        for unit in garrison.units.iter_mut() {
+            if let Some(fought) = simulation.unit(unit.id) {
+                *unit = fought.unit.clone();
+            }
+        }
+        garrison.remove_dead_units();
+    }
+
+    /// Runs one world map turn of every garrison: the owner pays the
+    /// upkeep, and a paid garrison that isn't under siege takes in men. An
+    /// unpaid one loses men to desertion instead. Errors, changing nothing,
+    /// if a company isn't in the registry.
+    pub fn advance_turn(
+        &mut self,
+        world: &WorldMap,
+        factions: &mut FactionStates,
+        sieges: &Sieges,
+        registry: &UnitRegistry,
+    ) -> Result<Vec<(ProvinceId, GarrisonEvent)>, UnitRegistryError> {
+        for garrison in self.garrisons.values() {
+            garrison.upkeep(registry)?;
+        }
+
+        let mut events = Vec::new();
+        let mut provinces: Vec<&Province> = world
+            .provinces
+            .values()
+            .filter(|p| p.owner.is_some() && !Garrison::establishment(p).is_empty())
+            .collect();
+        provinces.sort_by_key(|p| p.id.0);
+
+        for province in provinces {
+            let Some(owner) = province.owner else {
+                continue;
+            };
+            let garrison = self.garrisons.entry(province.id).or_insert_with(|| Garrison::new(province.id));
+
+            // Every garrison was checked against the registry above
+            let upkeep = garrison.upkeep(registry)?;
+            if !factions.get_mut(owner).treasury.subtract(&upkeep) {
+                let men = garrison.desert();
+                if men > 0 {
+                    events.push((province.id, GarrisonEvent::Deserted { men }));
+                }
+                continue;
+            }
+
+            if !sieges.is_besieged(province.id) {
+                let men = garrison.regenerate(province);
+                if men > 0 {
+                    events.push((province.id, GarrisonEvent::Reinforced { men }));
+                }
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn province(owner: FactionId, buildings: &[(BuildingType, u32)]) -> Province {
+        Province {
+            id: ProvinceId::default(),
+            name: "Test Keep".to_string(),
+            owner: Some(owner),
+            position: Position::new(0.0, 0.0),
+            population: 0,
+            resources: Resources::new(),
+            buildings: buildings
+                .iter()
+                .map(|(building_type, level)| Building {
+                    building_type: building_type.clone(),
+                    level: *level,
+                    construction_progress: 1.0,
+                })
+                .collect(),
+            adjacent_provinces: Vec::new(),
+            terrain_type: TerrainType::Plains,
+        }
+    }
+
+    fn world(province: &Province) -> WorldMap {
+        let mut world = WorldMap::new();
+        world.add_province(province.clone());
+        world
+    }
+
+    fn garrisoned(province: &Province, units: Vec<Unit>) -> Garrisons {
+        let mut garrisons = Garrisons::default();
+        let mut garrison = Garrison::new(province.id);
+        garrison.units = units;
+        garrisons.garrisons.insert(province.id, garrison);
+        garrisons
+    }
+
+    fn company(unit_type: UnitType, owner: FactionId, men: u32) -> Unit {
+        Garrison::company(unit_type, owner, men)
+    }
+
+    /// An army of `companies` full infantry companies.
+    fn army(owner: FactionId, province: ProvinceId, companies: usize) -> Army {
+        let mut army = Army::new("Besiegers".to_string(), owner, province);
+        army.units = (0..companies).map(|_| company(UnitType::Infantry, owner, 100)).collect();
+        army
+    }
+
+    fn no_money() -> Resources {
+        Resources {
+            gold: 0,
+            food: 0,
+            materials: 0,
+            manpower: 0,
+        }
+    }
+
+    #[test]
+    fn forts_and_cities_call_for_companies() {
+        let owner = FactionId::default();
+        let keep = province(owner, &[(BuildingType::Fort, 2), (BuildingType::City, 1)]);
+        let establishment = Garrison::establishment(&keep);
+        assert_eq!(establishment.iter().filter(|t| **t == UnitType::Infantry).count(), 5);
+        assert_eq!(establishment.iter().filter(|t| **t == UnitType::Archers).count(), 2);
+
+        let garrison = Garrison::full(&keep);
+        assert_eq!(garrison.total_men(), 7 * GARRISON_COMPANY_SIZE);
+        assert!(garrison.units.iter().all(|u| u.faction == owner));
+        assert!(Garrison::establishment(&province(owner, &[(BuildingType::Farm, 3)])).is_empty());
+    }
+
+    #[test]
+    fn a_paid_garrison_fills_back_up_unless_besieged() {
+        let owner = FactionId::default();
+        let keep = province(owner, &[(BuildingType::Fort, 1)]);
+        let world = world(&keep);
+        let registry = UnitRegistry::default();
+        let mut factions = FactionStates::default();
+        let mut garrisons = garrisoned(&keep, vec![company(UnitType::Infantry, owner, 50)]);
+        let upkeep = garrisons.get(keep.id).unwrap().upkeep(&registry).unwrap();
+
+        let events = garrisons.advance_turn(&world, &mut factions, &Sieges::default(), &registry).unwrap();
+        assert_eq!(events, vec![(keep.id, GarrisonEvent::Reinforced { men: 30 })]);
+        assert_eq!(factions.gold(owner), Resources::new().gold - upkeep.gold);
+        let men: Vec<u32> = garrisons.get(keep.id).unwrap().units.iter().map(|u| u.count).collect();
+        assert_eq!(men, vec![60, 10, 10]);
+
+        let mut sieges = Sieges::default();
+        sieges.sieges.insert(keep.id, Siege::new(&keep, ArmyId::default()));
+        let events = garrisons.advance_turn(&world, &mut factions, &sieges, &registry).unwrap();
+        assert!(events.is_empty());
+        assert_eq!(garrisons.men(keep.id), 80);
+    }
+
+    #[test]
+    fn an_unpaid_garrison_deserts() {
+        let owner = FactionId::default();
+        let keep = province(owner, &[(BuildingType::Fort, 1)]);
+        let mut garrisons = Garrisons::default();
+        garrisons.raise_all(&world(&keep));
+        let mut factions = FactionStates::default();
+        factions.get_mut(owner).treasury = no_money();
+
+        let events = garrisons
+            .advance_turn(&world(&keep), &mut factions, &Sieges::default(), &UnitRegistry::default())
+            .unwrap();
+        assert_eq!(events, vec![(keep.id, GarrisonEvent::Deserted { men: 30 })]);
+        assert_eq!(garrisons.men(keep.id), 270);
+        assert_eq!(factions.get(owner).unwrap().treasury.gold, 0);
+    }
+
+    #[test]
+    fn unknown_companies_are_errors_that_change_nothing() {
+        let owner = FactionId::default();
+        let keep = province(owner, &[(BuildingType::Fort, 1)]);
+        let registry = UnitRegistry::default();
+        let ghosts = company(UnitType::Special("Ghosts".to_string()), owner, 40);
+        let mut garrisons = garrisoned(&keep, vec![company(UnitType::Infantry, owner, 50), ghosts]);
+        let mut factions = FactionStates::default();
+
+        let result = garrisons.advance_turn(&world(&keep), &mut factions, &Sieges::default(), &registry);
+        assert!(matches!(result, Err(UnitRegistryError::UnknownUnit(_))));
+        assert_eq!(garrisons.men(keep.id), 90);
+        assert_eq!(factions.gold(owner), Resources::new().gold);
+
+        let battlefield = BattlefieldGenerator::from_province(&keep, 1);
+        let battle = Battle::new("garrison".to_string(), FactionId::default(), owner, battlefield);
+        let mut simulation = BattleSimulation::new(battle, 1);
+        assert!(garrisons.join_battle(&keep, &mut simulation, &registry).is_err());
+        assert!(simulation.units.is_empty());
+    }
+
+    #[test]
+    fn the_garrison_holds_its_ground_in_battles_over_its_province() {
+        let owner = FactionId::default();
+        let keep = province(owner, &[(BuildingType::Fort, 1)]);
+        let mut garrisons = Garrisons::default();
+        garrisons.raise_all(&world(&keep));
+
+        let battlefield = BattlefieldGenerator::from_province(&keep, 2);
+        let battle = Battle::new("garrison".to_string(), FactionId::default(), owner, battlefield);
+        let mut simulation = BattleSimulation::new(battle, 2);
+        garrisons.join_battle(&keep, &mut simulation, &UnitRegistry::default()).unwrap();
+
+        assert_eq!(simulation.battle.defender_units.len(), 3);
+        assert!(simulation
+            .units
+            .iter()
+            .all(|u| u.side == BattleSide::Defender && u.hold_position && !u.allowed_formations.is_empty()));
+    }
+
+    #[test]
+    fn a_starved_out_garrison_surrenders_the_province() {
+        let (owner, besieger) = (FactionId::default(), FactionId::default());
+        let keep = province(owner, &[(BuildingType::Fort, 1)]);
+        let mut world = world(&keep);
+        let mut armies = Armies::default();
+        let army = armies.add(army(besieger, keep.id, 2));
+        let mut garrisons = garrisoned(&keep, vec![company(UnitType::Infantry, owner, 1)]);
+        let mut sieges = Sieges::default();
+        sieges.begin(&world, &armies, &garrisons, keep.id, army).unwrap();
+        sieges.sieges.get_mut(&keep.id).unwrap().supplies = 0;
+
+        let events = sieges
+            .advance_turn(&mut world, &mut armies, &mut garrisons, &UnitRegistry::default())
+            .unwrap();
+        assert!(events.contains(&(keep.id, SiegeEvent::Starving { deaths: 1 })));
+        assert!(events.contains(&(keep.id, SiegeEvent::Surrendered)));
+        assert_eq!(world.get_province(keep.id).unwrap().owner, Some(besieger));
+        assert!(garrisons.get(keep.id).is_none());
+        assert!(!sieges.is_besieged(keep.id));
+    }
+
+    #[test]
+    fn a_stormed_garrison_fights_and_is_taken() {
+        let (owner, besieger) = (FactionId::default(), FactionId::default());
+        let keep = province(owner, &[(BuildingType::Fort, 1)]);
+        let mut world = world(&keep);
+        let mut armies = Armies::default();
+        let army = armies.add(army(besieger, keep.id, 8));
+        let mut garrisons = garrisoned(&keep, vec![company(UnitType::Infantry, owner, 20)]);
+        let mut sieges = Sieges::default();
+        sieges.begin(&world, &armies, &garrisons, keep.id, army).unwrap();
+
+        let outcome = sieges
+            .assault(
+                keep.id,
+                &mut world,
+                &mut armies,
+                &mut garrisons,
+                &UnitRegistry::default(),
+                &WorldCalendar::default(),
+                5,
+            )
+            .unwrap();
+        assert_eq!(outcome.winner, Some(BattleSide::Attacker));
+        assert_eq!(outcome.faction(owner).map(|f| f.men), Some(20));
+        assert_eq!(world.get_province(keep.id).unwrap().owner, Some(besieger));
+        assert!(garrisons.get(keep.id).is_none());
+        assert!(!sieges.is_besieged(keep.id));
+    }
+
+    #[test]
+    fn a_garrisoned_city_can_be_besieged_without_a_fort() {
+        let (owner, besieger) = (FactionId::default(), FactionId::default());
+        let town = province(owner, &[(BuildingType::City, 1)]);
+        let world = world(&town);
+        let mut armies = Armies::default();
+        let army = armies.add(army(besieger, town.id, 1));
+        let mut sieges = Sieges::default();
+
+        let undefended = Garrisons::default();
+        assert_eq!(
+            sieges.begin(&world, &armies, &undefended, town.id, army),
+            Err(SiegeError::NotFortified)
+        );
+
+        let mut garrisons = Garrisons::default();
+        garrisons.raise_all(&world);
+        assert_eq!(garrisons.men(town.id), GARRISON_COMPANY_SIZE);
+        sieges.begin(&world, &armies, &garrisons, town.id, army).unwrap();
+        assert_eq!(sieges.get(town.id).unwrap().wall_strength, 0.0);
+    }
+}
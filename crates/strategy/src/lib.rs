@@ -1,7 +1,23 @@
 use bevy::prelude::*;
+use dot_wars_battle::*;
 use dot_wars_core::*;
 use dot_wars_world::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+mod army;
+mod diplomacy;
+mod faction;
+mod garrison;
+mod prisoners;
+mod siege;
+
+pub use army::*;
+pub use diplomacy::*;
+pub use faction::*;
+pub use garrison::*;
+pub use prisoners::*;
+pub use siege::*;
 
 // TODO: Strategy systems will be implemented here
 // - Diplomacy
@@ -13,6 +29,14 @@ pub struct StrategyPlugin;
 
 impl Plugin for StrategyPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<Armies>()
+            .init_resource::<Diplomacy>()
+            .init_resource::<FactionStates>()
+            .init_resource::<Garrisons>()
+            .init_resource::<Prisoners>()
+            .init_resource::<Sieges>()
+            .init_resource::<WorldCalendar>();
+
         println!("Strategy Plugin loaded - Grand Strategy systems initialized");
     }
 }
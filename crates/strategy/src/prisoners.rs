@@ -0,0 +1,280 @@
+use crate::*;
+use std::fmt;
+
+// ============================================================================
+// PRISONERS
+// ============================================================================
+
+/// Gold a captor asks for each man, and for each general on top of a share
+/// for every skill level and victory of theirs.
+const RANSOM_PER_MAN: i32 = 5;
+const GENERAL_RANSOM: i32 = 200;
+const GENERAL_RANSOM_PER_SKILL_LEVEL: i32 = 25;
+const GENERAL_RANSOM_PER_VICTORY: i32 = 50;
+
+/// Putting prisoners to death shakes the captor's own people and sours its
+/// relations with their faction, and a little with everyone else it deals
+/// with. Killing a general is remembered the most.
+const EXECUTION_STABILITY_LOSS: f32 = 5.0;
+const EXECUTION_STABILITY_LOSS_PER_GENERAL: f32 = 5.0;
+const EXECUTION_RELATIONS_LOSS: f32 = 30.0;
+const EXECUTION_RELATIONS_LOSS_PER_GENERAL: f32 = 20.0;
+const EXECUTION_CONTACT_RELATIONS_LOSS: f32 = 5.0;
+
+/// Letting prisoners go for nothing earns some goodwill.
+const RELEASE_RELATIONS_GAIN: f32 = 15.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrisonerError {
+    NoPrisoners,
+    CannotAfford,
+}
+
+impl fmt::Display for PrisonerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrisonerError::NoPrisoners => write!(f, "no prisoners of that faction are held"),
+            PrisonerError::CannotAfford => write!(f, "the ransom is more than the faction can pay"),
+        }
+    }
+}
+
+impl std::error::Error for PrisonerError {}
+
+/// Prisoners of one faction that left the captor's hands, either going
+/// home or to their deaths.
+#[derive(Debug, Clone)]
+pub struct Captives {
+    pub faction: FactionId,
+    pub men: u32,
+    pub generals: Vec<General>,
+}
+
+/// Everyone held prisoner after the battles fought so far. Generals keep
+/// `GeneralStatus::Captured` for as long as they are held.
+#[derive(Resource, Debug, Default)]
+pub struct Prisoners {
+    pub soldiers: Vec<PrisonerGroup>,
+    pub generals: Vec<General>,
+}
+
+impl Prisoners {
+    /// Takes in everyone captured in a battle.
+    pub fn take(&mut self, outcome: &BattleOutcome) {
+        for group in &outcome.prisoners {
+            group.clone().add_to(&mut self.soldiers);
+        }
+        self.generals.extend(outcome.captured_generals.iter().cloned());
+    }
+
+    pub fn men_held(&self, captor: FactionId, faction: FactionId) -> u32 {
+        self.soldiers
+            .iter()
+            .filter(|p| p.captor == captor && p.faction == faction)
+            .map(|p| p.men)
+            .sum()
+    }
+
+    pub fn generals_held(&self, captor: FactionId, faction: FactionId) -> impl Iterator<Item = &General> {
+        self.generals
+            .iter()
+            .filter(move |g| g.status == GeneralStatus::Captured(captor) && g.faction == faction)
+    }
+
+    /// True when `captor` holds anyone of `faction`.
+    pub fn holds(&self, captor: FactionId, faction: FactionId) -> bool {
+        self.men_held(captor, faction) > 0 || self.generals_held(captor, faction).next().is_some()
+    }
+
+    /// Gold `captor` asks to let everyone of `faction` it holds go.
+    pub fn ransom_price(&self, captor: FactionId, faction: FactionId) -> i32 {
+        let generals: i32 = self
+            .generals_held(captor, faction)
+            .map(|g| {
+                let skills = g.skills.attack + g.skills.defense + g.skills.leadership + g.skills.movement;
+                GENERAL_RANSOM + GENERAL_RANSOM_PER_SKILL_LEVEL * skills as i32 + GENERAL_RANSOM_PER_VICTORY * g.victories as i32
+            })
+            .sum();
+        RANSOM_PER_MAN * self.men_held(captor, faction) as i32 + generals
+    }
+
+    /// `faction` buys its men and generals back from `captor` for the
+    /// `ransom_price`.
+    pub fn ransom(
+        &mut self,
+        captor: FactionId,
+        faction: FactionId,
+        factions: &mut FactionStates,
+    ) -> Result<Captives, PrisonerError> {
+        if !self.holds(captor, faction) {
+            return Err(PrisonerError::NoPrisoners);
+        }
+        if !factions.pay(faction, captor, self.ransom_price(captor, faction)) {
+            return Err(PrisonerError::CannotAfford);
+        }
+        self.free(captor, faction, factions).ok_or(PrisonerError::NoPrisoners)
+    }
+
+    /// `captor` lets everyone of `faction` it holds go for nothing.
+    pub fn release(
+        &mut self,
+        captor: FactionId,
+        faction: FactionId,
+        factions: &mut FactionStates,
+        diplomacy: &mut Diplomacy,
+    ) -> Result<Captives, PrisonerError> {
+        let released = self.free(captor, faction, factions).ok_or(PrisonerError::NoPrisoners)?;
+        diplomacy.change_relations(captor, faction, RELEASE_RELATIONS_GAIN);
+        Ok(released)
+    }
+
+    /// `captor` puts everyone of `faction` it holds to death. Costs it
+    /// stability and standing, see the `EXECUTION_` constants.
+    pub fn execute(
+        &mut self,
+        captor: FactionId,
+        faction: FactionId,
+        factions: &mut FactionStates,
+        diplomacy: &mut Diplomacy,
+    ) -> Result<Captives, PrisonerError> {
+        let mut executed = self.hand_over(captor, faction).ok_or(PrisonerError::NoPrisoners)?;
+        for general in executed.generals.iter_mut() {
+            general.status = GeneralStatus::Killed;
+        }
+
+        let generals = executed.generals.len() as f32;
+        factions
+            .get_mut(captor)
+            .change_stability(-(EXECUTION_STABILITY_LOSS + EXECUTION_STABILITY_LOSS_PER_GENERAL * generals));
+        for contact in diplomacy.contacts(captor) {
+            if contact != faction {
+                diplomacy.change_relations(captor, contact, -EXECUTION_CONTACT_RELATIONS_LOSS);
+            }
+        }
+        diplomacy.change_relations(
+            captor,
+            faction,
+            -(EXECUTION_RELATIONS_LOSS + EXECUTION_RELATIONS_LOSS_PER_GENERAL * generals),
+        );
+        Ok(executed)
+    }
+
+    /// Sends everyone of `faction` held by `captor` home. The men go back
+    /// into their faction's manpower, the generals are free to take command
+    /// again.
+    pub(crate) fn free(&mut self, captor: FactionId, faction: FactionId, factions: &mut FactionStates) -> Option<Captives> {
+        let mut freed = self.hand_over(captor, faction)?;
+        for general in freed.generals.iter_mut() {
+            general.status = GeneralStatus::Active;
+        }
+        factions.get_mut(faction).treasury.manpower += freed.men as i32;
+        Some(freed)
+    }
+
+    /// Takes everyone of `faction` out of `captor`'s hands.
+    fn hand_over(&mut self, captor: FactionId, faction: FactionId) -> Option<Captives> {
+        if !self.holds(captor, faction) {
+            return None;
+        }
+
+        let men = self.men_held(captor, faction);
+        self.soldiers.retain(|p| !(p.captor == captor && p.faction == faction));
+        let (generals, kept) = std::mem::take(&mut self.generals)
+            .into_iter()
+            .partition(|g| g.status == GeneralStatus::Captured(captor) && g.faction == faction);
+        self.generals = kept;
+        Some(Captives { faction, men, generals })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `captor` holding 40 men and a general with a victory of `faction`.
+    fn held(captor: FactionId, faction: FactionId) -> Prisoners {
+        let mut general = General::new("Prisoner".to_string(), faction);
+        general.skills.attack = 2;
+        general.victories = 1;
+        general.status = GeneralStatus::Captured(captor);
+
+        let mut prisoners = Prisoners::default();
+        for men in [30, 10] {
+            PrisonerGroup {
+                faction,
+                captor,
+                unit_type: UnitType::Infantry,
+                men,
+            }
+            .add_to(&mut prisoners.soldiers);
+        }
+        prisoners.generals.push(general);
+        prisoners
+    }
+
+    #[test]
+    fn ransom_buys_men_and_generals_back() {
+        let (captor, faction) = (FactionId::default(), FactionId::default());
+        let mut prisoners = held(captor, faction);
+        let mut factions = FactionStates::default();
+
+        assert_eq!(prisoners.soldiers.len(), 1);
+        let price = RANSOM_PER_MAN * 40 + GENERAL_RANSOM + 2 * GENERAL_RANSOM_PER_SKILL_LEVEL + GENERAL_RANSOM_PER_VICTORY;
+        assert_eq!(prisoners.ransom_price(captor, faction), price);
+
+        let freed = prisoners.ransom(captor, faction, &mut factions).unwrap();
+        assert_eq!(freed.men, 40);
+        assert_eq!(freed.generals[0].status, GeneralStatus::Active);
+        assert_eq!((factions.gold(faction), factions.gold(captor)), (1000 - price, 1000 + price));
+        assert_eq!(factions.get(faction).unwrap().treasury.manpower, 140);
+        assert!(!prisoners.holds(captor, faction));
+        assert_eq!(prisoners.ransom(captor, faction, &mut factions).unwrap_err(), PrisonerError::NoPrisoners);
+    }
+
+    #[test]
+    fn a_ransom_nobody_can_pay_frees_nobody() {
+        let (captor, faction) = (FactionId::default(), FactionId::default());
+        let mut prisoners = held(captor, faction);
+        let mut factions = FactionStates::default();
+        factions.get_mut(faction).treasury.gold = 10;
+
+        assert_eq!(prisoners.ransom(captor, faction, &mut factions).unwrap_err(), PrisonerError::CannotAfford);
+        assert_eq!(prisoners.men_held(captor, faction), 40);
+        assert_eq!(factions.gold(faction), 10);
+    }
+
+    #[test]
+    fn executions_cost_stability_and_standing() {
+        let (captor, faction, onlooker) = (FactionId::default(), FactionId::default(), FactionId::default());
+        let mut prisoners = held(captor, faction);
+        let mut factions = FactionStates::default();
+        let mut diplomacy = Diplomacy::default();
+        diplomacy.change_relations(captor, onlooker, 10.0);
+
+        let executed = prisoners.execute(captor, faction, &mut factions, &mut diplomacy).unwrap();
+        assert_eq!(executed.generals[0].status, GeneralStatus::Killed);
+        assert_eq!(
+            factions.get(captor).unwrap().stability,
+            75.0 - EXECUTION_STABILITY_LOSS - EXECUTION_STABILITY_LOSS_PER_GENERAL
+        );
+        assert_eq!(
+            diplomacy.relations(captor, faction),
+            -(EXECUTION_RELATIONS_LOSS + EXECUTION_RELATIONS_LOSS_PER_GENERAL)
+        );
+        assert_eq!(diplomacy.relations(captor, onlooker), 10.0 - EXECUTION_CONTACT_RELATIONS_LOSS);
+        assert!(!prisoners.holds(captor, faction));
+    }
+
+    #[test]
+    fn releasing_prisoners_earns_goodwill() {
+        let (captor, faction) = (FactionId::default(), FactionId::default());
+        let mut prisoners = held(captor, faction);
+        let mut factions = FactionStates::default();
+        let mut diplomacy = Diplomacy::default();
+
+        let freed = prisoners.release(captor, faction, &mut factions, &mut diplomacy).unwrap();
+        assert_eq!(freed.men, 40);
+        assert_eq!(diplomacy.relations(captor, faction), RELEASE_RELATIONS_GAIN);
+        assert_eq!(factions.gold(faction), 1000);
+    }
+}
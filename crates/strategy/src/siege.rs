@@ -0,0 +1,501 @@
+use crate::*;
+use std::fmt;
+
+// ============================================================================
+// SIEGES
+// ============================================================================
+
+/// Turns of food a besieged garrison starts with, before forts and cities
+/// add their stores.
+pub const BASE_SIEGE_SUPPLY_TURNS: u32 = 3;
+const SUPPLY_TURNS_PER_FORT_LEVEL: u32 = 2;
+const SUPPLY_TURNS_PER_CITY_LEVEL: u32 = 1;
+
+/// Share of the garrison lost every turn once the food has run out.
+const STARVATION_LOSS: f32 = 0.15;
+const STARVATION_MORALE_LOSS: f32 = 15.0;
+
+/// Seconds of artillery fire the besiegers get in between two turns.
+const BOMBARDMENT_SECONDS_PER_TURN: f32 = 60.0;
+
+/// A tactical assault is won by the attacker holding the fortifications
+/// this many seconds, and lost once the time limit runs out.
+const ASSAULT_HOLD_TIME: f32 = 60.0;
+const ASSAULT_TIME_LIMIT: f32 = 1200.0;
+
+/// Man-turns of work the besiegers need for each piece of equipment.
+const BATTERING_RAM_WORK: u32 = 400;
+const SIEGE_TOWER_WORK: u32 = 800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SiegeWork {
+    BatteringRam,
+    SiegeTower,
+}
+
+impl SiegeWork {
+    pub fn work_required(&self) -> u32 {
+        match self {
+            SiegeWork::BatteringRam => BATTERING_RAM_WORK,
+            SiegeWork::SiegeTower => SIEGE_TOWER_WORK,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SiegeEvent {
+    Starving { deaths: u32 },
+    EquipmentBuilt(SiegeWork),
+    WallsBreached,
+    Surrendered, // The garrison is gone and the province changed hands
+    Lifted,      // The besieging army left or was destroyed
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SiegeError {
+    UnknownProvince,
+    UnknownArmy,
+    ArmyNotInProvince,
+    OwnProvince,
+    NotFortified,
+    AlreadyBesieged,
+    NotBesieged,
+    InvalidUnit(String), // Rejected by the unit registry
+}
+
+impl fmt::Display for SiegeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SiegeError::UnknownProvince => write!(f, "the province does not exist"),
+            SiegeError::UnknownArmy => write!(f, "the army does not exist"),
+            SiegeError::ArmyNotInProvince => write!(f, "the army is not in the province"),
+            SiegeError::OwnProvince => write!(f, "an army cannot besiege its own province"),
+            SiegeError::NotFortified => write!(f, "the province has neither a fort nor a garrison to besiege"),
+            SiegeError::AlreadyBesieged => write!(f, "the province is already under siege"),
+            SiegeError::NotBesieged => write!(f, "the province is not under siege"),
+            SiegeError::InvalidUnit(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SiegeError {}
+
+impl From<UnitRegistryError> for SiegeError {
+    fn from(error: UnitRegistryError) -> Self {
+        SiegeError::InvalidUnit(error.to_string())
+    }
+}
+
+/// A province's fort held under siege by an army over several turns.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Siege {
+    pub province: ProvinceId,
+    pub besieger: ArmyId,
+    pub turns_elapsed: u32,
+    pub supplies: u32, // Turns the garrison can still eat
+    pub wall_strength: f32,
+    pub max_wall_strength: f32,
+    pub equipment: SiegeEquipment,
+    pub work: Option<SiegeWork>, // What the besiegers are building
+    pub work_progress: u32,
+}
+
+impl Siege {
+    pub fn new(province: &Province, besieger: ArmyId) -> Self {
+        Self {
+            province: province.id,
+            besieger,
+            turns_elapsed: 0,
+            supplies: Self::supply_turns(province),
+            wall_strength: province.wall_strength(),
+            max_wall_strength: province.wall_strength(),
+            equipment: SiegeEquipment::default(),
+            work: Some(SiegeWork::BatteringRam),
+            work_progress: 0,
+        }
+    }
+
+    pub fn supply_turns(province: &Province) -> u32 {
+        BASE_SIEGE_SUPPLY_TURNS
+            + SUPPLY_TURNS_PER_FORT_LEVEL * province.fort_level()
+            + SUPPLY_TURNS_PER_CITY_LEVEL * province.city_level()
+    }
+
+    pub fn is_starving(&self) -> bool {
+        self.supplies == 0
+    }
+
+    pub fn walls_breached(&self) -> bool {
+        self.wall_strength <= 0.0
+    }
+
+    /// Battlefield for storming the walls in a tactical battle, with the
+    /// damage done so far and the equipment built. Besides breaking the
+    /// garrison, the attacker can win by taking and holding the works, but
+    /// has to do it before the time limit. The weather is rolled for the
+    /// season on `calendar`.
+    pub fn tactical_assault(
+        &self,
+        province: &Province,
+        attacker: FactionId,
+        calendar: &WorldCalendar,
+        seed: u64,
+    ) -> Option<BattleSimulation> {
+        let defender = province.owner?;
+        let battlefield = BattlefieldGenerator::siege_assault(province, seed, self.wall_strength);
+        let works: Vec<Position> = battlefield
+            .terrain_effects
+            .iter()
+            .filter(|e| matches!(e.effect_type, TerrainEffectType::Fortification { .. }))
+            .map(|e| e.position.clone())
+            .collect();
+
+        let mut battle = Battle::new(format!("siege-{}-{}", province.id.0, self.turns_elapsed), attacker, defender, battlefield);
+        battle.conditions = BattleConditions::for_province(province, calendar, seed);
+        if !works.is_empty() {
+            battle.victory_conditions.push(VictoryCondition::capture_points(works, ASSAULT_HOLD_TIME));
+        }
+        battle.victory_conditions.push(VictoryCondition::TimeLimit {
+            seconds: ASSAULT_TIME_LIMIT,
+        });
+
+        let mut simulation = BattleSimulation::new(battle, seed);
+        simulation.siege_equipment = self.equipment.clone();
+        Some(simulation)
+    }
+
+    pub fn assault_context(&self, province: &Province, calendar: &WorldCalendar, seed: u64) -> AutoResolveContext {
+        AutoResolveContext {
+            defender_bonus: province.defense_bonus(),
+            wall_strength: self.wall_strength,
+            siege_equipment: self.equipment.clone(),
+            conditions: BattleConditions::for_province(province, calendar, seed),
+        }
+    }
+
+    fn bombard(&mut self, besieger: &Army, registry: &UnitRegistry) -> Result<Option<SiegeEvent>, SiegeError> {
+        if self.walls_breached() {
+            return Ok(None);
+        }
+
+        let damage = bombardment_damage(&besieger.units, registry, BOMBARDMENT_SECONDS_PER_TURN)?;
+        self.wall_strength = (self.wall_strength - damage).max(0.0);
+        Ok(self.walls_breached().then_some(SiegeEvent::WallsBreached))
+    }
+
+    fn build(&mut self, besieger: &Army) -> Option<SiegeEvent> {
+        let work = self.work?;
+        self.work_progress += besieger.total_men();
+        if self.work_progress < work.work_required() {
+            return None;
+        }
+
+        self.work_progress -= work.work_required();
+        match work {
+            SiegeWork::BatteringRam => self.equipment.battering_rams += 1,
+            SiegeWork::SiegeTower => self.equipment.siege_towers += 1,
+        }
+        Some(SiegeEvent::EquipmentBuilt(work))
+    }
+}
+
+/// Every siege going on, keyed by the besieged province.
+#[derive(Resource, Debug, Default)]
+pub struct Sieges {
+    pub sieges: HashMap<ProvinceId, Siege>,
+}
+
+impl Sieges {
+    pub fn get(&self, province: ProvinceId) -> Option<&Siege> {
+        self.sieges.get(&province)
+    }
+
+    pub fn is_besieged(&self, province: ProvinceId) -> bool {
+        self.sieges.contains_key(&province)
+    }
+
+    /// Lays siege to a fortified or garrisoned province. Provinces with
+    /// neither can be taken by simply beating the armies in them.
+    pub fn begin(
+        &mut self,
+        world: &WorldMap,
+        armies: &Armies,
+        garrisons: &Garrisons,
+        province: ProvinceId,
+        besieger: ArmyId,
+    ) -> Result<(), SiegeError> {
+        let target = world.get_province(province).ok_or(SiegeError::UnknownProvince)?;
+        let army = armies.get(besieger).ok_or(SiegeError::UnknownArmy)?;
+
+        if army.province != province {
+            return Err(SiegeError::ArmyNotInProvince);
+        }
+        if target.owner == Some(army.owner) {
+            return Err(SiegeError::OwnProvince);
+        }
+        if target.fort_level() == 0 && !garrisons.is_defended(province) {
+            return Err(SiegeError::NotFortified);
+        }
+        if self.is_besieged(province) {
+            return Err(SiegeError::AlreadyBesieged);
+        }
+
+        self.sieges.insert(province, Siege::new(target, besieger));
+        Ok(())
+    }
+
+    /// Chooses what the besiegers build next, `None` to stop building.
+    pub fn set_work(&mut self, province: ProvinceId, work: Option<SiegeWork>) -> Result<(), SiegeError> {
+        let siege = self.sieges.get_mut(&province).ok_or(SiegeError::NotBesieged)?;
+        if siege.work != work {
+            siege.work = work;
+            siege.work_progress = 0;
+        }
+        Ok(())
+    }
+
+    /// Runs one world map turn of every siege: food runs down and then the
+    /// garrison starves, the besiegers bombard the walls and build equipment.
+    /// Once the armies inside and the province's own garrison are both gone
+    /// the province surrenders to the besieger. Errors, changing nothing, if
+    /// a besieging unit isn't in the registry.
+    pub fn advance_turn(
+        &mut self,
+        world: &mut WorldMap,
+        armies: &mut Armies,
+        garrisons: &mut Garrisons,
+        registry: &UnitRegistry,
+    ) -> Result<Vec<(ProvinceId, SiegeEvent)>, SiegeError> {
+        for siege in self.sieges.values() {
+            for unit in armies.get(siege.besieger).map(|a| a.units.as_slice()).unwrap_or_default() {
+                registry.definition(&unit.unit_type)?;
+            }
+        }
+
+        let mut events = Vec::new();
+        let mut provinces: Vec<ProvinceId> = self.sieges.keys().copied().collect();
+        provinces.sort_by_key(|id| id.0);
+
+        for province_id in provinces {
+            let Some(mut siege) = self.sieges.remove(&province_id) else {
+                continue;
+            };
+            let besieger = armies
+                .get(siege.besieger)
+                .filter(|a| a.province == province_id && !a.is_destroyed())
+                .cloned();
+            let (Some(besieger), Some(province)) = (besieger, world.get_province(province_id)) else {
+                events.push((province_id, SiegeEvent::Lifted));
+                continue;
+            };
+
+            siege.turns_elapsed += 1;
+            let garrison = province
+                .owner
+                .map(|owner| armies.owned_in_province(province_id, owner))
+                .unwrap_or_default();
+
+            if siege.is_starving() {
+                let mut deaths = 0;
+                for &id in &garrison {
+                    if let Some(army) = armies.get_mut(id) {
+                        deaths += Self::starve(&mut army.units);
+                    }
+                }
+                if let Some(troops) = garrisons.get_mut(province_id) {
+                    deaths += Self::starve(&mut troops.units);
+                }
+                events.push((province_id, SiegeEvent::Starving { deaths }));
+            } else {
+                siege.supplies -= 1;
+            }
+
+            // Every besieger was checked against the registry above
+            events.extend(siege.bombard(&besieger, registry)?.map(|e| (province_id, e)));
+            events.extend(siege.build(&besieger).map(|e| (province_id, e)));
+
+            armies.remove_destroyed();
+            let garrison_left = garrison.iter().any(|&id| armies.get(id).is_some()) || garrisons.is_defended(province_id);
+            if garrison_left {
+                self.sieges.insert(province_id, siege);
+            } else {
+                garrisons.surrender(province_id);
+                world.set_owner(province_id, Some(besieger.owner));
+                events.push((province_id, SiegeEvent::Surrendered));
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn starve(units: &mut Vec<Unit>) -> u32 {
+        let mut deaths = 0;
+        for unit in units.iter_mut() {
+            let lost = ((unit.count as f32 * STARVATION_LOSS).ceil() as u32).min(unit.count);
+            unit.count -= lost;
+            unit.morale = (unit.morale - STARVATION_MORALE_LOSS).max(0.0);
+            deaths += lost;
+        }
+        units.retain(|u| u.count > 0);
+        deaths
+    }
+
+    /// Storms the walls with the auto-resolver. Armies of either side in
+    /// the neighbouring provinces march to the fight and join in once they
+    /// get there. A won assault captures the province and ends the siege, a
+    /// failed one leaves it going. The province's own garrison fights on
+    /// the defending side from the start. The garrison of a captured
+    /// province is taken prisoner along with whoever was caught in the
+    /// fighting. Errors, changing nothing, if a unit on either side isn't
+    /// in the registry.
+    #[allow(clippy::too_many_arguments)]
+    pub fn assault(
+        &mut self,
+        province: ProvinceId,
+        world: &mut WorldMap,
+        armies: &mut Armies,
+        garrisons: &mut Garrisons,
+        registry: &UnitRegistry,
+        calendar: &WorldCalendar,
+        seed: u64,
+    ) -> Result<BattleOutcome, SiegeError> {
+        let siege = self.sieges.get(&province).ok_or(SiegeError::NotBesieged)?;
+        let target = world.get_province(province).ok_or(SiegeError::UnknownProvince)?;
+        let context = siege.assault_context(target, calendar, seed);
+        let besieger_id = siege.besieger;
+        let besieger_owner = armies.get(besieger_id).ok_or(SiegeError::UnknownArmy)?.owner;
+        let garrison = target
+            .owner
+            .map(|owner| armies.owned_in_province(province, owner))
+            .unwrap_or_default();
+
+        let marching = |owner: FactionId| {
+            armies
+                .reinforcements(target, world, owner)
+                .into_iter()
+                .map(|r| (r.army, r.arrival_time))
+                .collect::<Vec<_>>()
+        };
+        let mut attacking = vec![(besieger_id, 0.0)];
+        attacking.extend(marching(besieger_owner));
+        let mut defending: Vec<(ArmyId, f32)> = garrison.iter().map(|&id| (id, 0.0)).collect();
+        defending.extend(target.owner.map(marching).unwrap_or_default());
+
+        let mut attackers = Muster::gather(armies, &attacking);
+        let mut defenders = Muster::gather(armies, &defending);
+        if let Some(troops) = garrisons.get_mut(province) {
+            defenders.garrison(troops);
+        }
+        let resolved = AutoResolve::resolve(attackers.side(), defenders.side(), &context, registry, seed);
+        attackers.disband(armies, garrisons);
+        defenders.disband(armies, garrisons);
+        let mut outcome = resolved?;
+        armies.remove_destroyed();
+
+        if outcome.winner == Some(BattleSide::Attacker) {
+            // Whoever is left inside lays down their arms
+            for army in garrison.into_iter().filter_map(|id| armies.remove(id)) {
+                Self::surrender_units(&mut outcome, army.units, besieger_owner);
+                if let Some(mut general) = army.general.filter(|g| g.is_active()) {
+                    general.status = GeneralStatus::Captured(besieger_owner);
+                    outcome.captured_generals.push(general);
+                }
+            }
+            Self::surrender_units(&mut outcome, garrisons.surrender(province), besieger_owner);
+            world.set_owner(province, Some(besieger_owner));
+            self.sieges.remove(&province);
+        }
+        Ok(outcome)
+    }
+
+    /// Counts units that laid down their arms as the defender's losses and
+    /// hands them to `captor` as prisoners.
+    fn surrender_units(outcome: &mut BattleOutcome, units: Vec<Unit>, captor: FactionId) {
+        for unit in units {
+            outcome.defender_casualties += unit.count;
+            if let Some(result) = outcome.factions.iter_mut().find(|f| f.faction == unit.faction) {
+                result.casualties += unit.count;
+            }
+            PrisonerGroup {
+                faction: unit.faction,
+                captor,
+                unit_type: unit.unit_type,
+                men: unit.count,
+            }
+            .add_to(&mut outcome.prisoners);
+        }
+    }
+}
+
+/// The units of several armies fighting as one side of an auto-resolved
+/// battle, kept together with what is needed to hand the survivors back.
+struct Muster {
+    units: Vec<Unit>,
+    arrivals: Vec<f32>,
+    sizes: Vec<(ArmyId, usize)>,
+    garrison: Option<ProvinceId>, // Its units come after the armies'
+    general: Option<(ArmyId, General)>,
+}
+
+impl Muster {
+    /// Takes the units out of each army, which joins the battle after the
+    /// given number of seconds. The first general still in command of an
+    /// army that is there from the start leads the side.
+    fn gather(armies: &mut Armies, marching: &[(ArmyId, f32)]) -> Self {
+        let mut muster = Muster {
+            units: Vec::new(),
+            arrivals: Vec::new(),
+            sizes: Vec::new(),
+            garrison: None,
+            general: None,
+        };
+
+        for &(id, arrival) in marching {
+            let Some(army) = armies.get_mut(id) else {
+                continue;
+            };
+            muster.sizes.push((id, army.units.len()));
+            muster.arrivals.extend(std::iter::repeat_n(arrival, army.units.len()));
+            muster.units.append(&mut army.units);
+            if muster.general.is_none() && arrival <= 0.0 && army.active_general().is_some() {
+                muster.general = army.general.take().map(|g| (id, g));
+            }
+        }
+        muster
+    }
+
+    /// Takes the units out of a province's garrison, which is there from
+    /// the start.
+    fn garrison(&mut self, garrison: &mut Garrison) {
+        self.garrison = Some(garrison.province);
+        self.arrivals.extend(std::iter::repeat_n(0.0, garrison.units.len()));
+        self.units.append(&mut garrison.units);
+    }
+
+    fn side(&mut self) -> AutoResolveSide<'_> {
+        let general = self.general.as_mut().map(|(_, g)| g);
+        AutoResolveSide::new(&mut self.units, general).with_arrivals(self.arrivals.clone())
+    }
+
+    /// Hands the survivors and the general back to the armies and the
+    /// garrison they came from.
+    fn disband(self, armies: &mut Armies, garrisons: &mut Garrisons) {
+        if let Some((id, general)) = self.general {
+            if let Some(army) = armies.get_mut(id) {
+                army.general = Some(general);
+            }
+        }
+
+        let mut survivors = self.units.into_iter();
+        for (id, size) in self.sizes {
+            if let Some(army) = armies.get_mut(id) {
+                army.units = survivors.by_ref().take(size).collect();
+                army.remove_dead_units();
+            }
+        }
+        if let Some(garrison) = self.garrison.and_then(|province| garrisons.get_mut(province)) {
+            garrison.units = survivors.collect();
+            garrison.remove_dead_units();
+        }
+    }
+}
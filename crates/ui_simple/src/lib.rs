@@ -1,5 +1,9 @@
 use bevy::prelude::*;
-use dot_wars_core::GameState;
+use bevy::window::PrimaryWindow;
+use dot_wars_battle::{
+    BattleClock, BattleSelection, BattleSide, BattleSimulation, BattleUnit, BattlefieldEdge, ReinforcementsArrived,
+};
+use dot_wars_core::{GameState, Position};
 
 // SimpleUIPlugin'i public olarak export et
 #[derive(Default)]
@@ -48,16 +52,33 @@ impl Plugin for SimpleUIPlugin {
     fn build(&self, app: &mut App) {
         app
             .init_resource::<UiAnimationTimer>()
+            .init_resource::<SelectionDrag>()
+            .add_event::<ReinforcementsArrived>()
             .add_systems(Startup, load_fonts)
             .add_systems(Update, (
                 check_fonts_and_setup_ui,
                 animate_ui_elements,
                 handle_main_menu_buttons.run_if(in_state(GameState::MainMenu)),
-                handle_world_map_ui.run_if(in_state(GameState::WorldMap))
+                handle_world_map_ui.run_if(in_state(GameState::WorldMap)),
+                show_reinforcement_notices.run_if(in_state(GameState::Battle)),
+                expire_reinforcement_notices,
+                (handle_battle_speed_keys, update_battle_speed_indicator)
+                    .chain()
+                    .run_if(in_state(GameState::Battle))
+                    .run_if(resource_exists::<BattleClock>),
+                update_unit_tooltip
+                    .run_if(in_state(GameState::Battle))
+                    .run_if(resource_exists::<BattleSimulation>),
+                (handle_selection_mouse, handle_selection_keys)
+                    .run_if(in_state(GameState::Battle))
+                    .run_if(resource_exists::<BattleSimulation>)
+                    .run_if(resource_exists::<BattleSelection>),
             ))
             .add_systems(OnExit(GameState::MainMenu), cleanup_main_menu)
             .add_systems(OnEnter(GameState::WorldMap), setup_world_map_ui)
-            .add_systems(OnExit(GameState::WorldMap), cleanup_world_map_ui);
+            .add_systems(OnExit(GameState::WorldMap), cleanup_world_map_ui)
+            .add_systems(OnEnter(GameState::Battle), (setup_battle_speed_indicator, setup_unit_tooltip))
+            .add_systems(OnExit(GameState::Battle), (cleanup_battle_speed_indicator, cleanup_unit_tooltip));
         
         println!("Modern UI Plugin loaded - Animasyonlu arayüz sistemi yüklendi!");
     }
@@ -526,3 +547,365 @@ fn cleanup_world_map_ui(mut commands: Commands, ui_query: Query<Entity, With<Wor
         commands.entity(entity).despawn_recursive();
     }
 }
+
+// Takviye bildirimi komponenti
+#[derive(Component)]
+pub struct ReinforcementNotice {
+    pub timer: Timer,
+}
+
+// Savaş sırasında gelen takviyeleri ekranın üstünde duyur
+fn show_reinforcement_notices(
+    mut commands: Commands,
+    mut arrivals: EventReader<ReinforcementsArrived>,
+    ui_fonts: Option<Res<UiFonts>>,
+    notice_query: Query<(), With<ReinforcementNotice>>,
+) {
+    let Some(fonts) = ui_fonts else {
+        arrivals.clear();
+        return;
+    };
+
+    // Yeni bildirimler eskilerin altına dizilir
+    let shown = notice_query.iter().count();
+    for (row, ReinforcementsArrived(arrival)) in (shown..).zip(arrivals.read()) {
+        let side = match arrival.side {
+            BattleSide::Attacker => "saldıran",
+            BattleSide::Defender => "savunan",
+        };
+        let edge = match arrival.edge {
+            BattlefieldEdge::North => "kuzey",
+            BattlefieldEdge::South => "güney",
+            BattlefieldEdge::East => "doğu",
+            BattlefieldEdge::West => "batı",
+        };
+        let text = format!("Takviye geldi! {} asker {} kenarından {} tarafa katıldı", arrival.men, edge, side);
+
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        top: Val::Px(20.0 + row as f32 * 50.0),
+                        left: Val::Percent(30.0),
+                        width: Val::Percent(40.0),
+                        padding: UiRect::all(Val::Px(10.0)),
+                        justify_content: JustifyContent::Center,
+                        border: UiRect::all(Val::Px(1.0)),
+                        ..default()
+                    },
+                    background_color: ModernColors::CARD_BG.into(),
+                    border_color: ModernColors::ACCENT_GOLD.into(),
+                    ..default()
+                },
+                ReinforcementNotice {
+                    timer: Timer::from_seconds(5.0, TimerMode::Once),
+                },
+            ))
+            .with_children(|notice| {
+                notice.spawn(TextBundle::from_sections([TextSection::new(
+                    text,
+                    TextStyle {
+                        font: fonts.bold.clone(),
+                        font_size: 18.0,
+                        color: ModernColors::ACCENT_GOLD,
+                    },
+                )]));
+            });
+    }
+}
+
+// Süresi dolan takviye bildirimlerini kaldır
+fn expire_reinforcement_notices(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut notice_query: Query<(Entity, &mut ReinforcementNotice)>,
+) {
+    for (entity, mut notice) in &mut notice_query {
+        if notice.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+// Savaş hızı göstergesi komponenti
+#[derive(Component)]
+pub struct BattleSpeedIndicator;
+
+// Savaş hızı göstergesini sağ üst köşeye yerleştir
+fn setup_battle_speed_indicator(mut commands: Commands, ui_fonts: Option<Res<UiFonts>>) {
+    let Some(fonts) = ui_fonts else {
+        return;
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(20.0),
+                    right: Val::Px(20.0),
+                    padding: UiRect::all(Val::Px(10.0)),
+                    border: UiRect::all(Val::Px(1.0)),
+                    ..default()
+                },
+                background_color: ModernColors::CARD_BG.into(),
+                border_color: ModernColors::ACCENT_BLUE.into(),
+                ..default()
+            },
+            BattleSpeedIndicator,
+        ))
+        .with_children(|indicator| {
+            indicator.spawn(TextBundle::from_sections([TextSection::new(
+                "Hız: 1x",
+                TextStyle {
+                    font: fonts.bold.clone(),
+                    font_size: 18.0,
+                    color: ModernColors::TEXT_PRIMARY,
+                },
+            )]));
+        });
+}
+
+// Savaş hızı kısayolları: Boşluk duraklatır, +/- hızı değiştirir,
+// duraklatılmışken nokta savaşı tek adım ilerletir. Arayüz kendi
+// zamanıyla çalıştığı için bunlardan etkilenmez.
+fn handle_battle_speed_keys(keys: Res<ButtonInput<KeyCode>>, mut clock: ResMut<BattleClock>) {
+    if keys.just_pressed(KeyCode::Space) {
+        clock.toggle_pause();
+    }
+    if keys.any_just_pressed([KeyCode::Equal, KeyCode::NumpadAdd]) {
+        clock.faster();
+    }
+    if keys.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        clock.slower();
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        clock.step_once();
+    }
+}
+
+// Göstergeyi savaşın hızına göre güncelle
+fn update_battle_speed_indicator(
+    clock: Res<BattleClock>,
+    indicator_query: Query<&Children, With<BattleSpeedIndicator>>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !clock.is_changed() {
+        return;
+    }
+
+    let label = if clock.paused {
+        "DURAKLATILDI".to_string()
+    } else {
+        format!("Hız: {}x", clock.speed)
+    };
+    for children in &indicator_query {
+        for &child in children {
+            if let Ok(mut text) = text_query.get_mut(child) {
+                text.sections[0].value = label.clone();
+            }
+        }
+    }
+}
+
+// Savaş hızı göstergesini kaldır
+fn cleanup_battle_speed_indicator(mut commands: Commands, indicator_query: Query<Entity, With<BattleSpeedIndicator>>) {
+    for entity in &indicator_query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Birlik bilgi kutusu komponenti
+#[derive(Component)]
+pub struct UnitTooltip;
+
+// Fare imlecinin savaş alanındaki konumu
+fn cursor_battlefield_position(
+    window_query: &Query<&Window, With<PrimaryWindow>>,
+    camera_query: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<Position> {
+    let cursor = window_query.get_single().ok()?.cursor_position()?;
+    let (camera, camera_transform) = camera_query.iter().find(|(camera, _)| camera.is_active)?;
+    let point = camera.viewport_to_world_2d(camera_transform, cursor)?;
+    Some(Position::new(point.x, point.y))
+}
+
+// İmlecin altındaki birlik, ayak izleri üst üste biniyorsa en yakını
+fn unit_under_cursor<'a>(simulation: &'a BattleSimulation, position: &Position) -> Option<&'a BattleUnit> {
+    simulation
+        .units
+        .iter()
+        .filter(|u| u.unit.count > 0 && u.position.distance_to(position) <= u.footprint_radius())
+        .min_by(|a, b| a.position.distance_to(position).total_cmp(&b.position.distance_to(position)))
+}
+
+// Gizli bilgi kutusunu oluştur, imleç bir birliğin üstüne gelince görünür
+fn setup_unit_tooltip(mut commands: Commands, ui_fonts: Option<Res<UiFonts>>) {
+    let Some(fonts) = ui_fonts else {
+        return;
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    padding: UiRect::all(Val::Px(8.0)),
+                    border: UiRect::all(Val::Px(1.0)),
+                    ..default()
+                },
+                background_color: ModernColors::CARD_BG.into(),
+                border_color: ModernColors::ACCENT_GOLD.into(),
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            UnitTooltip,
+        ))
+        .with_children(|tooltip| {
+            tooltip.spawn(TextBundle::from_sections([TextSection::new(
+                "",
+                TextStyle {
+                    font: fonts.regular.clone(),
+                    font_size: 15.0,
+                    color: ModernColors::TEXT_PRIMARY,
+                },
+            )]));
+        });
+}
+
+// Birliğin rütbesini, asker sayısını, moralini ve tecrübesini imlecin yanında göster
+fn update_unit_tooltip(
+    simulation: Res<BattleSimulation>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    mut tooltip_query: Query<(&mut Style, &Children), With<UnitTooltip>>,
+    mut text_query: Query<&mut Text>,
+) {
+    let cursor = window_query.get_single().ok().and_then(|w| w.cursor_position());
+    let hovered = cursor_battlefield_position(&window_query, &camera_query)
+        .and_then(|position| unit_under_cursor(&simulation, &position));
+
+    for (mut style, children) in &mut tooltip_query {
+        let (Some(unit), Some(cursor)) = (hovered, cursor) else {
+            style.display = Display::None;
+            continue;
+        };
+
+        style.display = Display::Flex;
+        style.left = Val::Px(cursor.x + 16.0);
+        style.top = Val::Px(cursor.y + 16.0);
+        for &child in children {
+            if let Ok(mut text) = text_query.get_mut(child) {
+                text.sections[0].value = unit.unit.tooltip_text();
+            }
+        }
+    }
+}
+
+// Bilgi kutusunu kaldır
+fn cleanup_unit_tooltip(mut commands: Commands, tooltip_query: Query<Entity, With<UnitTooltip>>) {
+    for entity in &tooltip_query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Sol tuşla sürüklenen seçim kutusunun başladığı yer
+#[derive(Resource, Default)]
+pub struct SelectionDrag {
+    pub start: Option<Position>,
+}
+
+// Bundan kısa sürüklemeler kutu değil tıklama sayılır
+const DRAG_THRESHOLD: f32 = 8.0;
+
+// Kontrol grupları 1'den 9'a kadar rakam tuşlarında
+const GROUP_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+// Fareyle seçim ve emirler: sol tuşla tıklanan birlik ya da sürüklenen
+// kutudakiler seçilir, sağ tuş seçilenleri oraya yürütür ya da altındaki
+// düşmana saldırtır. Shift seçime ekler, emirleri de sıraya koyar.
+fn handle_selection_mouse(
+    mouse: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    mut drag: ResMut<SelectionDrag>,
+    mut selection: ResMut<BattleSelection>,
+    mut simulation: ResMut<BattleSimulation>,
+) {
+    let Some(position) = cursor_battlefield_position(&window_query, &camera_query) else {
+        return;
+    };
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    if mouse.just_pressed(MouseButton::Left) {
+        drag.start = Some(position.clone());
+    }
+    if mouse.just_released(MouseButton::Left) {
+        if let Some(start) = drag.start.take() {
+            if start.distance_to(&position) >= DRAG_THRESHOLD {
+                selection.select_box(&simulation, &start, &position, shift);
+            } else {
+                selection.select_at(&simulation, &position, shift);
+            }
+        }
+    }
+
+    if mouse.just_pressed(MouseButton::Right) {
+        let enemy = unit_under_cursor(&simulation, &position)
+            .filter(|u| u.side != selection.side && u.can_fight())
+            .map(|u| u.id());
+        let orders = match enemy {
+            Some(target) => selection.attack(&simulation, target, shift),
+            None => selection.move_to(&simulation, &position, shift),
+        };
+        simulation.queue_commands(orders);
+    }
+}
+
+// Kontrol grupları ve emir kısayolları: Ctrl+rakam seçimi gruba kaydeder,
+// Ctrl+Shift+rakam gruba ekler, rakam tek başına grubu seçer. S birlikleri
+// durdurur, H oldukları yerde tutar.
+fn handle_selection_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut selection: ResMut<BattleSelection>,
+    mut simulation: ResMut<BattleSimulation>,
+) {
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    for (index, key) in GROUP_KEYS.into_iter().enumerate() {
+        if !keys.just_pressed(key) {
+            continue;
+        }
+        let group = index + 1;
+        match (ctrl, shift) {
+            (true, true) => selection.add_to_group(group),
+            (true, false) => selection.assign_group(group),
+            (false, _) => {
+                selection.recall_group(&simulation, group);
+            }
+        }
+    }
+
+    if keys.just_pressed(KeyCode::KeyS) {
+        let orders = selection.stop(&simulation);
+        simulation.queue_commands(orders);
+    }
+    if keys.just_pressed(KeyCode::KeyH) {
+        let orders = selection.hold_position(&simulation);
+        simulation.queue_commands(orders);
+    }
+}
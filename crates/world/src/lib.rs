@@ -20,6 +20,36 @@ pub struct Province {
     pub terrain_type: TerrainType,
 }
 
+impl Province {
+    /// Level of the strongest finished Fort in the province, 0 without one.
+    pub fn fort_level(&self) -> u32 {
+        self.completed_level(BuildingType::Fort)
+    }
+
+    pub fn city_level(&self) -> u32 {
+        self.completed_level(BuildingType::City)
+    }
+
+    fn completed_level(&self, building_type: BuildingType) -> u32 {
+        self.buildings
+            .iter()
+            .filter(|b| b.building_type == building_type && b.is_complete())
+            .map(|b| b.level)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Hit points of each stretch of the province's walls.
+    pub fn wall_strength(&self) -> f32 {
+        self.fort_level() as f32 * WALL_STRENGTH_PER_FORT_LEVEL
+    }
+
+    /// Defense bonus for troops holding the province, from terrain and forts.
+    pub fn defense_bonus(&self) -> f32 {
+        self.terrain_type.defense_bonus() + FORT_DEFENSE_BONUS_PER_LEVEL * self.fort_level() as f32
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TerrainType {
     Plains,
@@ -58,6 +88,9 @@ impl TerrainType {
 // BUILDINGS SYSTEM
 // ============================================================================
 
+pub const WALL_STRENGTH_PER_FORT_LEVEL: f32 = 500.0;
+pub const FORT_DEFENSE_BONUS_PER_LEVEL: f32 = 0.25;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Building {
     pub building_type: BuildingType,
@@ -65,7 +98,13 @@ pub struct Building {
     pub construction_progress: f32, // 0.0 to 1.0
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Building {
+    pub fn is_complete(&self) -> bool {
+        self.construction_progress >= 1.0 && self.level > 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BuildingType {
     City,
     Farm,
@@ -120,6 +159,63 @@ impl BuildingType {
     }
 }
 
+// ============================================================================
+// WORLD CALENDAR
+// ============================================================================
+
+/// A world map turn is a month.
+pub const TURNS_PER_YEAR: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Season {
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+}
+
+impl Season {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Season::Spring => "Spring",
+            Season::Summer => "Summer",
+            Season::Autumn => "Autumn",
+            Season::Winter => "Winter",
+        }
+    }
+}
+
+/// The date on the world map. The campaign starts in the first month of
+/// spring of year 1.
+#[derive(Resource, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorldCalendar {
+    pub turn: u32, // Turns played so far
+}
+
+impl WorldCalendar {
+    pub fn advance(&mut self) {
+        self.turn += 1;
+    }
+
+    /// 0 for the first month of the year, which is the first of spring.
+    pub fn month(&self) -> u32 {
+        self.turn % TURNS_PER_YEAR
+    }
+
+    pub fn year(&self) -> u32 {
+        self.turn / TURNS_PER_YEAR + 1
+    }
+
+    pub fn season(&self) -> Season {
+        match self.month() {
+            0..=2 => Season::Spring,
+            3..=5 => Season::Summer,
+            6..=8 => Season::Autumn,
+            _ => Season::Winter,
+        }
+    }
+}
+
 // ============================================================================
 // WORLD MAP RESOURCE
 // ============================================================================
@@ -156,6 +252,23 @@ impl WorldMap {
         self.provinces.get_mut(&id)
     }
     
+    /// Hands a province over to a new owner, e.g. after it was captured.
+    pub fn set_owner(&mut self, id: ProvinceId, owner: Option<FactionId>) {
+        let Some(province) = self.provinces.get_mut(&id) else {
+            return;
+        };
+
+        if let Some(previous) = province.owner {
+            if let Some(territory) = self.faction_territories.get_mut(&previous) {
+                territory.retain(|&p| p != id);
+            }
+        }
+        if let Some(owner) = owner {
+            self.faction_territories.entry(owner).or_default().push(id);
+        }
+        province.owner = owner;
+    }
+
     pub fn get_faction_provinces(&self, faction_id: FactionId) -> Vec<&Province> {
         self.faction_territories
             .get(&faction_id)
@@ -6,11 +6,11 @@ use dot_wars_ui_simple;
 pub use dot_wars_graphics::GraphicsPlugin;
 pub use dot_wars_ai::AIPlugin;
 pub use dot_wars_save_system::SaveSystemPlugin;
+pub use dot_wars_battle::BattlePlugin;
+pub use dot_wars_strategy::StrategyPlugin;
 
 pub struct CorePlugin;
 pub struct WorldPlugin;
-pub struct BattlePlugin;
-pub struct StrategyPlugin;
 
 impl Plugin for CorePlugin {
     fn build(&self, app: &mut App) {
@@ -26,20 +26,6 @@ impl Plugin for WorldPlugin {
     }
 }
 
-impl Plugin for BattlePlugin {
-    fn build(&self, app: &mut App) {
-        // Battle simulation systems
-        println!("Battle Plugin loaded");
-    }
-}
-
-impl Plugin for StrategyPlugin {
-    fn build(&self, app: &mut App) {
-        // Grand strategy systems
-        println!("Strategy Plugin loaded - Büyük strateji sistemleri yüklendi");
-    }
-}
-
 pub struct GamePlugins;
 
 impl Plugin for GamePlugins {